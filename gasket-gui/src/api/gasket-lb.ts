@@ -113,6 +113,90 @@ export const patchStream = async (
   return await response.json();
 };
 
+export const probeInput = async (
+  apiUrl: Uri,
+  uri: Uri,
+  timeout?: number,
+): Promise<any> => {
+  const response = await fetch(`${apiUrl}/probe`, {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({ uri, timeout }),
+  });
+
+  if (!response.ok) {
+    if (response.body) throw { error: response.body };
+    else throw { error: response.statusText };
+  }
+  return await response.json();
+};
+
+export const thumbnailUrl = (
+  apiUrl: Uri,
+  stream_id: Uuid,
+  output_id: Uuid,
+  tick?: number,
+): string =>
+  `${apiUrl}/stream/${stream_id}/output/${output_id}/thumbnail?t=${tick || 0}`;
+
+export const getRecordings = async (
+  apiUrl: Uri,
+  stream_id: Uuid,
+  output_id: Uuid,
+): Promise<any> => {
+  const response = await fetch(
+    `${apiUrl}/stream/${stream_id}/output/${output_id}/recording`,
+  );
+
+  if (!response.ok) {
+    if (response.body) throw { error: response.body };
+    else throw { error: response.statusText };
+  }
+  return await response.json();
+};
+
+export const recordingUrl = (
+  apiUrl: Uri,
+  stream_id: Uuid,
+  output_id: Uuid,
+  name: string,
+): string =>
+  `${apiUrl}/stream/${stream_id}/output/${output_id}/recording/${encodeURIComponent(name)}`;
+
+export const getJobs = async (apiUrl: Uri): Promise<any> => {
+  const response = await fetch(`${apiUrl}/job`);
+  return await response.json();
+};
+
+export const createJob = async (apiUrl: Uri, job: any): Promise<any> => {
+  const response = await fetch(`${apiUrl}/job`, {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify(job),
+  });
+  if (!response.ok) {
+    if (response.body) throw { error: response.body };
+    else throw { error: response.statusText };
+  }
+  return await response.json();
+};
+
+export const deleteJob = async (apiUrl: Uri, job_id: Uuid): Promise<any> => {
+  const response = await fetch(`${apiUrl}/job/${job_id}`, {
+    method: "DELETE",
+  });
+
+  if (!response.ok) {
+    if (response.body) throw { error: response.body };
+    else throw { error: response.statusText };
+  }
+  return await response.text();
+};
+
 export const getLb = async (apiUrl: Uri): Promise<any> => {
   const response = await fetch(`${apiUrl}/`);
   return await response.json();
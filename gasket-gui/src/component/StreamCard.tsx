@@ -21,6 +21,9 @@ import {
 import { useEffect, useState } from "react";
 import Iconify from "./Iconify";
 import useLb from "../hooks/useLb";
+import useInterval from "../hooks/useInterval";
+import { useCookies } from "react-cookie";
+import { thumbnailUrl } from "../api/gasket-lb";
 import StreamFlowchart from "./StreamFlowchart";
 import { Link } from "react-router-dom";
 import { Codec, Output, Stream } from "../types";
@@ -39,6 +42,15 @@ const StreamCard = ({
   const [showDetails, setShowDetails] = useState(false);
   const [editing, setEditing] = useState<boolean>(false);
   const [expanded, setExpanded] = useState("");
+  const [cookies] = useCookies(["gasket_api_url"]);
+  const [tick, setTick] = useState(0);
+
+  // Reload thumbnails, the worker refreshes them every few seconds
+  useInterval(() => setTick(Date.now()), 5000);
+
+  const previews = stream.output.filter(
+    (out) => out.status === "Running" && out.worker,
+  );
 
   useEffect(() => {
     if (highlighted) setEditing(true);
@@ -115,6 +127,25 @@ const StreamCard = ({
             editing={editing}
             callback={updatePatch}
           />
+          {!editing && previews.length > 0 && (
+            <Stack direction={"row"} spacing={1} useFlexGap flexWrap={"wrap"}>
+              {previews.map((out) => (
+                <Tooltip key={out.id} title={out.uri}>
+                  <img
+                    src={thumbnailUrl(
+                      cookies.gasket_api_url,
+                      stream.id,
+                      out.id,
+                      tick,
+                    )}
+                    alt={out.uri}
+                    width={160}
+                    style={{ borderRadius: "4px", background: "#000" }}
+                  />
+                </Tooltip>
+              ))}
+            </Stack>
+          )}
         </Stack>
       </CardContent>
       {controls && (
@@ -141,9 +141,9 @@ const WorkerCard = ({
                           Load
                         </Typography>
 
-                        {worker.stats.devices?.map((deviceUtil, index) => (
+                        {worker.stats.devices?.map((device, index) => (
                           <CircularProgressWithLabel
-                            value={deviceUtil}
+                            value={device.utilization}
                             key={worker.id + "stats" + index}
                           />
                         ))}
@@ -38,6 +38,42 @@ export default memo(({ data }: OutputNodeProps) => {
         }}
       >
         <strong>{chopString(data.resource.uri, 25)}</strong>
+        {data.resource.stats && (
+          <span style={{ fontSize: ".8rem" }}>
+            {data.resource.stats.fps.toFixed(1)} fps ·{" "}
+            {(data.resource.stats.bitrate / 1000).toFixed(2)} Mbps ·{" "}
+            {data.resource.stats.speed.toFixed(2)}x
+            {data.resource.stats.drop_frames > 0 &&
+              ` · ${data.resource.stats.drop_frames} dropped`}
+          </span>
+        )}
+        {data.resource.active_input &&
+          data.resource.active_input !== "primary input" && (
+            <span style={{ fontSize: ".8rem", fontWeight: "bold" }}>
+              On {data.resource.active_input}
+            </span>
+          )}
+        {data.resource.alarms?.some((alarm) => !alarm.ended) && (
+          <span style={{ fontSize: ".8rem", fontWeight: "bold" }}>
+            Alarm:{" "}
+            {data.resource.alarms
+              .filter((alarm) => !alarm.ended)
+              .map((alarm) => alarm.kind)
+              .join(" · ")}
+          </span>
+        )}
+        {data.resource.audio_tracks && data.resource.audio_tracks.length > 0 && (
+          <span style={{ fontSize: ".8rem" }}>
+            {data.resource.audio_tracks
+              .map(
+                (track) =>
+                  `${track.index}: ${track.codec ?? "?"}` +
+                  (track.language ? ` ${track.language}` : "") +
+                  (track.channel_layout ? ` ${track.channel_layout}` : ""),
+              )
+              .join(" · ")}
+          </span>
+        )}
         <span
           style={{
             display: "flex",
@@ -72,6 +108,19 @@ export default memo(({ data }: OutputNodeProps) => {
             </>
           )}
 
+          {data.resource.playlist && (
+            <Tooltip title={data.resource.playlist}>
+              <IconButton
+                size={"small"}
+                sx={{ color: "black" }}
+                href={data.resource.playlist}
+                target="_blank"
+              >
+                <Iconify icon={"mdi:playlist-play"} />
+              </IconButton>
+            </Tooltip>
+          )}
+
           {data.resource.last_error && (
             <Tooltip title={data.resource.last_error} open={errorTooltipOpen}>
               <IconButton
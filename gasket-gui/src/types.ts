@@ -13,7 +13,275 @@ export type StreamOptions = {
   framerate?: string;
   gop_size?: string;
   debug_text?: boolean;
+  overlays?: Overlay[];
   output_format?: string;
+  renditions?: Rendition[];
+  packaging?: Packaging;
+  input_srt?: SrtOptions;
+  output_srt?: SrtOptions;
+  input_mode?: InputMode;
+  restart?: RestartPolicy;
+  failover?: Failover;
+  rate_control?: RateControl;
+  tuning?: Tuning;
+  width?: number;
+  height?: number;
+  scaling_mode?: ScalingMode;
+  crop?: Crop;
+  pad?: string;
+  audio?: AudioOptions;
+  deinterlace?: Deinterlace;
+  framerate_mode?: FramerateMode;
+  tonemap?: Tonemap;
+  recording?: Recording;
+  alarms?: AlarmOptions;
+  analyze_input?: boolean;
+  program?: ProgramSelection;
+  scte35?: boolean;
+  mpegts?: MpegtsOptions;
+};
+
+export type ProgramSelection = {
+  number?: number;
+  service_name?: string;
+};
+
+export type MpegtsOptions = {
+  service_name?: string;
+  service_provider?: string;
+  service_id?: number;
+  transport_stream_id?: number;
+  original_network_id?: number;
+  pmt_pid?: number;
+  start_pid?: number;
+  video_pid?: number;
+  audio_pids?: number[];
+};
+
+export enum DeinterlaceFilter {
+  Yadif = "Yadif",
+  Bwdif = "Bwdif",
+}
+
+export type Deinterlace = {
+  filter?: DeinterlaceFilter;
+  field_rate?: boolean;
+  all_frames?: boolean;
+};
+
+export enum FramerateMode {
+  DropDup = "DropDup",
+  Blend = "Blend",
+  Interpolate = "Interpolate",
+}
+
+export enum TonemapAlgorithm {
+  Hable = "Hable",
+  Mobius = "Mobius",
+  Reinhard = "Reinhard",
+  Clip = "Clip",
+}
+
+export type Tonemap = {
+  algorithm?: TonemapAlgorithm;
+  peak?: number;
+};
+
+export type Recording = {
+  segment_duration?: number;
+  filename?: string;
+  max_segments?: number;
+  max_size_mb?: number;
+};
+
+export enum AlarmKind {
+  Black = "Black",
+  Freeze = "Freeze",
+  Silence = "Silence",
+}
+
+export type Detector = {
+  enabled?: boolean;
+  duration?: number;
+  threshold?: number;
+};
+
+export type AlarmOptions = {
+  enabled?: boolean;
+  black?: Detector;
+  freeze?: Detector;
+  silence?: Detector;
+};
+
+export type Alarm = {
+  kind: AlarmKind;
+  started: number;
+  ended?: number;
+};
+
+export type RecordingFile = {
+  name: string;
+  size: number;
+  modified: number;
+  worker?: Uuid;
+};
+
+export enum OverlayKind {
+  Text = "Text",
+  Timecode = "Timecode",
+  Logo = "Logo",
+  Debug = "Debug",
+}
+
+export enum OverlayPosition {
+  TopLeft = "TopLeft",
+  TopRight = "TopRight",
+  BottomLeft = "BottomLeft",
+  BottomRight = "BottomRight",
+  Center = "Center",
+}
+
+export type Overlay = {
+  kind: OverlayKind;
+  text?: string;
+  file?: string;
+  position?: OverlayPosition;
+  margin?: number;
+  size?: number;
+  color?: string;
+  background?: string;
+  font?: string;
+  opacity?: number;
+  rate?: string;
+};
+
+export enum AudioCodec {
+  Copy = "Copy",
+  AAC = "AAC",
+  AC3 = "AC3",
+  Opus = "Opus",
+}
+
+export type AudioOptions = {
+  codec?: AudioCodec;
+  bitrate?: string;
+  channel_layout?: string;
+  sample_rate?: number;
+  loudness?: number;
+  tracks?: number[];
+  languages?: string[];
+};
+
+export type AudioTrack = {
+  index: number;
+  codec?: string;
+  language?: string;
+  channels?: number;
+  channel_layout?: string;
+  sample_rate?: number;
+  program?: number;
+};
+
+export enum ScalingMode {
+  Fit = "Fit",
+  Fill = "Fill",
+  Stretch = "Stretch",
+}
+
+export type Crop = {
+  top?: number;
+  bottom?: number;
+  left?: number;
+  right?: number;
+};
+
+export enum RateControlMode {
+  CBR = "CBR",
+  VBR = "VBR",
+  CRF = "CRF",
+}
+
+export type RateControl = {
+  mode: RateControlMode;
+  max_bitrate?: string;
+  buffer_size?: string;
+  quality?: number;
+  muxrate?: string;
+};
+
+export enum Preset {
+  UltraFast = "UltraFast",
+  Fast = "Fast",
+  Medium = "Medium",
+  Slow = "Slow",
+  VerySlow = "VerySlow",
+}
+
+export type Tuning = {
+  preset?: Preset;
+  low_latency?: boolean;
+  profile?: string;
+  level?: string;
+  b_frames?: number;
+};
+
+export enum RestartMode {
+  Never = "Never",
+  OnFailure = "OnFailure",
+  Always = "Always",
+}
+
+export type RestartPolicy = {
+  mode: RestartMode;
+  max_retries?: number;
+  backoff?: number;
+  max_backoff?: number;
+};
+
+export type Failover = {
+  backup_inputs?: Uri[];
+  stall_timeout?: number;
+  slate?: string;
+};
+
+export enum InputMode {
+  Loop = "Loop",
+  Live = "Live",
+  Once = "Once",
+}
+
+export enum SrtMode {
+  Caller = "Caller",
+  Listener = "Listener",
+  Rendezvous = "Rendezvous",
+}
+
+export type SrtOptions = {
+  mode?: SrtMode;
+  latency?: number;
+  // Masked as "********" in responses, sending the mask back keeps the current passphrase
+  passphrase?: string;
+  streamid?: string;
+};
+
+export enum PackagingFormat {
+  HLS = "HLS",
+  CMAF = "CMAF",
+  DASH = "DASH",
+}
+
+export type Packaging = {
+  format: PackagingFormat;
+  segment_duration?: number;
+  window_size?: number;
+};
+
+export type Rendition = {
+  uri: Uri;
+  width?: number;
+  height?: number;
+  bitrate?: string;
+  gop_size?: string;
 };
 
 export type Output = {
@@ -25,6 +293,49 @@ export type Output = {
   worker?: Uuid;
   logs?: string[];
   last_error?: string;
+  playlist?: string;
+  audio_tracks?: AudioTrack[];
+  active_input?: string;
+  alarms?: Alarm[];
+  stats?: OutputStats;
+};
+
+export type Program = {
+  program_num: number;
+  pmt_pid?: number;
+  pcr_pid?: number;
+  service_name?: string;
+  service_provider?: string;
+};
+
+export type InputInfo = {
+  format?: string;
+  video_codec?: string;
+  width?: number;
+  height?: number;
+  framerate?: number;
+  programs: Program[];
+  audio_tracks: AudioTrack[];
+  data_streams: DataStream[];
+};
+
+export type DataStream = {
+  index: number;
+  codec?: string;
+  pid?: number;
+  program?: number;
+};
+
+export type OutputStats = {
+  frame: number;
+  fps: number;
+  bitrate: number;
+  total_size: number;
+  out_time_us: number;
+  dup_frames: number;
+  drop_frames: number;
+  speed: number;
+  updated: number;
 };
 
 export type Stream = {
@@ -36,6 +347,31 @@ export type Stream = {
   status: string;
 };
 
+export type JobStatus = "Creating" | "Running" | "Completed" | "Failed";
+
+export type Job = {
+  id: Uuid;
+  name: string;
+  input: Uri;
+  output: Uri;
+  codec: Codec;
+  options?: StreamOptions;
+  two_pass?: boolean;
+  status: JobStatus;
+  worker?: Uuid;
+  progress?: number;
+  pass?: number;
+  exit_code?: number;
+  error?: string;
+  elapsed?: number;
+  logs: string[];
+};
+
+export type DeviceStats = {
+  utilization: number;
+  sessions?: number;
+};
+
 export type Worker = {
   codecs: Codec[];
   encoder: string;
@@ -44,7 +380,7 @@ export type Worker = {
   public_ip?: string;
   id: Uuid;
   stats: {
-    devices: number[];
+    devices: DeviceStats[];
     utilization: number;
   };
   status: string;
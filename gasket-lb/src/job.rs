@@ -0,0 +1,142 @@
+use crate::state::App;
+use crate::stream::{Codec, Options};
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum JobStatus {
+    Creating,
+    Running,
+    // Completed and Failed jobs are never scheduled again
+    Completed,
+    Failed,
+}
+
+// File to file transcode, placed on a worker like a stream output but run only once
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct Job {
+    pub(crate) id: Uuid,
+    pub(crate) name: String,
+    pub(crate) input: String,
+    pub(crate) output: String,
+    pub(crate) codec: Codec,
+    pub(crate) options: Option<Options>,
+    pub(crate) two_pass: Option<bool>,
+    pub(crate) status: JobStatus,
+    pub(crate) worker: Option<Uuid>,
+    // Percent of all passes, as reported by the worker
+    pub(crate) progress: Option<f64>,
+    pub(crate) pass: Option<u8>,
+    pub(crate) exit_code: Option<i32>,
+    // Last ffmpeg error line of a failed job
+    pub(crate) error: Option<String>,
+    // Seconds spent on the worker
+    pub(crate) elapsed: Option<u64>,
+    pub(crate) logs: Vec<String>,
+}
+
+impl Job {
+    // Checks on top of the output options, a job reads one file once
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.output.is_empty() {
+            return Err("Job output cannot be empty".to_string());
+        }
+
+        let options = self.options.clone().unwrap_or_default();
+        options.validate(&self.input, &self.output)?;
+        if options.failover.is_some() {
+            return Err("Jobs have a single input, failover is not supported".to_string());
+        }
+        if options.recording.is_some() {
+            return Err("Job outputs are files already, recording is not supported".to_string());
+        }
+        if options.restart.is_some() {
+            return Err("Jobs are never restarted by the worker".to_string());
+        }
+
+        Ok(())
+    }
+
+    // Copy for API responses, see Stream::redacted
+    pub(crate) fn redacted(&self) -> Job {
+        let mut job = self.clone();
+        job.input = utils::redact_secrets(&job.input);
+        job.output = utils::redact_secrets(&job.output);
+        job.options = job.options.as_ref().map(Options::redacted);
+        job
+    }
+}
+
+//
+// Small util functions to update the state, see stream.rs
+//
+
+pub(crate) async fn get_all(state: Arc<App>) -> Vec<Job> {
+    let jobs = state.jobs.lock().await;
+    jobs.clone()
+}
+
+pub(crate) async fn set_status(
+    state: Arc<App>,
+    job_id: Uuid,
+    status: JobStatus,
+    worker_id: Option<Uuid>,
+) {
+    let mut jobs = state.jobs.lock().await;
+    if let Some(job) = jobs.iter_mut().find(|x| x.id == job_id) {
+        job.status = status;
+        job.worker = worker_id;
+        if status == JobStatus::Creating {
+            job.progress = None;
+            job.pass = None;
+        }
+    }
+}
+
+// Latest state of a job the worker is running or has just ended
+pub(crate) async fn set_progress(
+    state: Arc<App>,
+    job_id: Uuid,
+    progress: Option<f64>,
+    pass: u8,
+    elapsed: Option<u64>,
+) {
+    let mut jobs = state.jobs.lock().await;
+    if let Some(job) = jobs.iter_mut().find(|x| x.id == job_id) {
+        job.progress = progress;
+        job.pass = Some(pass);
+        job.elapsed = elapsed;
+    }
+}
+
+pub(crate) async fn set_exit(
+    state: Arc<App>,
+    job_id: Uuid,
+    status: JobStatus,
+    exit_code: Option<i32>,
+    error: Option<String>,
+) {
+    let mut jobs = state.jobs.lock().await;
+    if let Some(job) = jobs.iter_mut().find(|x| x.id == job_id) {
+        job.status = status;
+        job.exit_code = exit_code;
+        job.error = error;
+    }
+}
+
+pub(crate) async fn log(state: Arc<App>, job_id: Uuid, log: String) {
+    let mut jobs = state.jobs.lock().await;
+    if let Some(job) = jobs.iter_mut().find(|x| x.id == job_id) {
+        if job.logs.last().is_some_and(|last| last.ends_with(&log)) {
+            return;
+        }
+
+        job.logs
+            .push(format!("{}: {}", chrono::Utc::now().to_rfc3339(), log));
+        while job.logs.len() > 100 {
+            job.logs.remove(0);
+        }
+    }
+}
@@ -8,6 +8,7 @@ use std::sync::Arc;
 use tower_http::cors::CorsLayer;
 
 mod args;
+mod job;
 mod monitor;
 mod router;
 mod state;
@@ -48,6 +49,25 @@ async fn main() {
         .route("/stream/:uuid", get(router::get_stream))
         .route("/stream/:uuid", patch(router::patch_stream))
         .route("/stream/:uuid", delete(router::delete_stream))
+        .route(
+            "/stream/:uuid/output/:oid/thumbnail",
+            get(router::get_output_thumbnail),
+        )
+        .route(
+            "/stream/:uuid/output/:oid/recording",
+            get(router::get_output_recordings),
+        )
+        .route(
+            "/stream/:uuid/output/:oid/recording/:name",
+            get(router::get_output_recording),
+        )
+        // Job
+        .route("/job", get(router::get_all_jobs))
+        .route("/job", post(router::create_job))
+        .route("/job/:uuid", get(router::get_job))
+        .route("/job/:uuid", delete(router::delete_job))
+        // Probe
+        .route("/probe", post(router::probe_input))
         // Worker
         .route("/worker", get(router::get_all_workers))
         .route("/worker", post(router::create_worker))
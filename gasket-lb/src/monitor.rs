@@ -1,10 +1,12 @@
 use crate::args;
+use crate::job;
 use crate::state;
 use crate::state::StateDump;
 use crate::state::Worker;
 use crate::stream;
 use crate::stream::Options;
 use crate::stream::Output;
+use crate::utils;
 use crate::worker;
 use clap::Parser;
 use rand;
@@ -61,6 +63,14 @@ pub(crate) async fn load_state(state: Arc<state::App>) -> Option<StateDump> {
     }
     log::info!("Restored {} streams", streams.len());
 
+    let mut jobs = state.jobs.lock().await;
+    for job in &loaded_dump.jobs {
+        if !jobs.iter().any(|j| j.id == job.id) {
+            jobs.push(job.clone());
+        }
+    }
+    log::info!("Restored {} jobs", jobs.len());
+
     Some(loaded_dump)
 }
 
@@ -215,6 +225,12 @@ pub(crate) async fn ping_worker(state: Arc<state::App>, worker: state::Worker) {
                     }
                 }
             }
+            for j in state.jobs.lock().await.iter_mut() {
+                if j.status == job::JobStatus::Running && j.worker == Some(worker.id) {
+                    j.status = job::JobStatus::Creating;
+                    j.worker = None;
+                }
+            }
         }
 
         log::warn!("Worker {} is down", worker.host);
@@ -228,6 +244,7 @@ pub(crate) async fn ping_worker(state: Arc<state::App>, worker: state::Worker) {
 
     // Check worker outputs
     tokio::spawn(check_worker_outputs(state.clone(), worker.clone()));
+    tokio::spawn(check_worker_jobs(state.clone(), worker.clone()));
 
     // Lock workers and update status
     let mut workers = state.workers.lock().await;
@@ -243,21 +260,28 @@ pub(crate) async fn ping_worker(state: Arc<state::App>, worker: state::Worker) {
 #[derive(serde::Deserialize, Clone)]
 struct WorkerStream {
     id: Uuid,
-    // status: String,
+    status: String,
     name: String,
     input: String,
     output: String,
     codec: String,
     options: Option<Options>,
     // pid: Option<u32>,
+    playlist: Option<String>,
+    audio_tracks: Option<Vec<stream::AudioTrack>>,
+    active_input: Option<String>,
+    last_switch: Option<String>,
+    stats: Option<stream::OutputStats>,
+    alarms: Option<Vec<stream::Alarm>>,
 }
 
 fn compare_configs(worker_stream: WorkerStream, stream: stream::Stream, output: Output) -> bool {
     if worker_stream.codec != output.codec.ffmpeg_name()
-        || worker_stream.input != stream.input
-        || worker_stream.output != output.uri
+        // The worker masks secrets the same way, a change of secret alone restarts the output from patch_stream
+        || worker_stream.input != utils::redact_secrets(&stream.input)
+        || worker_stream.output != utils::redact_secrets(&output.uri)
         || worker_stream.name != stream.name
-        || worker_stream.options != output.options
+        || worker_stream.options != output.options.as_ref().map(Options::redacted)
     {
         return true;
     }
@@ -344,12 +368,114 @@ pub(crate) async fn check_worker_outputs(state: Arc<state::App>, worker: Worker)
                             ));
                         } else {
                             found = true;
+
+                            if worker_stream.status == "Finished"
+                                && output.status != stream::Status::Finished
+                            {
+                                tokio::spawn(stream::set_output_status(
+                                    state.clone(),
+                                    stream.id,
+                                    output.id,
+                                    stream::Status::Finished,
+                                    Some(worker.id),
+                                ));
+                            }
+
+                            tokio::spawn(stream::set_output_stats(
+                                state.clone(),
+                                stream.id,
+                                output.id,
+                                worker_stream.stats.clone(),
+                            ));
+
+                            if worker_stream.playlist != output.playlist {
+                                tokio::spawn(stream::set_output_playlist(
+                                    state.clone(),
+                                    stream.id,
+                                    output.id,
+                                    worker_stream.playlist.clone(),
+                                ));
+                            }
+
+                            // Failover on the worker, starting on the primary input is not a switch
+                            if worker_stream.active_input != output.active_input {
+                                let primary =
+                                    worker_stream.active_input.as_deref() == Some("primary input");
+                                if output.active_input.is_some() || !primary {
+                                    let message =
+                                        worker_stream.last_switch.clone().unwrap_or(format!(
+                                            "Switched to {}",
+                                            worker_stream.active_input.as_deref().unwrap_or("?")
+                                        ));
+                                    let level = if primary {
+                                        stream::LogLevel::Info
+                                    } else {
+                                        stream::LogLevel::Error
+                                    };
+                                    tokio::spawn(stream::log(
+                                        state.clone(),
+                                        stream.id,
+                                        output.id,
+                                        message,
+                                        level,
+                                    ));
+                                }
+                                tokio::spawn(stream::set_output_active_input(
+                                    state.clone(),
+                                    stream.id,
+                                    output.id,
+                                    worker_stream.active_input.clone(),
+                                ));
+                            }
+
+                            // Log what was raised or cleared since the last poll
+                            if worker_stream.alarms != output.alarms {
+                                for alarm in worker_stream.alarms.iter().flatten() {
+                                    let known = output.alarms.iter().flatten().find(|x| {
+                                        x.kind == alarm.kind && x.started == alarm.started
+                                    });
+                                    let (message, level) = match (known, alarm.ended) {
+                                        (None, None) => (
+                                            format!("{:?} alarm raised", alarm.kind),
+                                            stream::LogLevel::Error,
+                                        ),
+                                        (Some(known), Some(_)) if known.ended.is_none() => (
+                                            format!("{:?} alarm cleared", alarm.kind),
+                                            stream::LogLevel::Info,
+                                        ),
+                                        _ => continue,
+                                    };
+                                    tokio::spawn(stream::log(
+                                        state.clone(),
+                                        stream.id,
+                                        output.id,
+                                        message,
+                                        level,
+                                    ));
+                                }
+                                tokio::spawn(stream::set_output_alarms(
+                                    state.clone(),
+                                    stream.id,
+                                    output.id,
+                                    worker_stream.alarms.clone(),
+                                ));
+                            }
+
+                            if worker_stream.audio_tracks != output.audio_tracks {
+                                tokio::spawn(stream::set_output_audio_tracks(
+                                    state.clone(),
+                                    stream.id,
+                                    output.id,
+                                    worker_stream.audio_tracks.clone(),
+                                ));
+                            }
                         }
                         break;
                     }
                 }
 
-                if !found {
+                // The worker drops Finished outputs after its --finished-ttl
+                if !found && output.status != stream::Status::Finished {
                     log::warn!(
                         "Stream {} output {} is not running on worker {}",
                         stream.id,
@@ -431,6 +557,34 @@ pub(crate) async fn update_worker_status(state: Arc<state::App>) {
     }
 }
 
+// Worker with the least utilization which supports the codec
+fn pick_worker(workers: &[Worker], codec: &stream::Codec) -> Option<Worker> {
+    let mut best_worker: Option<Worker> = None;
+
+    // 100 should be max but let's use u32::MAX for now in case some worker manages to go over
+    let mut best_utilization = u32::MAX;
+
+    for worker in workers {
+        if worker.status != state::WorkerStatus::Up {
+            continue;
+        }
+
+        if worker.codecs.contains(codec) {
+            if worker.stats.utilization < best_utilization {
+                best_worker = Some(worker.clone());
+                best_utilization = worker.stats.utilization;
+            } else if worker.stats.utilization == best_utilization {
+                // If utilization is the same, randomly choose one
+                if rand::random() {
+                    best_worker = Some(worker.clone());
+                }
+            }
+        }
+    }
+
+    best_worker
+}
+
 pub(crate) async fn start_new_streams(state: Arc<state::App>) {
     let streams = stream::get_all(state.clone()).await;
     let workers = worker::get_all(state.clone()).await;
@@ -442,29 +596,7 @@ pub(crate) async fn start_new_streams(state: Arc<state::App>) {
                     continue;
                 }
 
-                // find worker with least utilization which supports stream.output.codec
-                let mut best_worker: Option<Worker> = None;
-
-                // 100 should be max but let's use u32::MAX for now in case some worker manages to go over
-                let mut best_utilization = u32::MAX;
-
-                for worker in workers.clone() {
-                    if worker.status != state::WorkerStatus::Up {
-                        continue;
-                    }
-
-                    if worker.codecs.contains(&output.codec) {
-                        if worker.stats.utilization < best_utilization {
-                            best_worker = Some(worker.clone());
-                            best_utilization = worker.stats.utilization;
-                        } else if worker.stats.utilization == best_utilization {
-                            // If utilization is the same, randomly choose one
-                            if rand::random() {
-                                best_worker = Some(worker.clone());
-                            }
-                        }
-                    }
-                }
+                let best_worker = pick_worker(&workers, &output.codec);
 
                 if best_worker.is_none() {
                     log::warn!(
@@ -489,6 +621,121 @@ pub(crate) async fn start_new_streams(state: Arc<state::App>) {
     }
 }
 
+// Jobs waiting for a worker, ended jobs are never scheduled again
+pub(crate) async fn start_new_jobs(state: Arc<state::App>) {
+    let jobs = job::get_all(state.clone()).await;
+    let workers = worker::get_all(state.clone()).await;
+
+    for job in jobs {
+        if job.status != job::JobStatus::Creating {
+            continue;
+        }
+
+        let Some(best_worker) = pick_worker(&workers, &job.codec) else {
+            tokio::spawn(job::log(
+                state.clone(),
+                job.id,
+                "No worker available for job".to_string(),
+            ));
+            continue;
+        };
+
+        worker::start_job(best_worker, job, state.clone()).await;
+    }
+}
+
+// Follow the jobs of a worker, and remove ended or unknown ones from it
+pub(crate) async fn check_worker_jobs(state: Arc<state::App>, worker: Worker) {
+    let Some(worker_jobs) = worker::get_jobs(&worker).await else {
+        return;
+    };
+
+    let jobs = job::get_all(state.clone()).await;
+    for job in jobs.iter() {
+        if job.status != job::JobStatus::Running || job.worker != Some(worker.id) {
+            continue;
+        }
+
+        let Some(worker_job) = worker_jobs.iter().find(|x| x.id == job.id) else {
+            log::warn!("Job {} is not running on worker {}", job.id, worker.host);
+            tokio::spawn(job::log(
+                state.clone(),
+                job.id,
+                format!("Job is not running on worker {}. Restarting", worker.id),
+            ));
+            tokio::spawn(job::set_status(
+                state.clone(),
+                job.id,
+                job::JobStatus::Creating,
+                None,
+            ));
+            continue;
+        };
+
+        job::set_progress(
+            state.clone(),
+            job.id,
+            worker_job.progress,
+            worker_job.pass,
+            worker_job.elapsed,
+        )
+        .await;
+
+        let status = match worker_job.status.as_str() {
+            "Completed" => job::JobStatus::Completed,
+            "Failed" => job::JobStatus::Failed,
+            _ => continue,
+        };
+        let message = match &worker_job.error {
+            Some(error) => format!(
+                "Job {:?} with exit code {:?}: {}",
+                status, worker_job.exit_code, error
+            ),
+            None => format!("Job {:?} with exit code {:?}", status, worker_job.exit_code),
+        };
+        log::info!("Job {}: {}", job.id, message);
+        job::log(state.clone(), job.id, message).await;
+        job::set_exit(
+            state.clone(),
+            job.id,
+            status,
+            worker_job.exit_code,
+            worker_job.error.clone(),
+        )
+        .await;
+    }
+
+    // Anything else on the worker has ended here already, or isn't known at all
+    for worker_job in worker_jobs {
+        // Being cancelled already
+        if worker_job.status == "Stopping" || worker_job.status == "Cancelled" {
+            continue;
+        }
+        let ended = worker_job.status == "Completed" || worker_job.status == "Failed";
+        let Some(job) = jobs.iter().find(|x| x.id == worker_job.id) else {
+            tokio::spawn(worker::delete_job(worker.clone(), worker_job.id));
+            continue;
+        };
+
+        // Started while the start request was still in flight, keep it
+        if job.status == job::JobStatus::Creating && !ended {
+            job::set_status(
+                state.clone(),
+                job.id,
+                job::JobStatus::Running,
+                Some(worker.id),
+            )
+            .await;
+            continue;
+        }
+
+        let running_here = job.status == job::JobStatus::Running && job.worker == Some(worker.id);
+        if !running_here || ended {
+            tokio::spawn(worker::delete_job(worker.clone(), worker_job.id));
+        }
+    }
+}
+
 pub(crate) async fn update_stream_state(state: Arc<state::App>) {
     let mut streams = state.streams.lock().await;
 
@@ -497,7 +744,9 @@ pub(crate) async fn update_stream_state(state: Arc<state::App>) {
         if stream.status != stream::Status::Running {
             let mut all_up = true;
             for output in stream.output.clone() {
-                if output.status != stream::Status::Running {
+                if output.status != stream::Status::Running
+                    && output.status != stream::Status::Finished
+                {
                     all_up = false;
                 }
             }
@@ -546,6 +795,7 @@ pub(crate) async fn monitor(state: Arc<state::App>) {
 
         // Check for new streams
         start_new_streams(state.clone()).await;
+        start_new_jobs(state.clone()).await;
 
         // If all outputs are up, set stream as up
         update_stream_state(state.clone()).await;
@@ -1,15 +1,18 @@
 use std::sync::Arc;
 
+use axum::body::Body;
 use axum::extract::Path;
-use axum::http::StatusCode;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::IntoResponse;
 use axum::{extract::State, Json};
 use clap::Parser;
 use json_patch::merge;
 use serde_json::{from_value, json, Error, Value};
 
+use crate::job::{self, Job};
 use crate::stream::Stream;
 use crate::{args, state};
-use crate::{stream, utils};
+use crate::{stream, utils, worker};
 
 pub(crate) async fn index(State(data): State<Arc<state::App>>) -> Json<Value> {
     // get number of streams
@@ -29,9 +32,11 @@ pub(crate) async fn index(State(data): State<Arc<state::App>>) -> Json<Value> {
 pub(crate) async fn reset_state(State(data): State<Arc<state::App>>) -> StatusCode {
     let mut streams_list = data.streams.lock().await;
     let mut workers_list = data.workers.lock().await;
+    let mut jobs_list = data.jobs.lock().await;
 
     streams_list.clear();
     workers_list.clear();
+    jobs_list.clear();
 
     return StatusCode::NO_CONTENT;
 }
@@ -40,8 +45,9 @@ pub(crate) async fn reset_state(State(data): State<Arc<state::App>>) -> StatusCo
 
 pub(crate) async fn get_all_streams(State(data): State<Arc<state::App>>) -> Json<Value> {
     let streams_list = data.streams.lock().await;
+    let streams: Vec<Stream> = streams_list.iter().map(Stream::redacted).collect();
 
-    return Json(json!(streams_list.clone()));
+    return Json(json!(streams));
 }
 
 // GET /stream:uuid
@@ -62,7 +68,7 @@ pub(crate) async fn get_stream(
         ));
     }
 
-    return Ok(Json(json!(stream.unwrap())));
+    return Ok(Json(json!(stream.unwrap().redacted())));
 }
 
 // POST /stream
@@ -77,11 +83,46 @@ pub(crate) struct CreateStream {
     name: String,
     input: String,
     output: Vec<CreateStreamOutput>,
+    // Probe the input on a worker and check the outputs against it
+    probe: Option<bool>,
 }
 pub(crate) async fn create_stream(
     State(data): State<Arc<state::App>>,
     Json(payload): Json<CreateStream>,
-) -> Json<Value> {
+) -> Result<Json<Value>, (StatusCode, String)> {
+    for output in payload.output.iter() {
+        let options = output.options.clone().unwrap_or_default();
+        if let Err(err_msg) = options.validate(&payload.input, &output.uri) {
+            return Err((StatusCode::BAD_REQUEST, err_msg));
+        }
+    }
+
+    if payload.probe.unwrap_or(false) {
+        let request = worker::ProbeRequest {
+            uri: payload.input.clone(),
+            input_srt: payload
+                .output
+                .iter()
+                .find_map(|x| x.options.as_ref().and_then(|o| o.input_srt.clone())),
+            timeout: None,
+        };
+        let info = probe(data.clone(), &request).await?;
+
+        if info.video_codec.is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Input has no video stream".to_string(),
+            ));
+        }
+        for output in payload.output.iter() {
+            if let Some(options) = &output.options {
+                if let Err(err_msg) = options.validate_input(&info) {
+                    return Err((StatusCode::BAD_REQUEST, err_msg));
+                }
+            }
+        }
+    }
+
     let mut streams_list = data.streams.lock().await;
 
     let new_stream = stream::Stream {
@@ -100,6 +141,11 @@ pub(crate) async fn create_stream(
                 worker: None,
                 logs: Vec::new(),
                 last_error: None,
+                playlist: None,
+                audio_tracks: None,
+                active_input: None,
+                alarms: None,
+                stats: None,
             })
             .collect(),
         enabled: true,
@@ -108,7 +154,7 @@ pub(crate) async fn create_stream(
 
     streams_list.push(new_stream.clone());
 
-    return Json(json!(new_stream));
+    return Ok(Json(json!(new_stream.redacted())));
 }
 
 // PATCH /stream:uuid
@@ -161,9 +207,61 @@ pub(crate) async fn patch_stream(
         return Err((StatusCode::BAD_REQUEST, err_msg));
     }
 
-    streams_list[index.unwrap()] = new_stream.unwrap().clone();
+    let mut new_stream = new_stream.unwrap();
+    utils::restore_uri(&mut new_stream.input, [&stream.input]);
+    let input_changed = new_stream.input != stream.input;
+    let masked_input_changed =
+        utils::redact_secrets(&new_stream.input) != utils::redact_secrets(&stream.input);
+
+    // Outputs whose secrets changed and nothing else, the worker only reports them masked
+    let mut secret_changes = Vec::new();
+    for output in new_stream.output.iter_mut() {
+        let current = stream.output.iter().find(|o| o.id == output.id);
+        let current_options = current.and_then(|o| o.options.as_ref());
+        if let Some(current) = current {
+            utils::restore_uri(&mut output.uri, [&current.uri]);
+        }
+        if let Some(options) = &mut output.options {
+            options.restore_secrets(current_options);
+        }
+
+        let options = output.options.clone().unwrap_or_default();
+        if let Err(err_msg) = options.validate(&new_stream.input, &output.uri) {
+            return Err((StatusCode::BAD_REQUEST, err_msg));
+        }
+
+        let Some(current) = current else {
+            continue;
+        };
+        let changed = input_changed
+            || output.uri != current.uri
+            || output.options.as_ref() != current_options;
+        let masked_changed = masked_input_changed
+            || utils::redact_secrets(&output.uri) != utils::redact_secrets(&current.uri)
+            || output.options.as_ref().map(stream::Options::redacted)
+                != current_options.map(stream::Options::redacted);
+        if changed && !masked_changed {
+            if let Some(worker) = current.worker {
+                secret_changes.push((worker, output.id));
+            }
+        }
+    }
+
+    let response = json!(new_stream.redacted());
+    streams_list[index.unwrap()] = new_stream;
+    drop(streams_list);
+
+    // Restarted with the new secrets once the monitor finds the output gone
+    if !secret_changes.is_empty() {
+        let workers_list = data.workers.lock().await;
+        for (worker_id, output_id) in secret_changes {
+            if let Some(worker) = workers_list.iter().find(|w| w.id == worker_id) {
+                tokio::spawn(worker::stop_stream(worker.clone(), stream.id, output_id));
+            }
+        }
+    }
 
-    return Ok(Json(stream_json));
+    Ok(Json(response))
 }
 
 // DELETE /stream:uuid
@@ -186,6 +284,265 @@ pub(crate) async fn delete_stream(
     return Ok(StatusCode::NO_CONTENT);
 }
 
+// GET /job
+pub(crate) async fn get_all_jobs(State(data): State<Arc<state::App>>) -> Json<Value> {
+    let jobs_list = data.jobs.lock().await;
+    let jobs: Vec<Job> = jobs_list.iter().map(Job::redacted).collect();
+
+    Json(json!(jobs))
+}
+
+// GET /job:uuid
+pub(crate) async fn get_job(
+    State(data): State<Arc<state::App>>,
+    Path(uuid): Path<String>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let jobs_list = data.jobs.lock().await;
+
+    let Some(job) = jobs_list.iter().find(|x| x.id.to_string() == uuid) else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Job with id {} not found", uuid),
+        ));
+    };
+
+    Ok(Json(json!(job.redacted())))
+}
+
+// POST /job
+#[derive(serde::Deserialize)]
+pub(crate) struct CreateJob {
+    name: String,
+    input: String,
+    output: String,
+    codec: stream::Codec,
+    options: Option<stream::Options>,
+    // Software encoders only, ignored by the worker otherwise
+    two_pass: Option<bool>,
+}
+pub(crate) async fn create_job(
+    State(data): State<Arc<state::App>>,
+    Json(payload): Json<CreateJob>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let new_job = Job {
+        id: uuid::Uuid::new_v4(),
+        name: payload.name,
+        input: payload.input,
+        output: payload.output,
+        codec: payload.codec,
+        options: payload.options,
+        two_pass: payload.two_pass,
+        status: job::JobStatus::Creating,
+        worker: None,
+        progress: None,
+        pass: None,
+        exit_code: None,
+        error: None,
+        elapsed: None,
+        logs: Vec::new(),
+    };
+    if let Err(err_msg) = new_job.validate() {
+        return Err((StatusCode::BAD_REQUEST, err_msg));
+    }
+
+    data.jobs.lock().await.push(new_job.clone());
+
+    Ok(Json(json!(new_job.redacted())))
+}
+
+// DELETE /job:uuid
+// A running job is stopped on its worker by the monitor once it is gone here
+pub(crate) async fn delete_job(
+    State(data): State<Arc<state::App>>,
+    Path(uuid): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let mut jobs_list = data.jobs.lock().await;
+
+    let Some(index) = jobs_list.iter().position(|x| x.id.to_string() == uuid) else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Job with id {} not found", uuid),
+        ));
+    };
+
+    jobs_list.remove(index);
+    log::info!("Job with id {} deleted", uuid);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// POST /probe
+// Probe an input on the least busy worker
+pub(crate) async fn probe_input(
+    State(data): State<Arc<state::App>>,
+    Json(payload): Json<worker::ProbeRequest>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let info = probe(data, &payload).await?;
+    Ok(Json(json!(info)))
+}
+
+async fn probe(
+    state: Arc<state::App>,
+    request: &worker::ProbeRequest,
+) -> Result<stream::InputInfo, (StatusCode, String)> {
+    let Some(worker) = worker::probe_worker(state).await else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No worker available to probe the input".to_string(),
+        ));
+    };
+    worker::probe(&worker, request).await
+}
+
+// GET /stream/:uuid/output/:oid/thumbnail
+// Proxied from the worker running the output
+pub(crate) async fn get_output_thumbnail(
+    State(data): State<Arc<state::App>>,
+    Path((uuid, oid)): Path<(String, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let output = {
+        let streams_list = data.streams.lock().await;
+        streams_list
+            .iter()
+            .find(|x| x.id.to_string() == uuid)
+            .and_then(|stream| stream.output.iter().find(|x| x.id.to_string() == oid))
+            .cloned()
+    };
+    let Some(output) = output else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Output with id {} not found in stream {}", oid, uuid),
+        ));
+    };
+
+    let worker = match output.worker {
+        Some(worker_id) => worker::get_all(data.clone())
+            .await
+            .into_iter()
+            .find(|x| x.id == worker_id),
+        None => None,
+    };
+    let Some(worker) = worker else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Output {} is not running on a worker", oid),
+        ));
+    };
+
+    let jpeg = worker::get_thumbnail(&worker, output.id).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/jpeg"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        jpeg,
+    ))
+}
+
+// Output of a stream, recordings are only served under the stream they belong to
+async fn stream_output_id(
+    data: &Arc<state::App>,
+    uuid: &str,
+    oid: &str,
+) -> Result<uuid::Uuid, (StatusCode, String)> {
+    let streams_list = data.streams.lock().await;
+    let Some(stream) = streams_list.iter().find(|x| x.id.to_string() == uuid) else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Stream with id {} not found", uuid),
+        ));
+    };
+    stream
+        .output
+        .iter()
+        .find(|x| x.id.to_string() == oid)
+        .map(|output| output.id)
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            format!("Output with id {} not found in stream {}", oid, uuid),
+        ))
+}
+
+// Recordings outlive their output run and may be spread over every worker it ran on, so all of them are asked
+async fn list_recordings(
+    data: &Arc<state::App>,
+    output_id: uuid::Uuid,
+) -> Vec<worker::RecordingFile> {
+    let mut files = Vec::new();
+    for worker in worker::get_all(data.clone()).await {
+        files.extend(worker::get_recordings(&worker, output_id).await);
+    }
+    files.sort_by(|a, b| (a.modified, &a.name).cmp(&(b.modified, &b.name)));
+
+    data.recordings
+        .lock()
+        .await
+        .insert(output_id, files.clone());
+    files
+}
+
+// GET /stream/:uuid/output/:oid/recording
+pub(crate) async fn get_output_recordings(
+    State(data): State<Arc<state::App>>,
+    Path((uuid, oid)): Path<(String, String)>,
+) -> Result<Json<Vec<worker::RecordingFile>>, (StatusCode, String)> {
+    let output_id = stream_output_id(&data, &uuid, &oid).await?;
+    Ok(Json(list_recordings(&data, output_id).await))
+}
+
+// GET /stream/:uuid/output/:oid/recording/:name
+// Streamed through from the worker holding the segment, found in the last listing or a new one
+pub(crate) async fn get_output_recording(
+    State(data): State<Arc<state::App>>,
+    Path((uuid, oid, name)): Path<(String, String, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let output_id = stream_output_id(&data, &uuid, &oid).await?;
+
+    let holder = |files: &[worker::RecordingFile]| {
+        files.iter().find(|f| f.name == name).and_then(|f| f.worker)
+    };
+    let listed = data
+        .recordings
+        .lock()
+        .await
+        .get(&output_id)
+        .and_then(|files| holder(files));
+    let worker_id = match listed {
+        Some(worker_id) => Some(worker_id),
+        None => holder(&list_recordings(&data, output_id).await),
+    };
+    let worker = match worker_id {
+        Some(worker_id) => worker::get_all(data.clone())
+            .await
+            .into_iter()
+            .find(|x| x.id == worker_id),
+        None => None,
+    };
+
+    if let Some(worker) = worker {
+        let response = worker::get_recording(&worker, output_id, &name).await?;
+        let mut headers = HeaderMap::new();
+        for key in [
+            header::CONTENT_TYPE,
+            header::CONTENT_LENGTH,
+            header::CONTENT_DISPOSITION,
+        ] {
+            let value = response
+                .headers()
+                .get(key.as_str())
+                .and_then(|v| HeaderValue::from_bytes(v.as_bytes()).ok());
+            if let Some(value) = value {
+                headers.insert(key, value);
+            }
+        }
+        return Ok((headers, Body::from_stream(response.bytes_stream())));
+    }
+
+    Err((
+        StatusCode::NOT_FOUND,
+        format!("Recording {} of output {} not found", name, oid),
+    ))
+}
+
 // GET /worker
 pub(crate) async fn get_all_workers(State(data): State<Arc<state::App>>) -> Json<Value> {
     let workers_list = data.workers.lock().await;
@@ -1,5 +1,8 @@
+use crate::job::{Job, JobStatus};
 use crate::stream::{Codec, Stream};
+use crate::worker::RecordingFile;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
@@ -19,15 +22,25 @@ pub(crate) struct WorkerInfo {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub(crate) struct WorkerStats {
     pub utilization: u32,
-    pub devices: Vec<u32>,
+    pub devices: Vec<DeviceStats>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DeviceStats {
+    pub utilization: u32,
+    pub sessions: Option<u32>,
+}
+
+// Variant names are the JSON values
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy, Eq)]
 pub(crate) enum Encoder {
     NVENC,
     VideoToolbox,
     U30,
     NIT2A,
+    QSV,
+    VAAPI,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -45,30 +58,56 @@ pub(crate) struct Worker {
     pub(crate) streams: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub(crate) struct StateDump {
     pub(crate) streams: Vec<Stream>,
     pub(crate) workers: Vec<Worker>,
+    // Missing in state files written before jobs existed
+    #[serde(default)]
+    pub(crate) jobs: Vec<Job>,
 }
 
 pub(crate) struct App {
     pub(crate) streams: Mutex<Vec<Stream>>,
     pub(crate) workers: Mutex<Vec<Worker>>,
+    pub(crate) jobs: Mutex<Vec<Job>>,
+    // Recordings of each output as last listed, a download goes straight to the worker holding the file
+    pub(crate) recordings: Mutex<HashMap<Uuid, Vec<RecordingFile>>>,
 }
 impl App {
     pub(crate) fn new() -> App {
         return App {
             streams: Mutex::new(Vec::new()),
             workers: Mutex::new(Vec::new()),
+            jobs: Mutex::new(Vec::new()),
+            recordings: Mutex::new(HashMap::new()),
         };
     }
 
     pub(crate) async fn dump(&self) -> StateDump {
         let streams = self.streams.lock().await;
         let workers = self.workers.lock().await;
+        // Output stats change every second, leave them out so they don't trigger state file writes
+        let mut streams = streams.clone();
+        for stream in streams.iter_mut() {
+            for output in stream.output.iter_mut() {
+                output.stats = None;
+            }
+        }
+
+        // Same for the progress of running jobs
+        let mut jobs = self.jobs.lock().await.clone();
+        for job in jobs.iter_mut() {
+            if job.status == JobStatus::Running {
+                job.progress = None;
+                job.elapsed = None;
+            }
+        }
+
         return StateDump {
-            streams: streams.clone(),
+            streams,
             workers: workers.clone(),
+            jobs,
         };
     }
 }
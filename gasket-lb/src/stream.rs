@@ -1,4 +1,5 @@
 use crate::state::App;
+use crate::utils;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use uuid::Uuid;
@@ -27,17 +28,1009 @@ impl Codec {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
 pub(crate) struct Options {
     pub(crate) pixel_format: Option<String>,
     pub(crate) bitrate: Option<String>,
     pub(crate) framerate: Option<String>,
     pub(crate) gop_size: Option<String>,
     pub(crate) debug_text: Option<bool>,
+    pub(crate) overlays: Option<Vec<Overlay>>,
     pub(crate) output_format: Option<String>,
+    pub(crate) renditions: Option<Vec<Rendition>>,
+    pub(crate) packaging: Option<Packaging>,
+    pub(crate) input_srt: Option<SrtOptions>,
+    pub(crate) output_srt: Option<SrtOptions>,
+    pub(crate) input_mode: Option<InputMode>,
+    pub(crate) restart: Option<RestartPolicy>,
+    pub(crate) failover: Option<Failover>,
+    pub(crate) rate_control: Option<RateControl>,
+    pub(crate) tuning: Option<Tuning>,
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    pub(crate) scaling_mode: Option<ScalingMode>,
+    pub(crate) crop: Option<Crop>,
+    pub(crate) pad: Option<String>,
+    pub(crate) audio: Option<AudioOptions>,
+    pub(crate) deinterlace: Option<Deinterlace>,
+    pub(crate) framerate_mode: Option<FramerateMode>,
+    pub(crate) tonemap: Option<Tonemap>,
+    pub(crate) recording: Option<Recording>,
+    pub(crate) alarms: Option<AlarmOptions>,
+    // MPEG-TS analysis of udp inputs on the worker, false if not set
+    pub(crate) analyze_input: Option<bool>,
+    pub(crate) program: Option<ProgramSelection>,
+    pub(crate) scte35: Option<bool>,
+    pub(crate) mpegts: Option<MpegtsOptions>,
+}
+
+// Program of an MPTS input, by number or by the service name found when probing the input
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ProgramSelection {
+    pub(crate) number: Option<u32>,
+    pub(crate) service_name: Option<String>,
+}
+
+impl ProgramSelection {
+    fn validate(&self) -> Result<(), String> {
+        match (self.number, &self.service_name) {
+            (Some(_), Some(_)) | (None, None) => {
+                Err("Program needs either a number or a service_name".to_string())
+            }
+            (Some(number), None) if number == 0 || number > 0xFFFF => Err(format!(
+                "Program number {} must be between 1 and 65535",
+                number
+            )),
+            (None, Some(service_name)) if service_name.is_empty() => {
+                Err("Program service_name cannot be empty".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Service information and PIDs of mpegts outputs, ffmpeg's defaults if not set
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct MpegtsOptions {
+    pub(crate) service_name: Option<String>,
+    pub(crate) service_provider: Option<String>,
+    pub(crate) service_id: Option<u32>,
+    pub(crate) transport_stream_id: Option<u32>,
+    pub(crate) original_network_id: Option<u32>,
+    pub(crate) pmt_pid: Option<u32>,
+    pub(crate) start_pid: Option<u32>,
+    pub(crate) video_pid: Option<u32>,
+    pub(crate) audio_pids: Option<Vec<u32>>,
+}
+
+impl MpegtsOptions {
+    fn validate(&self) -> Result<(), String> {
+        let ids = [
+            ("service_id", self.service_id),
+            ("transport_stream_id", self.transport_stream_id),
+            ("original_network_id", self.original_network_id),
+        ];
+        for (name, id) in ids {
+            if let Some(id) = id.filter(|id| *id > 0xFFFF) {
+                return Err(format!("mpegts {} {} must be below 65536", name, id));
+            }
+        }
+        if self.service_id == Some(0) {
+            return Err("mpegts service_id 0 is reserved for the network information".to_string());
+        }
+
+        // PIDs below 0x10 carry the PSI tables, 0x1FFF is the null packet
+        let mut pids: Vec<(&str, u32)> = [
+            ("pmt_pid", self.pmt_pid),
+            ("start_pid", self.start_pid),
+            ("video_pid", self.video_pid),
+        ]
+        .into_iter()
+        .filter_map(|(name, pid)| Some((name, pid?)))
+        .collect();
+        pids.extend(
+            self.audio_pids
+                .iter()
+                .flatten()
+                .map(|pid| ("audio_pids", *pid)),
+        );
+        for (name, pid) in &pids {
+            if !(0x10..=0x1FFE).contains(pid) {
+                return Err(format!(
+                    "mpegts {} {} must be between 16 and 8190",
+                    name, pid
+                ));
+            }
+        }
+
+        // start_pid only numbers the streams without a PID of their own
+        let stream_pids: Vec<u32> = pids
+            .iter()
+            .filter(|(name, _)| *name != "start_pid")
+            .map(|(_, pid)| *pid)
+            .collect();
+        for (i, pid) in stream_pids.iter().enumerate() {
+            if stream_pids[..i].contains(pid) {
+                return Err(format!("mpegts PID {} is used more than once", pid));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DeinterlaceFilter {
+    Yadif,
+    Bwdif,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Deinterlace {
+    pub(crate) filter: Option<DeinterlaceFilter>,
+    pub(crate) field_rate: Option<bool>,
+    pub(crate) all_frames: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FramerateMode {
+    DropDup,
+    Blend,
+    Interpolate,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TonemapAlgorithm {
+    Hable,
+    Mobius,
+    Reinhard,
+    Clip,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Tonemap {
+    pub(crate) algorithm: Option<TonemapAlgorithm>,
+    // Nits
+    pub(crate) peak: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OverlayKind {
+    Text,
+    Timecode,
+    Logo,
+    Debug,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OverlayPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Overlay {
+    pub(crate) kind: OverlayKind,
+    pub(crate) text: Option<String>,
+    pub(crate) file: Option<String>,
+    pub(crate) position: Option<OverlayPosition>,
+    pub(crate) margin: Option<u32>,
+    pub(crate) size: Option<u32>,
+    pub(crate) color: Option<String>,
+    pub(crate) background: Option<String>,
+    pub(crate) font: Option<String>,
+    // Percent
+    pub(crate) opacity: Option<u32>,
+    pub(crate) rate: Option<String>,
+}
+
+impl Overlay {
+    fn validate(&self) -> Result<(), String> {
+        match self.kind {
+            OverlayKind::Text if self.text.as_deref().unwrap_or_default().is_empty() => {
+                return Err("Text overlay needs a text".to_string());
+            }
+            OverlayKind::Logo if self.file.as_deref().unwrap_or_default().is_empty() => {
+                return Err("Logo overlay needs a file".to_string());
+            }
+            _ => {}
+        }
+
+        if self.size == Some(0) {
+            return Err("Overlay size must be greater than 0".to_string());
+        }
+        if self.opacity.is_some_and(|o| o > 100) {
+            return Err("Overlay opacity must be between 0 and 100".to_string());
+        }
+        // Would end the quoted drawtext option
+        if self.text.as_ref().is_some_and(|t| t.contains('\'')) {
+            return Err("Overlay text cannot contain single quotes".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// Variant names are the JSON values
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AudioCodec {
+    Copy,
+    AAC,
+    AC3,
+    Opus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct AudioOptions {
+    pub(crate) codec: Option<AudioCodec>,
+    pub(crate) bitrate: Option<String>,
+    pub(crate) channel_layout: Option<String>,
+    pub(crate) sample_rate: Option<u32>,
+    pub(crate) loudness: Option<i32>,
+    pub(crate) tracks: Option<Vec<u32>>,
+    pub(crate) languages: Option<Vec<String>>,
+}
+
+impl AudioOptions {
+    fn validate(&self) -> Result<(), String> {
+        match self.codec {
+            Some(AudioCodec::Copy)
+                if self.bitrate.is_some()
+                    || self.channel_layout.is_some()
+                    || self.sample_rate.is_some()
+                    || self.loudness.is_some() =>
+            {
+                return Err(
+                    "Audio copy cannot change bitrate, channel_layout, sample_rate or loudness"
+                        .to_string(),
+                );
+            }
+            Some(AudioCodec::Opus) => {
+                if let Some(sample_rate) = self.sample_rate {
+                    if ![8000, 12000, 16000, 24000, 48000].contains(&sample_rate) {
+                        return Err(format!("Opus does not support sample_rate {}", sample_rate));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if self.sample_rate == Some(0) {
+            return Err("Audio sample_rate must be greater than 0".to_string());
+        }
+        if let Some(bitrate) = &self.bitrate {
+            parse_rate("audio bitrate", bitrate)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Audio stream of the input, reported by the worker
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct AudioTrack {
+    // Counted in the whole input in InputInfo, within the selected program in StreamInfo
+    pub(crate) index: u32,
+    pub(crate) codec: Option<String>,
+    pub(crate) language: Option<String>,
+    pub(crate) channels: Option<u32>,
+    pub(crate) channel_layout: Option<String>,
+    pub(crate) sample_rate: Option<u32>,
+    pub(crate) program: Option<u32>,
+}
+
+// What ffprobe found in the input, reported by the worker
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub(crate) struct InputInfo {
+    pub(crate) format: Option<String>,
+    pub(crate) video_codec: Option<String>,
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    pub(crate) framerate: Option<f64>,
+    // Seconds, files only
+    pub(crate) duration: Option<f64>,
+    pub(crate) programs: Vec<Program>,
+    pub(crate) audio_tracks: Vec<AudioTrack>,
+    pub(crate) data_streams: Vec<DataStream>,
+}
+
+impl InputInfo {
+    // Program a selection refers to, None if the input has no such program
+    fn program_number(&self, selection: &ProgramSelection) -> Option<u32> {
+        self.programs
+            .iter()
+            .find(|p| {
+                Some(p.program_num) == selection.number
+                    || (p.service_name.is_some() && p.service_name == selection.service_name)
+            })
+            .map(|p| p.program_num)
+    }
+
+    // Audio tracks numbered like the worker maps them once a program is selected
+    fn program_audio_tracks(&self, program: Option<u32>) -> Vec<AudioTrack> {
+        let Some(program) = program else {
+            return self.audio_tracks.clone();
+        };
+        self.audio_tracks
+            .iter()
+            .filter(|t| t.program == Some(program))
+            .enumerate()
+            .map(|(index, track)| AudioTrack {
+                index: index as u32,
+                ..track.clone()
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DataStream {
+    pub(crate) index: u32,
+    pub(crate) codec: Option<String>,
+    pub(crate) pid: Option<u32>,
+    pub(crate) program: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Program {
+    pub(crate) program_num: u32,
+    pub(crate) pmt_pid: Option<u32>,
+    pub(crate) pcr_pid: Option<u32>,
+    pub(crate) service_name: Option<String>,
+    pub(crate) service_provider: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ScalingMode {
+    Fit,
+    Fill,
+    Stretch,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Crop {
+    pub(crate) top: Option<u32>,
+    pub(crate) bottom: Option<u32>,
+    pub(crate) left: Option<u32>,
+    pub(crate) right: Option<u32>,
+}
+
+// Variant names are the JSON values
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RateControlMode {
+    CBR,
+    VBR,
+    CRF,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RateControl {
+    pub(crate) mode: RateControlMode,
+    pub(crate) max_bitrate: Option<String>,
+    pub(crate) buffer_size: Option<String>,
+    pub(crate) quality: Option<u32>,
+    pub(crate) muxrate: Option<String>,
+}
+
+impl RateControl {
+    // mpegts is false for any other muxer, including packaged outputs
+    pub(crate) fn validate(&self, bitrate: &str, mpegts: bool) -> Result<(), String> {
+        let bitrate = parse_rate("bitrate", bitrate)?;
+
+        if let Some(max_bitrate) = &self.max_bitrate {
+            if parse_rate("max_bitrate", max_bitrate)? < bitrate {
+                return Err(format!("max_bitrate {} is below the bitrate", max_bitrate));
+            }
+        }
+        if let Some(buffer_size) = &self.buffer_size {
+            parse_rate("buffer_size", buffer_size)?;
+        }
+
+        match self.mode {
+            RateControlMode::CRF => {
+                if self.quality.is_some_and(|q| q > 63) {
+                    return Err("Rate control quality must be between 0 and 63".to_string());
+                }
+            }
+            _ => {
+                if self.quality.is_some() {
+                    return Err("Rate control quality is only used with CRF".to_string());
+                }
+            }
+        }
+
+        if let Some(muxrate) = &self.muxrate {
+            if !mpegts {
+                return Err("muxrate needs mpegts output".to_string());
+            }
+            // Room for audio and PSI on top of the video
+            if parse_rate("muxrate", muxrate)? <= bitrate {
+                return Err(format!("muxrate {} must be above the bitrate", muxrate));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Preset {
+    UltraFast,
+    Fast,
+    Medium,
+    Slow,
+    VerySlow,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Tuning {
+    pub(crate) preset: Option<Preset>,
+    pub(crate) low_latency: Option<bool>,
+    pub(crate) profile: Option<String>,
+    pub(crate) level: Option<String>,
+    pub(crate) b_frames: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RestartMode {
+    Never,
+    OnFailure,
+    Always,
+}
+
+// Restarts done locally by the worker before the output is rescheduled
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RestartPolicy {
+    pub(crate) mode: RestartMode,
+    pub(crate) max_retries: Option<u32>,
+    // Milliseconds
+    pub(crate) backoff: Option<u64>,
+    pub(crate) max_backoff: Option<u64>,
+}
+
+// Backup inputs and slate the worker switches to when the input stalls or fails
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Failover {
+    pub(crate) backup_inputs: Option<Vec<String>>,
+    // Seconds
+    pub(crate) stall_timeout: Option<u32>,
+    // File on the worker
+    pub(crate) slate: Option<String>,
+}
+impl Failover {
+    fn validate(&self) -> Result<(), String> {
+        let backups = self.backup_inputs.as_deref().unwrap_or_default();
+        if backups.is_empty() && self.slate.is_none() {
+            return Err("Failover needs backup_inputs or a slate".to_string());
+        }
+        if backups.iter().any(|b| b.is_empty()) {
+            return Err("Backup input uri cannot be empty".to_string());
+        }
+        if self.slate.as_ref().is_some_and(|s| s.is_empty()) {
+            return Err("Slate cannot be empty".to_string());
+        }
+        if self.stall_timeout == Some(0) {
+            return Err("Failover stall_timeout must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+// How the worker reads the input, inferred from the input uri when not set
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum InputMode {
+    Loop,
+    Live,
+    Once,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SrtMode {
+    Caller,
+    Listener,
+    Rendezvous,
+}
+
+// SRT settings, the worker turns them into srt:// query parameters
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub(crate) struct SrtOptions {
+    pub(crate) mode: Option<SrtMode>,
+    // Latency in milliseconds
+    pub(crate) latency: Option<u32>,
+    pub(crate) passphrase: Option<String>,
+    pub(crate) streamid: Option<String>,
+}
+impl SrtOptions {
+    // Check the settings against the uri they are applied to
+    pub(crate) fn validate(&self, uri: &str) -> Result<(), String> {
+        let address = match uri.split_once("://") {
+            Some(("srt", rest)) => rest,
+            Some(_) => return Err(format!("SRT options given for non-SRT uri {}", uri)),
+            None => uri,
+        };
+        let (address, query) = address.split_once('?').unwrap_or((address, ""));
+        let listener =
+            self.mode == Some(SrtMode::Listener) || query.split('&').any(|p| p == "mode=listener");
+
+        let (host, port) = match address.rsplit_once(':') {
+            Some(host_port) => host_port,
+            None => return Err(format!("SRT uri {} is missing a port", uri)),
+        };
+        if port.parse::<u16>().is_err() {
+            return Err(format!("SRT uri {} has an invalid port", uri));
+        }
+        // Only a listener can bind without a host
+        if host.is_empty() && !listener {
+            return Err(format!("SRT uri {} is missing a host", uri));
+        }
+
+        // A bare uri carries it in its query, where percent-encoded bytes count once
+        let query_passphrase = query
+            .split('&')
+            .find_map(|p| p.strip_prefix("passphrase="))
+            .map(|p| p.len().saturating_sub(2 * p.matches('%').count()));
+        if let Some(length) = self
+            .passphrase
+            .as_ref()
+            .map(String::len)
+            .or(query_passphrase)
+        {
+            if !(10..=79).contains(&length) {
+                return Err("SRT passphrase must be 10 to 79 characters".to_string());
+            }
+        }
+
+        if let Some(streamid) = &self.streamid {
+            if streamid.len() > 512 {
+                return Err("SRT streamid must be at most 512 characters".to_string());
+            }
+        }
+
+        Ok(())
+    }
 }
 
+// Extra ladder rung, placed on the same worker as the rest of its output
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Rendition {
+    pub(crate) uri: String,
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    pub(crate) bitrate: Option<String>,
+    pub(crate) gop_size: Option<String>,
+}
+
+// Variant names are the JSON values
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PackagingFormat {
+    HLS,
+    CMAF,
+    DASH,
+}
+
+// HLS/CMAF/DASH output, the output uri is a directory on the worker or an http(s) PUT target
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Packaging {
+    pub(crate) format: PackagingFormat,
+    pub(crate) segment_duration: Option<u32>,
+    pub(crate) window_size: Option<u32>,
+}
+
+// Segmented copy of the output on the worker's disk, see GET /stream/:uuid/output/:oid/recording
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Recording {
+    pub(crate) segment_duration: Option<u32>,
+    // strftime template, e.g. "%Y%m%d-%H%M%S.ts"
+    pub(crate) filename: Option<String>,
+    pub(crate) max_segments: Option<usize>,
+    pub(crate) max_size_mb: Option<u64>,
+}
+
+impl Recording {
+    fn validate(&self) -> Result<(), String> {
+        if self.segment_duration == Some(0) {
+            return Err("Recording segment_duration must be greater than 0".to_string());
+        }
+        if self.max_segments == Some(0) || self.max_size_mb == Some(0) {
+            return Err("Recording limits must be greater than 0".to_string());
+        }
+
+        // Segments stay in the stream's recording directory, the extension picks the container
+        if let Some(filename) = &self.filename {
+            if filename.contains(['/', '\\']) {
+                return Err("Recording filename cannot contain a path".to_string());
+            }
+            if !filename.contains('%') {
+                return Err(format!(
+                    "Recording filename {} needs a strftime field like %H, or every segment overwrites the last",
+                    filename
+                ));
+            }
+            if filename
+                .rsplit_once('.')
+                .is_none_or(|(_, ext)| ext.is_empty())
+            {
+                return Err(format!(
+                    "Recording filename {} needs an extension",
+                    filename
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AlarmKind {
+    Black,
+    Freeze,
+    Silence,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Detector {
+    pub(crate) enabled: Option<bool>,
+    // Seconds the condition lasts before the alarm is raised
+    pub(crate) duration: Option<u32>,
+    // Black: percent of dark pixels, freeze and silence: noise tolerance in dB
+    pub(crate) threshold: Option<i32>,
+}
+
+// Black, freeze and silence detection of the decoded input on the worker, only the detectors that are set run
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct AlarmOptions {
+    pub(crate) enabled: Option<bool>,
+    pub(crate) black: Option<Detector>,
+    pub(crate) freeze: Option<Detector>,
+    pub(crate) silence: Option<Detector>,
+}
+
+impl AlarmOptions {
+    fn validate(&self) -> Result<(), String> {
+        let detectors = [
+            (AlarmKind::Black, &self.black),
+            (AlarmKind::Freeze, &self.freeze),
+            (AlarmKind::Silence, &self.silence),
+        ];
+        for (kind, detector) in detectors {
+            let Some(detector) = detector else {
+                continue;
+            };
+            if detector.duration == Some(0) {
+                return Err(format!("{:?} alarm duration must be greater than 0", kind));
+            }
+            match (kind, detector.threshold) {
+                (AlarmKind::Black, Some(threshold)) if !(1..=100).contains(&threshold) => {
+                    return Err(format!(
+                        "Black alarm threshold {} must be a percentage between 1 and 100",
+                        threshold
+                    ));
+                }
+                (AlarmKind::Freeze | AlarmKind::Silence, Some(threshold)) if threshold >= 0 => {
+                    return Err(format!(
+                        "{:?} alarm threshold {} must be below 0 dB",
+                        kind, threshold
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Options {
+    // Reject configurations the worker would only fail on once ffmpeg is running
+    pub(crate) fn validate(&self, input: &str, uri: &str) -> Result<(), String> {
+        if let Some(renditions) = &self.renditions {
+            // Same default as the worker
+            let gop_size = self.gop_size.clone().unwrap_or("60".to_string());
+            let gop = parse_gop(&gop_size)?;
+
+            for rendition in renditions {
+                if rendition.uri.is_empty() {
+                    return Err("Rendition uri cannot be empty".to_string());
+                }
+
+                if let Some(rendition_gop_size) = &rendition.gop_size {
+                    if parse_gop(rendition_gop_size)? % gop != 0 {
+                        return Err(format!(
+                            "Rendition {} gop_size {} is not a multiple of gop_size {}, keyframes would not be aligned",
+                            rendition.uri, rendition_gop_size, gop_size
+                        ));
+                    }
+                }
+            }
+        }
+
+        // Bare srt:// uris are checked too, with the settings in their query
+        let bare = SrtOptions::default();
+        let srt_uri = |uri: &str| uri.starts_with("srt://");
+        if let Some(srt) = self.input_srt.as_ref().or(srt_uri(input).then_some(&bare)) {
+            srt.validate(input)?;
+        }
+        let backups = self
+            .failover
+            .iter()
+            .flat_map(|f| f.backup_inputs.iter().flatten());
+        for backup in backups.filter(|b| srt_uri(b)) {
+            bare.validate(backup)?;
+        }
+
+        let outputs = std::iter::once(uri).chain(
+            self.renditions
+                .iter()
+                .flatten()
+                .map(|rendition| rendition.uri.as_str()),
+        );
+        for output in outputs {
+            if let Some(srt) = self
+                .output_srt
+                .as_ref()
+                .or(srt_uri(output).then_some(&bare))
+            {
+                srt.validate(output)?;
+            }
+        }
+
+        if let Some(packaging) = &self.packaging {
+            if packaging.segment_duration == Some(0) {
+                return Err("Packaging segment_duration must be greater than 0".to_string());
+            }
+        }
+
+        self.validate_scaling()?;
+
+        for overlay in self.overlays.iter().flatten() {
+            overlay.validate()?;
+        }
+
+        if self.framerate_mode.is_some() && self.framerate.is_none() {
+            return Err("framerate_mode needs a framerate".to_string());
+        }
+        if self.tonemap.as_ref().is_some_and(|t| t.peak == Some(0)) {
+            return Err("Tonemap peak must be greater than 0".to_string());
+        }
+
+        if let Some(rate_control) = &self.rate_control {
+            // Same default as the worker
+            let bitrate = self.bitrate.clone().unwrap_or("500k".to_string());
+            rate_control.validate(&bitrate, self.mpegts())?;
+        }
+
+        if let Some(audio) = &self.audio {
+            audio.validate()?;
+        }
+
+        if let Some(failover) = &self.failover {
+            failover.validate()?;
+        }
+
+        if let Some(recording) = &self.recording {
+            recording.validate()?;
+        }
+
+        if let Some(alarms) = &self.alarms {
+            alarms.validate()?;
+        }
+
+        if let Some(program) = &self.program {
+            program.validate()?;
+        }
+
+        if let Some(mpegts) = &self.mpegts {
+            if !self.mpegts() {
+                return Err("mpegts options need an mpegts output".to_string());
+            }
+            mpegts.validate()?;
+        }
+        if self.scte35 == Some(true) && !self.mpegts() {
+            return Err("SCTE-35 passthrough needs an mpegts output".to_string());
+        }
+
+        Ok(())
+    }
+
+    // Copy for API responses, SRT passphrases are masked, in the settings and in the URIs
+    pub(crate) fn redacted(&self) -> Options {
+        let mut options = self.clone();
+        for srt in [&mut options.input_srt, &mut options.output_srt]
+            .into_iter()
+            .flatten()
+        {
+            if srt.passphrase.is_some() {
+                srt.passphrase = Some(utils::REDACTED.to_string());
+            }
+        }
+        for rendition in options.renditions.iter_mut().flatten() {
+            rendition.uri = utils::redact_secrets(&rendition.uri);
+        }
+        for backup in options.backup_inputs_mut() {
+            *backup = utils::redact_secrets(backup);
+        }
+        options
+    }
+
+    fn backup_inputs_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        self.failover
+            .iter_mut()
+            .flat_map(|f| f.backup_inputs.iter_mut().flatten())
+    }
+
+    // Keep the current passphrases where a client sent back the masked ones
+    pub(crate) fn restore_secrets(&mut self, current: Option<&Options>) {
+        let current_srt = [
+            current.and_then(|c| c.input_srt.as_ref()),
+            current.and_then(|c| c.output_srt.as_ref()),
+        ];
+        for (srt, current) in [&mut self.input_srt, &mut self.output_srt]
+            .into_iter()
+            .zip(current_srt)
+        {
+            if let Some(srt) = srt {
+                if srt.passphrase.as_deref() == Some(utils::REDACTED) {
+                    srt.passphrase = current.and_then(|c| c.passphrase.clone());
+                }
+            }
+        }
+
+        let current_renditions = current.and_then(|c| c.renditions.as_ref());
+        for rendition in self.renditions.iter_mut().flatten() {
+            utils::restore_uri(
+                &mut rendition.uri,
+                current_renditions.into_iter().flatten().map(|r| &r.uri),
+            );
+        }
+        let current_backups = current
+            .and_then(|c| c.failover.as_ref())
+            .and_then(|f| f.backup_inputs.as_ref());
+        for backup in self.backup_inputs_mut() {
+            utils::restore_uri(backup, current_backups.into_iter().flatten());
+        }
+    }
+
+    // Check the options against what the worker found in the input
+    pub(crate) fn validate_input(&self, info: &InputInfo) -> Result<(), String> {
+        let program = match &self.program {
+            Some(program) => Some(info.program_number(program).ok_or_else(|| {
+                format!(
+                    "Input has no program {}",
+                    program
+                        .service_name
+                        .clone()
+                        .unwrap_or_else(|| program.number.unwrap_or_default().to_string())
+                )
+            })?),
+            None => None,
+        };
+
+        let Some(audio) = &self.audio else {
+            return Ok(());
+        };
+        // Track numbers count within the selected program
+        let audio_tracks = info.program_audio_tracks(program);
+
+        for track in audio.tracks.iter().flatten() {
+            if !audio_tracks.iter().any(|t| t.index == *track) {
+                return Err(format!("Input has no audio track {}", track));
+            }
+        }
+        for language in audio.languages.iter().flatten() {
+            if !audio_tracks
+                .iter()
+                .any(|t| t.language.as_ref() == Some(language))
+            {
+                return Err(format!("Input has no {} audio track", language));
+            }
+        }
+
+        // Copied audio has to fit in the output container
+        if audio.codec == Some(AudioCodec::Copy) && self.mpegts() {
+            let supported = ["aac", "mp2", "mp3", "ac3", "eac3", "opus"];
+            let all = audio.tracks.is_none() && audio.languages.is_none();
+            let mapped = audio_tracks.iter().filter(|t| {
+                all || audio.tracks.iter().flatten().any(|i| *i == t.index)
+                    || audio
+                        .languages
+                        .iter()
+                        .flatten()
+                        .any(|l| t.language.as_ref() == Some(l))
+            });
+            for track in mapped {
+                if let Some(codec) = &track.codec {
+                    if !supported.contains(&codec.as_str()) {
+                        return Err(format!(
+                            "Input audio track {} is {}, which cannot be copied to mpegts",
+                            track.index, codec
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Default muxer on the worker, packaged outputs use their own
+    fn mpegts(&self) -> bool {
+        self.packaging.is_none() && self.output_format.as_deref().unwrap_or("mpegts") == "mpegts"
+    }
+
+    fn validate_scaling(&self) -> Result<(), String> {
+        // 4:2:0 chroma needs even sizes
+        let sizes = std::iter::once((self.width, self.height)).chain(
+            self.renditions
+                .iter()
+                .flatten()
+                .map(|r| (r.width, r.height)),
+        );
+        for (width, height) in sizes {
+            if width
+                .into_iter()
+                .chain(height)
+                .any(|d| d == 0 || d % 2 != 0)
+            {
+                return Err("Output width and height must be even and greater than 0".to_string());
+            }
+        }
+
+        let exact = self.width.is_some() && self.height.is_some();
+        if self.scaling_mode == Some(ScalingMode::Fill) && !exact {
+            return Err("Fill scaling needs both width and height".to_string());
+        }
+        if self.pad.is_some() && (self.scaling_mode != Some(ScalingMode::Fit) || !exact) {
+            return Err("Padding needs Fit scaling with both width and height".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_rate(name: &str, rate: &str) -> Result<u64, String> {
+    utils::parse_bitrate(rate).ok_or(format!("Invalid {} {}", name, rate))
+}
+
+fn parse_gop(gop_size: &str) -> Result<u32, String> {
+    match gop_size.parse::<u32>() {
+        Ok(gop) if gop > 0 => Ok(gop),
+        _ => Err(format!("Invalid gop_size {}", gop_size)),
+    }
+}
+
+// Runtime stats reported by the worker, from ffmpeg -progress
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub(crate) struct OutputStats {
+    pub(crate) frame: u64,
+    pub(crate) fps: f64,
+    // kbit/s
+    pub(crate) bitrate: f64,
+    pub(crate) total_size: u64,
+    pub(crate) out_time_us: i64,
+    pub(crate) dup_frames: u64,
+    pub(crate) drop_frames: u64,
+    pub(crate) speed: f64,
+    pub(crate) updated: u64,
+}
+
+// Detected on the worker, active while ended is not set
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Alarm {
+    pub(crate) kind: AlarmKind,
+    // Unix timestamps in milliseconds
+    pub(crate) started: u64,
+    pub(crate) ended: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub(crate) struct Output {
     pub(crate) id: Uuid,
     pub(crate) uri: String,
@@ -47,9 +1040,19 @@ pub(crate) struct Output {
     pub(crate) worker: Option<Uuid>,
     pub(crate) logs: Vec<String>,
     pub(crate) last_error: Option<String>,
+    // Playlist/manifest location reported by the worker for packaged outputs
+    pub(crate) playlist: Option<String>,
+    // Audio tracks found in the input by the worker
+    pub(crate) audio_tracks: Option<Vec<AudioTrack>>,
+    // Input the worker is reading, changes on failover
+    pub(crate) active_input: Option<String>,
+    // Alarm history of the worker, oldest first
+    pub(crate) alarms: Option<Vec<Alarm>>,
+    // Runtime only, not persisted in the state file
+    pub(crate) stats: Option<OutputStats>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Stream {
     pub(crate) id: Uuid,
     pub(crate) name: String,
@@ -59,6 +1062,19 @@ pub struct Stream {
     pub(crate) status: Status,
 }
 
+impl Stream {
+    // Copy for API responses, the state file keeps the secrets
+    pub(crate) fn redacted(&self) -> Stream {
+        let mut stream = self.clone();
+        stream.input = utils::redact_secrets(&stream.input);
+        for output in stream.output.iter_mut() {
+            output.uri = utils::redact_secrets(&output.uri);
+            output.options = output.options.as_ref().map(Options::redacted);
+        }
+        stream
+    }
+}
+
 //
 // Small util functions to update the state
 // Seems to be the meta so we don't lock the state too long in the big monitor functions
@@ -97,6 +1113,91 @@ pub(crate) async fn set_output_status(
                     LogLevel::Info,
                 ));
             }
+
+            if output.status == Status::Finished {
+                tokio::spawn(log(
+                    state.clone(),
+                    stream_id,
+                    output_id,
+                    "Output has finished".to_string(),
+                    LogLevel::Info,
+                ));
+            }
+        }
+    }
+}
+
+pub(crate) async fn set_output_playlist(
+    state: Arc<App>,
+    stream_id: Uuid,
+    output_id: Uuid,
+    playlist: Option<String>,
+) {
+    let mut streams_list = state.streams.lock().await;
+
+    if let Some(stream) = streams_list.iter_mut().find(|x| x.id == stream_id) {
+        if let Some(output) = stream.output.iter_mut().find(|x| x.id == output_id) {
+            output.playlist = playlist;
+        }
+    }
+}
+
+pub(crate) async fn set_output_audio_tracks(
+    state: Arc<App>,
+    stream_id: Uuid,
+    output_id: Uuid,
+    audio_tracks: Option<Vec<AudioTrack>>,
+) {
+    let mut streams_list = state.streams.lock().await;
+
+    if let Some(stream) = streams_list.iter_mut().find(|x| x.id == stream_id) {
+        if let Some(output) = stream.output.iter_mut().find(|x| x.id == output_id) {
+            output.audio_tracks = audio_tracks;
+        }
+    }
+}
+
+pub(crate) async fn set_output_active_input(
+    state: Arc<App>,
+    stream_id: Uuid,
+    output_id: Uuid,
+    active_input: Option<String>,
+) {
+    let mut streams_list = state.streams.lock().await;
+
+    if let Some(stream) = streams_list.iter_mut().find(|x| x.id == stream_id) {
+        if let Some(output) = stream.output.iter_mut().find(|x| x.id == output_id) {
+            output.active_input = active_input;
+        }
+    }
+}
+
+pub(crate) async fn set_output_alarms(
+    state: Arc<App>,
+    stream_id: Uuid,
+    output_id: Uuid,
+    alarms: Option<Vec<Alarm>>,
+) {
+    let mut streams_list = state.streams.lock().await;
+
+    if let Some(stream) = streams_list.iter_mut().find(|x| x.id == stream_id) {
+        if let Some(output) = stream.output.iter_mut().find(|x| x.id == output_id) {
+            output.alarms = alarms;
+        }
+    }
+}
+
+pub(crate) async fn set_output_stats(
+    state: Arc<App>,
+    stream_id: Uuid,
+    output_id: Uuid,
+    stats: Option<OutputStats>,
+) {
+    let mut streams_list = state.streams.lock().await;
+
+    if let Some(stream) = streams_list.iter_mut().find(|x| x.id == stream_id) {
+        if let Some(output) = stream.output.iter_mut().find(|x| x.id == output_id) {
+            output.stats = stats;
         }
     }
 }
@@ -106,7 +1207,8 @@ pub(crate) async fn check_all_outputs_running(state: Arc<App>, stream_id: Uuid)
 
     if let Some(stream) = streams_list.iter_mut().find(|x| x.id == stream_id) {
         for output in stream.output.iter() {
-            if output.status != Status::Running {
+            // One-shot outputs that are done are never restarted
+            if output.status != Status::Running && output.status != Status::Finished {
                 stream.status = Status::Creating;
                 return false;
             }
@@ -158,3 +1260,310 @@ pub(crate) async fn log(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const INPUT: &str = "udp://239.0.0.1:5000";
+    const OUTPUT: &str = "udp://239.0.0.2:5000";
+
+    fn options(value: serde_json::Value) -> Options {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn validate(value: serde_json::Value) -> Result<(), String> {
+        options(value).validate(INPUT, OUTPUT)
+    }
+
+    fn rate_control(value: serde_json::Value) -> RateControl {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn parses_bitrates() {
+        assert_eq!(utils::parse_bitrate("500k"), Some(500_000));
+        assert_eq!(utils::parse_bitrate("8M"), Some(8_000_000));
+        assert_eq!(utils::parse_bitrate(" 2000000 "), Some(2_000_000));
+        assert_eq!(utils::parse_bitrate("1.5G"), Some(1_500_000_000));
+
+        for rate in ["", "abc", "-1k", "inf", "NaN", "1e20", "2000G"] {
+            assert_eq!(utils::parse_bitrate(rate), None, "{rate}");
+        }
+    }
+
+    #[test]
+    fn validates_srt_uris() {
+        let caller = SrtOptions::default();
+        assert!(caller.validate("srt://10.0.0.1:9000").is_ok());
+        assert!(caller.validate("srt://:9000?mode=listener").is_ok());
+        assert!(caller.validate("srt://:9000").is_err());
+        assert!(caller.validate("srt://10.0.0.1").is_err());
+        assert!(caller.validate("srt://10.0.0.1:port").is_err());
+        assert!(caller.validate("udp://10.0.0.1:9000").is_err());
+
+        let listener = SrtOptions {
+            mode: Some(SrtMode::Listener),
+            ..Default::default()
+        };
+        assert!(listener.validate("srt://:9000").is_ok());
+
+        let passphrase = |passphrase: &str| SrtOptions {
+            passphrase: Some(passphrase.to_string()),
+            ..Default::default()
+        };
+        assert!(passphrase("0123456789")
+            .validate("srt://10.0.0.1:9000")
+            .is_ok());
+        assert!(passphrase("short").validate("srt://10.0.0.1:9000").is_err());
+        assert!(passphrase(&"x".repeat(80))
+            .validate("srt://10.0.0.1:9000")
+            .is_err());
+
+        let streamid = SrtOptions {
+            streamid: Some("x".repeat(513)),
+            ..Default::default()
+        };
+        assert!(streamid.validate("srt://10.0.0.1:9000").is_err());
+    }
+
+    #[test]
+    fn validates_bare_srt_uris() {
+        let output = |uri: &str| options(json!({})).validate(INPUT, uri);
+        assert!(output("srt://10.0.0.1:9000?passphrase=0123456789").is_ok());
+        assert!(output("srt://10.0.0.1:9000?passphrase=short").is_err());
+        assert!(output("srt://10.0.0.1:9000?latency=200&passphrase=short").is_err());
+        // Percent-encoded bytes count once
+        assert!(output("srt://10.0.0.1:9000?passphrase=%20%20%20%20%20%20%20%20%20").is_err());
+        assert!(output("srt://10.0.0.1").is_err());
+
+        assert!(options(json!({}))
+            .validate("srt://:9000?mode=listener", OUTPUT)
+            .is_ok());
+        assert!(options(json!({})).validate("srt://:9000", OUTPUT).is_err());
+
+        assert!(validate(json!({
+            "failover": { "backup_inputs": ["srt://10.0.0.3:9000?passphrase=short"] }
+        }))
+        .is_err());
+        assert!(validate(json!({
+            "renditions": [{ "uri": "srt://10.0.0.4:9000?passphrase=short" }]
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn validates_rate_control() {
+        let cbr = rate_control(json!({ "mode": "CBR", "buffer_size": "4M" }));
+        assert!(cbr.validate("4M", true).is_ok());
+        assert!(cbr.validate("inf", true).is_err());
+
+        let vbr = rate_control(json!({ "mode": "VBR", "max_bitrate": "6M" }));
+        assert!(vbr.validate("4M", true).is_ok());
+        assert!(vbr.validate("8M", true).is_err());
+
+        assert!(rate_control(json!({ "mode": "CRF", "quality": 63 }))
+            .validate("4M", true)
+            .is_ok());
+        assert!(rate_control(json!({ "mode": "CRF", "quality": 64 }))
+            .validate("4M", true)
+            .is_err());
+        assert!(rate_control(json!({ "mode": "VBR", "quality": 23 }))
+            .validate("4M", true)
+            .is_err());
+        assert!(
+            rate_control(json!({ "mode": "CBR", "buffer_size": "1e20" }))
+                .validate("4M", true)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn muxrate_needs_room_above_the_bitrate() {
+        let muxrate = |muxrate: &str| rate_control(json!({ "mode": "CBR", "muxrate": muxrate }));
+        assert!(muxrate("5M").validate("4M", true).is_ok());
+        assert!(muxrate("4M").validate("4M", true).is_err());
+        assert!(muxrate("3M").validate("4M", true).is_err());
+        assert!(muxrate("5M").validate("4M", false).is_err());
+
+        // Packaged outputs aren't plain mpegts
+        assert!(validate(json!({
+            "bitrate": "4M",
+            "packaging": { "format": "HLS" },
+            "rate_control": { "mode": "CBR", "muxrate": "5M" }
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn validates_mpegts_pids() {
+        let pids = |mpegts: serde_json::Value| validate(json!({ "mpegts": mpegts }));
+        assert!(pids(json!({
+            "pmt_pid": 0x10,
+            "video_pid": 0x100,
+            "audio_pids": [0x101, 0x1FFE]
+        }))
+        .is_ok());
+        assert!(pids(json!({ "pmt_pid": 0x0F })).is_err());
+        assert!(pids(json!({ "video_pid": 0x1FFF })).is_err());
+        assert!(pids(json!({ "audio_pids": [0x101, 0x2000] })).is_err());
+
+        assert!(pids(json!({ "video_pid": 0x100, "audio_pids": [0x100] })).is_err());
+        assert!(pids(json!({ "audio_pids": [0x101, 0x101] })).is_err());
+        assert!(pids(json!({ "pmt_pid": 0x100, "video_pid": 0x100 })).is_err());
+        // start_pid only numbers the other streams
+        assert!(pids(json!({ "start_pid": 0x100, "video_pid": 0x100 })).is_ok());
+    }
+
+    #[test]
+    fn validates_mpegts_service() {
+        let service = |mpegts: serde_json::Value| validate(json!({ "mpegts": mpegts }));
+        assert!(service(json!({ "service_name": "gasket", "service_id": 1 })).is_ok());
+        assert!(service(json!({ "service_id": 0 })).is_err());
+        assert!(service(json!({ "transport_stream_id": 0x10000 })).is_err());
+
+        assert!(validate(json!({
+            "output_format": "flv",
+            "mpegts": { "service_name": "gasket" }
+        }))
+        .is_err());
+        assert!(validate(json!({ "output_format": "flv", "scte35": true })).is_err());
+        assert!(validate(json!({ "scte35": true })).is_ok());
+    }
+
+    #[test]
+    fn validates_scaling() {
+        assert!(validate(json!({ "width": 1280, "height": 720 })).is_ok());
+        assert!(validate(json!({ "width": 1280 })).is_ok());
+        assert!(validate(json!({ "width": 1279, "height": 720 })).is_err());
+        assert!(validate(json!({ "width": 1280, "height": 719 })).is_err());
+        assert!(validate(json!({ "height": 0 })).is_err());
+        assert!(validate(json!({
+            "renditions": [{ "uri": "udp://239.0.0.3:5000", "width": 640, "height": 359 }]
+        }))
+        .is_err());
+
+        assert!(validate(json!({ "width": 1280, "scaling_mode": "Fill" })).is_err());
+        assert!(validate(json!({ "width": 1280, "height": 720, "scaling_mode": "Fill" })).is_ok());
+
+        assert!(validate(json!({
+            "width": 1280,
+            "height": 720,
+            "scaling_mode": "Fit",
+            "pad": "black"
+        }))
+        .is_ok());
+        assert!(validate(json!({ "width": 1280, "height": 720, "pad": "black" })).is_err());
+        assert!(validate(json!({ "width": 1280, "scaling_mode": "Fit", "pad": "black" })).is_err());
+    }
+
+    #[test]
+    fn validates_renditions() {
+        let rendition = |gop_size: &str| {
+            validate(json!({
+                "gop_size": "60",
+                "renditions": [{ "uri": "udp://239.0.0.3:5000", "gop_size": gop_size }]
+            }))
+        };
+        assert!(rendition("120").is_ok());
+        assert!(rendition("90").is_err());
+        assert!(rendition("0").is_err());
+        assert!(validate(json!({ "renditions": [{ "uri": "" }] })).is_err());
+    }
+
+    fn mpts() -> InputInfo {
+        let track = |program, codec: &str, language: &str| AudioTrack {
+            index: 0,
+            codec: Some(codec.to_string()),
+            language: Some(language.to_string()),
+            channels: Some(2),
+            channel_layout: None,
+            sample_rate: Some(48000),
+            program: Some(program),
+        };
+        let program = |program_num, service_name: &str| Program {
+            program_num,
+            pmt_pid: None,
+            pcr_pid: None,
+            service_name: Some(service_name.to_string()),
+            service_provider: None,
+        };
+        InputInfo {
+            programs: vec![program(1, "one"), program(2, "two")],
+            audio_tracks: vec![
+                AudioTrack {
+                    index: 0,
+                    ..track(1, "aac", "eng")
+                },
+                AudioTrack {
+                    index: 1,
+                    ..track(2, "aac", "fra")
+                },
+                AudioTrack {
+                    index: 2,
+                    ..track(2, "pcm_s16le", "deu")
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validates_the_program_against_the_input() {
+        let info = mpts();
+        let program = |program: serde_json::Value| {
+            options(json!({ "program": program })).validate_input(&info)
+        };
+        assert!(program(json!({ "number": 2 })).is_ok());
+        assert!(program(json!({ "service_name": "two" })).is_ok());
+        assert!(program(json!({ "number": 3 })).is_err());
+        assert!(program(json!({ "service_name": "three" })).is_err());
+
+        assert!(validate(json!({ "program": { "number": 0 } })).is_err());
+        assert!(validate(json!({ "program": { "number": 1, "service_name": "one" } })).is_err());
+        assert!(validate(json!({ "program": {} })).is_err());
+    }
+
+    #[test]
+    fn audio_tracks_count_within_the_program() {
+        let info = mpts();
+        let audio = |program: u32, audio: serde_json::Value| {
+            options(json!({ "program": { "number": program }, "audio": audio }))
+                .validate_input(&info)
+        };
+        assert!(audio(2, json!({ "tracks": [0, 1] })).is_ok());
+        assert!(audio(2, json!({ "tracks": [2] })).is_err());
+        assert!(audio(1, json!({ "tracks": [1] })).is_err());
+        assert!(audio(2, json!({ "languages": ["fra"] })).is_ok());
+        assert!(audio(1, json!({ "languages": ["fra"] })).is_err());
+
+        // Without a program the tracks of the whole input count
+        assert!(options(json!({ "audio": { "tracks": [2] } }))
+            .validate_input(&info)
+            .is_ok());
+    }
+
+    #[test]
+    fn copied_audio_has_to_fit_mpegts() {
+        let info = mpts();
+        let copy = |program: u32, tracks: serde_json::Value| {
+            options(json!({
+                "program": { "number": program },
+                "audio": { "codec": "Copy", "tracks": tracks }
+            }))
+            .validate_input(&info)
+        };
+        assert!(copy(2, json!([0])).is_ok());
+        assert!(copy(2, json!([1])).is_err());
+        assert!(copy(1, json!([0])).is_ok());
+
+        assert!(options(json!({
+            "output_format": "matroska",
+            "program": { "number": 2 },
+            "audio": { "codec": "Copy" }
+        }))
+        .validate_input(&info)
+        .is_ok());
+    }
+}
@@ -10,3 +10,56 @@ pub(crate) fn get_build_info() -> String {
 
     raw_ver.replace(long_sha, &short_sha)
 }
+
+// Shown instead of secrets in API responses, the worker masks them the same way
+pub(crate) const REDACTED: &str = "********";
+
+// Mask the value of every passphrase= query parameter, same as the worker
+pub(crate) fn redact_secrets(text: &str) -> String {
+    const KEY: &str = "passphrase=";
+
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(KEY) {
+        let (head, tail) = rest.split_at(start + KEY.len());
+        redacted.push_str(head);
+        redacted.push_str(REDACTED);
+        let end = tail
+            .find(|c: char| c == '&' || c == '"' || c == '\'' || c == '|' || c.is_whitespace())
+            .unwrap_or(tail.len());
+        rest = &tail[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+// Put back the secrets of a URI a client sent back masked, from the current URI it stands for
+pub(crate) fn restore_uri<'a>(uri: &mut String, current: impl IntoIterator<Item = &'a String>) {
+    if !uri.contains(REDACTED) {
+        return;
+    }
+    if let Some(current) = current.into_iter().find(|c| redact_secrets(c) == *uri) {
+        *uri = current.clone();
+    }
+}
+
+// Far above any stream, keeps arithmetic on rates from overflowing
+const MAX_BITRATE: f64 = 1e12;
+
+// Bits per second of an ffmpeg style rate like "500k", "8M" or "2000000"
+pub(crate) fn parse_bitrate(rate: &str) -> Option<u64> {
+    let rate = rate.trim();
+    let (number, multiplier) = match rate.chars().last()? {
+        'k' | 'K' => (&rate[..rate.len() - 1], 1_000.0),
+        'm' | 'M' => (&rate[..rate.len() - 1], 1_000_000.0),
+        'g' | 'G' => (&rate[..rate.len() - 1], 1_000_000_000.0),
+        _ => (rate, 1.0),
+    };
+    let number: f64 = number.parse().ok()?;
+    // Also rules out "inf" and "NaN", which would saturate to u64::MAX
+    let rate = number * multiplier;
+    if !(0.0..=MAX_BITRATE).contains(&rate) {
+        return None;
+    }
+    Some(rate as u64)
+}
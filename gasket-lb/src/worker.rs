@@ -1,9 +1,12 @@
 use crate::{
+    job::{self, Job},
     state::{self, Worker},
     stream::{self, Output, Stream},
 };
+use axum::http::StatusCode;
 use reqwest::Client;
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Debug, serde::Serialize)]
@@ -77,8 +80,11 @@ pub(crate) async fn stop_stream(worker: Worker, stream_id: Uuid, output_id: Uuid
         log::info!("Stopping stream {} on worker {}", stream_id, worker.host);
     }
 
-    // Start stream on best_worker
-    let url = format!("{}://{}/stream/{}", worker.protocol, worker.host, output_id);
+    // Wait for the worker to confirm the process is gone, so a restart doesn't race it for ports or devices
+    let url = format!(
+        "{}://{}/stream/{}?wait=true",
+        worker.protocol, worker.host, output_id
+    );
     let client = Client::new();
 
     let response = client
@@ -115,6 +121,276 @@ pub(crate) async fn stop_stream(worker: Worker, stream_id: Uuid, output_id: Uuid
     }
 }
 
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct ProbeRequest {
+    pub(crate) uri: String,
+    pub(crate) input_srt: Option<stream::SrtOptions>,
+    // Seconds
+    pub(crate) timeout: Option<u64>,
+}
+
+// Run ffprobe on the worker, errors keep the worker's status code
+pub(crate) async fn probe(
+    worker: &Worker,
+    request: &ProbeRequest,
+) -> Result<stream::InputInfo, (StatusCode, String)> {
+    let url = format!("{}://{}/probe", worker.protocol, worker.host);
+    // The worker caps the timeout at 60s, leave it time to answer
+    let timeout = Duration::from_secs(request.timeout.unwrap_or(10).min(60) + 5);
+    let client = Client::new();
+
+    let response = client
+        .post(&url)
+        .json(request)
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::BAD_GATEWAY,
+                format!("Could not reach worker {}: {}", worker.host, e),
+            )
+        })?;
+
+    let status = response.status().as_u16();
+    if !response.status().is_success() {
+        let text = response.text().await.unwrap_or_default();
+        return Err((
+            StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY),
+            text,
+        ));
+    }
+
+    response.json::<stream::InputInfo>().await.map_err(|e| {
+        (
+            StatusCode::BAD_GATEWAY,
+            format!("Invalid probe response from worker {}: {}", worker.host, e),
+        )
+    })
+}
+
+// Latest JPEG snapshot of an output running on the worker
+pub(crate) async fn get_thumbnail(
+    worker: &Worker,
+    output_id: Uuid,
+) -> Result<Vec<u8>, (StatusCode, String)> {
+    let url = format!(
+        "{}://{}/stream/{}/thumbnail",
+        worker.protocol, worker.host, output_id
+    );
+    let client = Client::new();
+
+    let response = client
+        .get(&url)
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::BAD_GATEWAY,
+                format!("Could not reach worker {}: {}", worker.host, e),
+            )
+        })?;
+
+    if !response.status().is_success() {
+        return Err((
+            StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY),
+            format!(
+                "No thumbnail for output {} on worker {}",
+                output_id, worker.host
+            ),
+        ));
+    }
+
+    response.bytes().await.map(|b| b.to_vec()).map_err(|e| {
+        (
+            StatusCode::BAD_GATEWAY,
+            format!(
+                "Could not read thumbnail from worker {}: {}",
+                worker.host, e
+            ),
+        )
+    })
+}
+
+#[derive(Debug, serde::Serialize)]
+struct StartJobData {
+    id: Uuid,
+    name: String,
+    input: String,
+    output: String,
+    codec: String,
+    options: Option<stream::Options>,
+    two_pass: Option<bool>,
+}
+
+pub(crate) async fn start_job(worker: Worker, job: Job, state: Arc<state::App>) {
+    log::info!("Starting job {} on worker {}", job.name, worker.host);
+    let url = format!("{}://{}/job", worker.protocol, worker.host);
+    let client = Client::new();
+
+    let start_job_data = StartJobData {
+        id: job.id,
+        name: job.name.clone(),
+        input: job.input.clone(),
+        output: job.output.clone(),
+        codec: job.codec.ffmpeg_name().to_string(),
+        options: job.options.clone(),
+        two_pass: job.two_pass,
+    };
+
+    let response = match client.post(&url).json(&start_job_data).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            log::error!(
+                "Job {} failed to start on worker {}: {}",
+                job.name,
+                worker.host,
+                e
+            );
+            return;
+        }
+    };
+
+    if response.status().is_success() {
+        log::info!("Job {} started on worker {}", job.name, worker.host);
+        job::set_status(
+            state.clone(),
+            job.id,
+            job::JobStatus::Running,
+            Some(worker.id),
+        )
+        .await;
+        job::log(
+            state,
+            job.id,
+            format!("Job started on worker {}", worker.id),
+        )
+        .await;
+    } else {
+        log::error!("Job {} failed to start on worker {}", job.name, worker.host);
+        log::error!("{:?}", response.text().await);
+    }
+}
+
+// A job as reported by GET /job on the worker
+#[derive(serde::Deserialize, Debug, Clone)]
+pub(crate) struct WorkerJob {
+    pub(crate) id: Uuid,
+    pub(crate) status: String,
+    pub(crate) pass: u8,
+    pub(crate) progress: Option<f64>,
+    pub(crate) exit_code: Option<i32>,
+    pub(crate) error: Option<String>,
+    pub(crate) elapsed: Option<u64>,
+}
+
+pub(crate) async fn get_jobs(worker: &Worker) -> Option<Vec<WorkerJob>> {
+    let url = format!("{}://{}/job", worker.protocol, worker.host);
+    let client = Client::new();
+
+    let response = client
+        .get(&url)
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+        .ok()?;
+    response.json::<Vec<WorkerJob>>().await.ok()
+}
+
+// Cancels the job if it's still running, the worker stops its process and forgets it
+pub(crate) async fn delete_job(worker: Worker, job_id: Uuid) {
+    let url = format!("{}://{}/job/{}", worker.protocol, worker.host, job_id);
+    let client = Client::new();
+
+    match client.delete(&url).send().await {
+        Ok(response) if response.status().is_success() => {
+            log::info!("Job {} removed from worker {}", job_id, worker.host);
+        }
+        _ => log::error!(
+            "Job {} failed to be removed from worker {}",
+            job_id,
+            worker.host
+        ),
+    }
+}
+
+// Recorded segment of an output on a worker, see GET /stream/:uuid/output/:oid/recording
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub(crate) struct RecordingFile {
+    pub(crate) name: String,
+    pub(crate) size: u64,
+    pub(crate) modified: u64,
+    // Set by the load balancer, the worker holding the file
+    pub(crate) worker: Option<Uuid>,
+}
+
+// Recordings of an output on the worker, empty if it has none or can't be reached
+pub(crate) async fn get_recordings(worker: &Worker, output_id: Uuid) -> Vec<RecordingFile> {
+    let url = format!(
+        "{}://{}/stream/{}/recording",
+        worker.protocol, worker.host, output_id
+    );
+    let client = Client::new();
+
+    let response = match client
+        .get(&url)
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+    {
+        Ok(response) if response.status().is_success() => response,
+        _ => return Vec::new(),
+    };
+
+    let mut files = response
+        .json::<Vec<RecordingFile>>()
+        .await
+        .unwrap_or_default();
+    for file in files.iter_mut() {
+        file.worker = Some(worker.id);
+    }
+    files
+}
+
+// Download of a recorded segment, the body is streamed through by the caller
+pub(crate) async fn get_recording(
+    worker: &Worker,
+    output_id: Uuid,
+    name: &str,
+) -> Result<reqwest::Response, (StatusCode, String)> {
+    let url = format!(
+        "{}://{}/stream/{}/recording/{}",
+        worker.protocol, worker.host, output_id, name
+    );
+    let client = Client::new();
+
+    let response = client.get(&url).send().await.map_err(|e| {
+        (
+            StatusCode::BAD_GATEWAY,
+            format!("Could not reach worker {}: {}", worker.host, e),
+        )
+    })?;
+
+    if !response.status().is_success() {
+        return Err((
+            StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY),
+            format!("No recording {} on worker {}", name, worker.host),
+        ));
+    }
+
+    Ok(response)
+}
+
+// Any worker can probe, use the least busy one
+pub(crate) async fn probe_worker(state: Arc<state::App>) -> Option<Worker> {
+    get_all(state)
+        .await
+        .into_iter()
+        .filter(|w| w.status == state::WorkerStatus::Up)
+        .min_by_key(|w| w.stats.utilization)
+}
+
 pub(crate) async fn get_all(state: Arc<state::App>) -> Vec<Worker> {
     let workers = state.workers.lock().await;
     return workers.clone();
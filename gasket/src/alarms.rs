@@ -0,0 +1,126 @@
+use crate::state::{AlarmKind, AlarmOptions};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Alarms kept per stream, oldest are dropped first
+const HISTORY: usize = 50;
+
+// A detected condition, active until it ends
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Alarm {
+    pub(crate) kind: AlarmKind,
+    // Unix timestamps in milliseconds, started is when the condition began rather than when it was raised
+    pub(crate) started: u64,
+    pub(crate) ended: Option<u64>,
+}
+
+// Alarm state of a stream, fed with the ffmpeg log lines of its detection output
+pub(crate) struct Alarms {
+    options: AlarmOptions,
+    history: VecDeque<Alarm>,
+    // blackdetect marks the first dark frame of every run, the alarm waits for the duration here
+    black_since: Option<(u64, Instant)>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl Alarms {
+    pub(crate) fn new(options: AlarmOptions) -> Alarms {
+        Alarms {
+            options,
+            history: VecDeque::with_capacity(HISTORY),
+            black_since: None,
+        }
+    }
+
+    fn duration(&self, kind: AlarmKind) -> u64 {
+        self.options
+            .detector(kind)
+            .map(|(duration, _)| duration as u64)
+            .unwrap_or_default()
+    }
+
+    fn active(&self, kind: AlarmKind) -> bool {
+        self.history
+            .iter()
+            .any(|alarm| alarm.kind == kind && alarm.ended.is_none())
+    }
+
+    fn raise(&mut self, kind: AlarmKind, started: u64) {
+        if self.active(kind) {
+            return;
+        }
+
+        self.history.push_back(Alarm {
+            kind,
+            started,
+            ended: None,
+        });
+        while self.history.len() > HISTORY {
+            self.history.pop_front();
+        }
+    }
+
+    fn end(&mut self, kind: AlarmKind) {
+        let ended = now();
+        for alarm in self.history.iter_mut() {
+            if alarm.kind == kind && alarm.ended.is_none() {
+                alarm.ended = Some(ended);
+            }
+        }
+    }
+
+    // One stderr line of ffmpeg, anything but detector events is ignored. Black comes from the
+    // metadata filters after blackdetect, freeze and silence are only reported once they lasted
+    // their duration
+    pub(crate) fn push(&mut self, line: &str) {
+        if line.contains("lavfi.black_start=") {
+            if self.black_since.is_none() && !self.active(AlarmKind::Black) {
+                self.black_since = Some((now(), Instant::now()));
+            }
+        } else if line.contains("lavfi.black_end=") {
+            self.black_since = None;
+            self.end(AlarmKind::Black);
+        } else if line.contains("freezedetect") && line.contains("freeze_start:") {
+            let duration = self.duration(AlarmKind::Freeze);
+            self.raise(AlarmKind::Freeze, now().saturating_sub(duration * 1000));
+        } else if line.contains("freezedetect") && line.contains("freeze_end:") {
+            self.end(AlarmKind::Freeze);
+        } else if line.contains("silencedetect") && line.contains("silence_start:") {
+            let duration = self.duration(AlarmKind::Silence);
+            self.raise(AlarmKind::Silence, now().saturating_sub(duration * 1000));
+        } else if line.contains("silencedetect") && line.contains("silence_end:") {
+            self.end(AlarmKind::Silence);
+        }
+    }
+
+    // Raise the black alarm once the dark frames lasted long enough, called by the monitor
+    pub(crate) fn tick(&mut self) {
+        let duration = Duration::from_secs(self.duration(AlarmKind::Black));
+        if let Some((started, since)) = self.black_since {
+            if since.elapsed() >= duration {
+                self.black_since = None;
+                self.raise(AlarmKind::Black, started);
+            }
+        }
+    }
+
+    // The process exited, nothing is detected until the next one runs
+    pub(crate) fn end_all(&mut self) {
+        self.black_since = None;
+        for kind in [AlarmKind::Black, AlarmKind::Freeze, AlarmKind::Silence] {
+            self.end(kind);
+        }
+    }
+
+    // Oldest first, active alarms have no end
+    pub(crate) fn history(&self) -> Vec<Alarm> {
+        self.history.iter().cloned().collect()
+    }
+}
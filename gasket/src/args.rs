@@ -37,7 +37,39 @@ pub(crate) struct Args {
     #[arg(short, long, env, default_value = "ffmpeg")]
     pub(crate) ffmpeg: String,
 
+    // ffprobe path, used to find the input codec
+    #[arg(long, env, default_value = "ffprobe")]
+    pub(crate) ffprobe: String,
+
     // Force CPU encoding
     #[arg(long, env)]
     pub(crate) cpu_only: bool,
+
+    // Milliseconds to wait after SIGTERM before sending SIGKILL to a stopping stream
+    #[arg(long, env, default_value = "5000")]
+    pub(crate) stop_grace_period: u64,
+
+    // DRM render node used by the QSV and VAAPI encoders
+    #[arg(long, env, default_value = "/dev/dri/renderD128")]
+    pub(crate) render_device: String,
+
+    // Directory for the stream thumbnails
+    #[arg(long, env, default_value = "/tmp/gasket/thumbnails")]
+    pub(crate) thumbnail_dir: String,
+
+    // Seconds between thumbnails of a stream, off (0) unless set since every stream pays for the extra output
+    #[arg(long, env, default_value = "0")]
+    pub(crate) thumbnail_interval: u64,
+
+    // Directory for stream recordings, one subdirectory per stream
+    #[arg(long, env, default_value = "/tmp/gasket/recordings")]
+    pub(crate) recording_dir: String,
+
+    // Seconds a Finished stream stays in GET /stream before it is removed, so the LB sees its final state
+    #[arg(long, env, default_value = "300")]
+    pub(crate) finished_ttl: u64,
+
+    // Number of log lines kept per stream
+    #[arg(long, env, default_value = "1000")]
+    pub(crate) log_lines: usize,
 }
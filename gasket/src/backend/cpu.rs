@@ -0,0 +1,91 @@
+use super::{aligned_keyframe_args, quality_args, EncoderBackend};
+use crate::state::{Codec, Encoder, Preset, RateControl, RateControlMode, Tuning};
+
+// Software encoding, used with --cpu-only
+pub(crate) struct Cpu;
+
+impl EncoderBackend for Cpu {
+    fn encoder(&self) -> Option<Encoder> {
+        None
+    }
+
+    fn encoder_name(&self, codec: Codec) -> Option<&'static str> {
+        match codec {
+            Codec::H264 => Some("libx264"),
+            Codec::H265 => Some("libx265"),
+            Codec::AV1 => Some("libaom-av1"),
+        }
+    }
+
+    // Assumed to be built into ffmpeg
+    fn supported_codecs(&self, _ffmpeg_encoders: &str) -> Vec<Codec> {
+        Codec::all()
+    }
+
+    // x264/x265 scene cuts would add keyframes in some rungs only
+    fn aligned_keyframe_args(&self, gop_size: &str) -> Vec<String> {
+        let mut args = aligned_keyframe_args(gop_size);
+        args.extend(["-sc_threshold".to_string(), "0".to_string()]);
+        args
+    }
+
+    fn quality_args(
+        &self,
+        codec: Codec,
+        bitrate: &str,
+        rate_control: Option<&RateControl>,
+        tuning: Option<&Tuning>,
+    ) -> Vec<String> {
+        let mut args = quality_args(bitrate, rate_control, tuning);
+        let mode = rate_control.map(|rc| rc.mode);
+
+        match codec {
+            Codec::H264 | Codec::H265 => {
+                // Filler data and HRD signalling for strict CBR
+                if mode == Some(RateControlMode::CBR) {
+                    let (option, params) = match codec {
+                        Codec::H264 => ("-x264-params", "nal-hrd=cbr:force-cfr=1"),
+                        _ => ("-x265-params", "strict-cbr=1"),
+                    };
+                    args.extend([option.to_string(), params.to_string()]);
+                }
+
+                if let Some(preset) = tuning.and_then(|t| t.preset) {
+                    let name = match preset {
+                        Preset::UltraFast => "ultrafast",
+                        Preset::Fast => "fast",
+                        Preset::Medium => "medium",
+                        Preset::Slow => "slow",
+                        Preset::VerySlow => "veryslow",
+                    };
+                    args.extend(["-preset".to_string(), name.to_string()]);
+                }
+                if tuning.and_then(|t| t.low_latency) == Some(true) {
+                    args.extend(["-tune".to_string(), "zerolatency".to_string()]);
+                }
+            }
+            Codec::AV1 => {
+                // Constant quality needs the target bitrate unset
+                if mode == Some(RateControlMode::CRF) {
+                    args.extend(["-b:v".to_string(), "0".to_string()]);
+                }
+
+                if let Some(preset) = tuning.and_then(|t| t.preset) {
+                    let cpu_used = match preset {
+                        Preset::UltraFast => 8,
+                        Preset::Fast => 6,
+                        Preset::Medium => 4,
+                        Preset::Slow => 2,
+                        Preset::VerySlow => 0,
+                    };
+                    args.extend(["-cpu-used".to_string(), cpu_used.to_string()]);
+                }
+                if tuning.and_then(|t| t.low_latency) == Some(true) {
+                    args.extend(["-usage".to_string(), "realtime".to_string()]);
+                }
+            }
+        }
+
+        args
+    }
+}
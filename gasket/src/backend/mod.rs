@@ -0,0 +1,401 @@
+use crate::args;
+use crate::state::{
+    Codec, Deinterlace, DeviceStats, Encoder, EncoderStats, RateControl, RateControlMode,
+    ScalingMode, Tonemap, Tuning,
+};
+use crate::utils::{self, get_ffmpeg_path};
+use clap::Parser;
+use std::env;
+use std::process::Command;
+use std::sync::Arc;
+
+mod cpu;
+mod netint;
+mod nvenc;
+mod qsv;
+mod u30;
+mod vaapi;
+mod videotoolbox;
+
+pub(crate) use cpu::Cpu;
+pub(crate) use netint::Netint;
+pub(crate) use nvenc::Nvenc;
+pub(crate) use qsv::Qsv;
+pub(crate) use u30::U30;
+pub(crate) use vaapi::Vaapi;
+pub(crate) use videotoolbox::VideoToolbox;
+
+// An encoder accelerator (or the CPU), everything transcode and the monitor need to know about it
+pub(crate) trait EncoderBackend: Send + Sync {
+    // Encoder reported in /capabilities, None for CPU encoding
+    fn encoder(&self) -> Option<Encoder>;
+
+    // ffmpeg encoder name for the codec, None if the backend can't encode it
+    fn encoder_name(&self, codec: Codec) -> Option<&'static str>;
+
+    // Codecs with an encoder present in the `ffmpeg -encoders` output
+    fn supported_codecs(&self, ffmpeg_encoders: &str) -> Vec<Codec> {
+        Codec::all()
+            .into_iter()
+            .filter(|codec| match self.encoder_name(*codec) {
+                Some(name) => has_encoder(ffmpeg_encoders, name),
+                None => false,
+            })
+            .collect()
+    }
+
+    // Usable if ffmpeg was built with at least one of its encoders
+    fn detect(&self, ffmpeg_encoders: &str) -> bool {
+        !self.supported_codecs(ffmpeg_encoders).is_empty()
+    }
+
+    // Arguments placed before -i, e.g. device selection
+    fn input_args(&self, _device: Option<usize>) -> Vec<String> {
+        Vec::new()
+    }
+
+    // Input codecs (ffprobe names) the device can decode, the rest is decoded on the CPU
+    fn hw_decode_codecs(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    // Arguments placed before -i to decode one of hw_decode_codecs on the device,
+    // without keep_frames the decoded frames have to be usable by CPU filters
+    fn decoder_args(
+        &self,
+        _input_codec: &str,
+        _device: Option<usize>,
+        _keep_frames: bool,
+    ) -> Vec<String> {
+        Vec::new()
+    }
+
+    // Environment of the ffmpeg process
+    fn env(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    // Arguments placed after the input, before any output
+    fn global_output_args(&self, _frames_on_device: bool) -> Vec<String> {
+        Vec::new()
+    }
+
+    // Encoder arguments of one output
+    fn encoder_args(&self, codec: Codec, _device: Option<usize>) -> Vec<String> {
+        match self.encoder_name(codec) {
+            Some(name) => vec!["-c:v".to_string(), name.to_string()],
+            None => Vec::new(),
+        }
+    }
+
+    // Bitrate, rate control and tuning arguments of one output
+    fn quality_args(
+        &self,
+        _codec: Codec,
+        bitrate: &str,
+        rate_control: Option<&RateControl>,
+        tuning: Option<&Tuning>,
+    ) -> Vec<String> {
+        quality_args(bitrate, rate_control, tuning)
+    }
+
+    // Keyframes of a ladder rung, forced on the same frames in every rung. Encoders that add
+    // keyframes of their own or don't make forced ones IDR need their flags here
+    fn aligned_keyframe_args(&self, gop_size: &str) -> Vec<String> {
+        aligned_keyframe_args(gop_size)
+    }
+
+    // drawtext working on device frames, None if frames have to leave the device for it
+    fn drawtext_filter(&self) -> Option<&'static str> {
+        None
+    }
+
+    // Scaler working on device frames, None if frames have to leave the device for it.
+    // The mode is Stretch when a dimension is -2
+    fn scale_filter(&self, _width: &str, _height: &str, _mode: ScalingMode) -> Option<String> {
+        None
+    }
+
+    // Deinterlacer working on device frames, None if frames have to leave the device for it
+    fn deinterlace_filter(&self, _deinterlace: &Deinterlace) -> Option<String> {
+        None
+    }
+
+    // HDR to SDR tone mapping on device frames, None if frames have to leave the device for it
+    fn tonemap_filter(&self, _tonemap: &Tonemap) -> Option<String> {
+        None
+    }
+
+    // Prepended to CPU filters when hardware decoded frames don't leave the device by themselves
+    fn download_filter(&self) -> Option<&'static str> {
+        None
+    }
+
+    // Moves frames kept on the device to system memory, for outputs next to the encoder
+    fn hwdownload_filter(&self) -> Option<&'static str> {
+        None
+    }
+
+    // Appended to every CPU filter chain to move frames onto the device
+    fn upload_filter(&self) -> Option<&'static str> {
+        None
+    }
+
+    // Current load of the accelerator, None if it can't be queried
+    fn utilization(&self) -> Option<Result<EncoderStats, String>> {
+        None
+    }
+
+    // Device the next stream should run on, pinned holds the number of our streams per device
+    fn pick_device(&self, _stats: &EncoderStats, _pinned: &[u32]) -> Option<usize> {
+        None
+    }
+}
+
+// Backends available on this OS, in order of preference
+fn registry() -> Vec<Arc<dyn EncoderBackend>> {
+    let device = args::Args::parse().render_device;
+
+    match env::consts::OS {
+        "macos" => vec![Arc::new(VideoToolbox)],
+        "linux" => vec![
+            Arc::new(Nvenc),
+            Arc::new(U30),
+            Arc::new(Netint),
+            Arc::new(Qsv {
+                device: device.clone(),
+            }),
+            Arc::new(Vaapi { device }),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+// Backends picked at startup, every codec is encoded by the first backend in registry order that has it
+#[derive(Clone, Default)]
+pub(crate) struct Backends {
+    // First detected backend, its devices are monitored and reported in /capabilities
+    pub(crate) primary: Option<Arc<dyn EncoderBackend>>,
+    codecs: Vec<(Codec, Arc<dyn EncoderBackend>)>,
+}
+
+impl Backends {
+    // Every codec on the first backend that has an encoder for it, e.g. nvenc H264 next to NETINT AV1
+    pub(crate) fn select(
+        backends: Vec<Arc<dyn EncoderBackend>>,
+        ffmpeg_encoders: &str,
+    ) -> Backends {
+        let mut selected = Backends::default();
+        for backend in backends {
+            if !backend.detect(ffmpeg_encoders) {
+                continue;
+            }
+            for codec in backend.supported_codecs(ffmpeg_encoders) {
+                if selected.backend(codec).is_none() {
+                    selected.codecs.push((codec, backend.clone()));
+                }
+            }
+            selected.primary.get_or_insert(backend);
+        }
+        selected
+    }
+
+    pub(crate) fn codecs(&self) -> Vec<Codec> {
+        self.codecs.iter().map(|(codec, _)| *codec).collect()
+    }
+
+    pub(crate) fn backend(&self, codec: Codec) -> Option<Arc<dyn EncoderBackend>> {
+        self.codecs
+            .iter()
+            .find(|(c, _)| *c == codec)
+            .map(|(_, backend)| backend.clone())
+    }
+
+    // Backend of a stream or job codec given as its ffmpeg name, the primary one if it isn't known
+    pub(crate) fn for_codec(&self, codec: &str) -> Option<Arc<dyn EncoderBackend>> {
+        match Codec::from_ffmpeg_name(codec) {
+            Some(codec) => self.backend(codec).or(self.primary.clone()),
+            None => self.primary.clone(),
+        }
+    }
+
+    // Only the primary backend's devices have stats to pick a device from
+    pub(crate) fn is_primary(&self, backend: &Arc<dyn EncoderBackend>) -> bool {
+        self.primary
+            .as_ref()
+            .is_some_and(|primary| Arc::ptr_eq(primary, backend))
+    }
+}
+
+// Pick the backends to encode with, CPU only with --cpu-only
+pub(crate) fn detect() -> Backends {
+    let args = args::Args::parse();
+
+    if args.cpu_only {
+        let cpu: Arc<dyn EncoderBackend> = Arc::new(Cpu);
+        return Backends::select(vec![cpu], "");
+    }
+
+    let mut command = Command::new(get_ffmpeg_path());
+    command.arg("-hide_banner").arg("-encoders");
+    let ffmpeg_encoders = utils::run_command_capture(command);
+
+    log::info!("Using OS {}", env::consts::OS);
+
+    let backends = Backends::select(registry(), &ffmpeg_encoders);
+    for (codec, backend) in &backends.codecs {
+        log::info!(
+            "Using encoder {:?} for codec {:?}",
+            backend.encoder(),
+            codec
+        );
+    }
+    if backends.primary.is_none() {
+        log::error!("No hardware encoder found");
+    }
+    backends
+}
+
+// Lines of `ffmpeg -encoders` look like " V....D h264_nvenc           NVIDIA NVENC H.264 encoder"
+pub(crate) fn has_encoder(ffmpeg_encoders: &str, name: &str) -> bool {
+    ffmpeg_encoders
+        .lines()
+        .any(|line| line.split_whitespace().nth(1) == Some(name))
+}
+
+// Index of the device with the lowest utilization, streams we already pinned break ties
+// since utilization lags behind streams that just started
+pub(crate) fn least_loaded(stats: &EncoderStats, pinned: &[u32]) -> Option<usize> {
+    stats
+        .devices
+        .iter()
+        .enumerate()
+        .min_by_key(|&(i, device)| (device.utilization, pinned.get(i).copied().unwrap_or(0)))
+        .map(|(i, _)| i)
+}
+
+// Average over devices, 100 when there are none so nothing gets scheduled on it
+pub(crate) fn average(devices: &[DeviceStats]) -> u32 {
+    if devices.is_empty() {
+        return 100;
+    }
+    devices.iter().map(|d| d.utilization).sum::<u32>() / devices.len() as u32
+}
+
+// Generic ffmpeg rate control and tuning, understood by most encoders
+pub(crate) fn quality_args(
+    bitrate: &str,
+    rate_control: Option<&RateControl>,
+    tuning: Option<&Tuning>,
+) -> Vec<String> {
+    let mut args = rate_args(bitrate, rate_control);
+    args.extend(tuning_args(tuning));
+    args
+}
+
+pub(crate) fn rate_args(bitrate: &str, rate_control: Option<&RateControl>) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+
+    match rate_control {
+        None => args.extend(["-b:v".to_string(), bitrate.to_string()]),
+        Some(rc) => match rc.mode {
+            RateControlMode::CBR => args.extend([
+                "-b:v".to_string(),
+                bitrate.to_string(),
+                "-minrate".to_string(),
+                bitrate.to_string(),
+                "-maxrate".to_string(),
+                bitrate.to_string(),
+                "-bufsize".to_string(),
+                buffer_size(rc, bitrate),
+            ]),
+            RateControlMode::VBR => {
+                args.extend(["-b:v".to_string(), bitrate.to_string()]);
+                if let Some(max_bitrate) = &rc.max_bitrate {
+                    args.extend([
+                        "-maxrate".to_string(),
+                        max_bitrate.clone(),
+                        "-bufsize".to_string(),
+                        buffer_size(rc, max_bitrate),
+                    ]);
+                }
+            }
+            RateControlMode::CRF => {
+                args.extend(["-crf".to_string(), quality(rc).to_string()]);
+            }
+        },
+    }
+
+    args
+}
+
+// Every gop_size-th frame is a keyframe, counted in frames so it doesn't depend on the framerate
+pub(crate) fn aligned_keyframe_args(gop_size: &str) -> Vec<String> {
+    vec![
+        "-force_key_frames".to_string(),
+        format!("expr:not(mod(n,{gop_size}))"),
+    ]
+}
+
+// Profile, level and B-frames, presets are named differently by every encoder
+pub(crate) fn tuning_args(tuning: Option<&Tuning>) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+
+    if let Some(tuning) = tuning {
+        if let Some(profile) = &tuning.profile {
+            args.extend(["-profile:v".to_string(), profile.clone()]);
+        }
+        if let Some(level) = &tuning.level {
+            args.extend(["-level".to_string(), level.clone()]);
+        }
+        if let Some(b_frames) = tuning.b_frames {
+            args.extend(["-bf".to_string(), b_frames.to_string()]);
+        }
+    }
+
+    args
+}
+
+// VBV buffer, one second at the peak rate unless set
+pub(crate) fn buffer_size(rate_control: &RateControl, peak: &str) -> String {
+    rate_control.buffer_size.clone().unwrap_or(peak.to_string())
+}
+
+// CRF/CQ value, 23 is the x264 default
+pub(crate) fn quality(rate_control: &RateControl) -> u32 {
+    rate_control.quality.unwrap_or(23)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODERS: &str = "Encoders:
+ V..... = Video
+ ------
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC / MPEG-4 part 10 (codec h264)
+ V....D h264_nvenc           NVIDIA NVENC H.264 encoder (codec h264)
+ V....D av1_ni_quadra_enc    NETINT Quadra AV1 encoder v4.9.5 (codec av1)
+ A....D aac                  AAC (Advanced Audio Coding)
+";
+
+    #[test]
+    fn selects_a_backend_per_codec() {
+        let nvenc: Arc<dyn EncoderBackend> = Arc::new(Nvenc);
+        let netint: Arc<dyn EncoderBackend> = Arc::new(Netint);
+        let backends =
+            Backends::select(vec![nvenc.clone(), Arc::new(U30), netint.clone()], ENCODERS);
+
+        assert_eq!(backends.codecs(), vec![Codec::H264, Codec::AV1]);
+        assert!(backends.is_primary(&nvenc));
+        assert!(Arc::ptr_eq(&backends.backend(Codec::H264).unwrap(), &nvenc));
+        assert!(Arc::ptr_eq(&backends.backend(Codec::AV1).unwrap(), &netint));
+        assert!(backends.backend(Codec::H265).is_none());
+        assert!(Arc::ptr_eq(&backends.for_codec("av1").unwrap(), &netint));
+        assert!(Arc::ptr_eq(&backends.for_codec("hevc").unwrap(), &nvenc));
+        assert!(Arc::ptr_eq(
+            &backends.for_codec("mpeg2video").unwrap(),
+            &nvenc
+        ));
+    }
+}
@@ -0,0 +1,286 @@
+use super::{average, buffer_size, least_loaded, quality, EncoderBackend};
+use crate::state::{
+    Codec, DeviceStats, Encoder, EncoderStats, RateControl, RateControlMode, ScalingMode, Tuning,
+};
+use crate::utils;
+use serde::Deserialize;
+use std::process::Command;
+
+// NETINT Quadra (T2A)
+pub(crate) struct Netint;
+
+impl EncoderBackend for Netint {
+    fn encoder(&self) -> Option<Encoder> {
+        Some(Encoder::NIT2A)
+    }
+
+    fn encoder_name(&self, codec: Codec) -> Option<&'static str> {
+        match codec {
+            Codec::H264 => Some("h264_ni_quadra_enc"),
+            Codec::H265 => Some("h265_ni_quadra_enc"),
+            Codec::AV1 => Some("av1_ni_quadra_enc"),
+        }
+    }
+
+    fn hw_decode_codecs(&self) -> &'static [&'static str] {
+        &["h264", "hevc", "vp9"]
+    }
+
+    // Decode on the card, out=hw keeps the frames there
+    fn decoder_args(
+        &self,
+        input_codec: &str,
+        device: Option<usize>,
+        keep_frames: bool,
+    ) -> Vec<String> {
+        let decoder = match input_codec {
+            "hevc" => "h265_ni_quadra_dec",
+            "vp9" => "vp9_ni_quadra_dec",
+            _ => "h264_ni_quadra_dec",
+        };
+
+        let mut args = vec!["-c:v".to_string(), decoder.to_string()];
+        if let Some(device) = device {
+            args.extend(["-dec".to_string(), device.to_string()]);
+        }
+        if keep_frames {
+            args.extend(["-xcoder-params".to_string(), "out=hw".to_string()]);
+        }
+        args
+    }
+
+    // Encode on the card that decoded the frames
+    fn encoder_args(&self, codec: Codec, device: Option<usize>) -> Vec<String> {
+        let mut args = match self.encoder_name(codec) {
+            Some(name) => vec!["-c:v".to_string(), name.to_string()],
+            None => return Vec::new(),
+        };
+        if let Some(device) = device {
+            args.extend(["-enc".to_string(), device.to_string()]);
+        }
+        args
+    }
+
+    // Rate control and tuning all go in one -xcoder-params list, the VBV buffer is given in milliseconds
+    fn quality_args(
+        &self,
+        codec: Codec,
+        bitrate: &str,
+        rate_control: Option<&RateControl>,
+        tuning: Option<&Tuning>,
+    ) -> Vec<String> {
+        let mut args = vec!["-b:v".to_string(), bitrate.to_string()];
+        // Forced keyframes, e.g. of ladder rungs, are ignored without it
+        let mut params: Vec<String> = vec!["forceFrameType=1".to_string()];
+
+        if let Some(rc) = rate_control {
+            let peak = match rc.mode {
+                RateControlMode::CBR => {
+                    params.extend(["RcEnable=1".to_string(), "cbr=1".to_string()]);
+                    Some(bitrate)
+                }
+                RateControlMode::VBR => {
+                    params.push("RcEnable=1".to_string());
+                    if let Some(max_bitrate) =
+                        rc.max_bitrate.as_deref().and_then(utils::parse_bitrate)
+                    {
+                        params.push(format!("vbvMaxRate={max_bitrate}"));
+                    }
+                    rc.max_bitrate.as_deref()
+                }
+                RateControlMode::CRF => {
+                    params.push(format!("crf={}", quality(rc)));
+                    None
+                }
+            };
+
+            if let Some(peak) = peak {
+                let vbv = utils::parse_bitrate(&buffer_size(rc, peak));
+                if let (Some(vbv), Some(peak)) = (vbv, utils::parse_bitrate(peak)) {
+                    if let Some(ms) = vbv.checked_mul(1000).and_then(|vbv| vbv.checked_div(peak)) {
+                        params.push(format!("vbvBufferSize={ms}"));
+                    }
+                }
+            }
+        }
+
+        if let Some(tuning) = tuning {
+            let low_latency = tuning.low_latency == Some(true);
+            if low_latency {
+                params.push("lowDelay=1".to_string());
+            }
+            // Only a B-frame free GOP preset can be picked, other counts keep the default GOP
+            if tuning.b_frames == Some(0) {
+                params.push(format!("gopPresetIdx={}", if low_latency { 9 } else { 2 }));
+            }
+            if let Some(profile) = tuning
+                .profile
+                .as_deref()
+                .and_then(|p| profile_index(codec, p))
+            {
+                params.push(format!("profile={profile}"));
+            }
+            if let Some(level) = &tuning.level {
+                params.push(format!("level={level}"));
+            }
+        }
+
+        args.extend(["-xcoder-params".to_string(), params.join(":")]);
+        args
+    }
+
+    // An automatically inserted software scaler can't take frames on the card
+    fn global_output_args(&self, frames_on_device: bool) -> Vec<String> {
+        if frames_on_device {
+            vec!["-noautoscale".to_string()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn drawtext_filter(&self) -> Option<&'static str> {
+        Some("ni_quadra_drawtext")
+    }
+
+    fn hwdownload_filter(&self) -> Option<&'static str> {
+        Some("hwdownload,format=yuv420p")
+    }
+
+    fn scale_filter(&self, width: &str, height: &str, mode: ScalingMode) -> Option<String> {
+        match mode {
+            ScalingMode::Stretch => Some(format!("ni_quadra_scale={width}:{height}")),
+            _ => None,
+        }
+    }
+
+    fn utilization(&self) -> Option<Result<EncoderStats, String>> {
+        let mut command = Command::new("ni_rsrc_mon");
+        command
+            .arg("-S")
+            .arg("-l")
+            .arg("none")
+            .arg("-o")
+            .arg("json1");
+
+        let output = utils::run_command_capture(command);
+        Some(parse_ni_rsrc_mon(&output))
+    }
+
+    fn pick_device(&self, stats: &EncoderStats, pinned: &[u32]) -> Option<usize> {
+        least_loaded(stats, pinned)
+    }
+}
+
+// Quadra profile numbers
+fn profile_index(codec: Codec, profile: &str) -> Option<u32> {
+    match (codec, profile) {
+        (Codec::H264, "baseline") => Some(1),
+        (Codec::H264, "main") => Some(2),
+        (Codec::H264, "extended") => Some(3),
+        (Codec::H264, "high") => Some(4),
+        (Codec::H264, "high10") => Some(5),
+        (Codec::H265, "main") => Some(1),
+        (Codec::H265, "main10") => Some(2),
+        (Codec::AV1, "main") => Some(1),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct NetintDevStats {
+    #[serde(rename = "LOAD")]
+    load: u32,
+    #[serde(rename = "INST")]
+    instances: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct NetintStats {
+    #[serde(rename = "encoders")]
+    encoders: Vec<NetintDevStats>,
+}
+
+// Parse `ni_rsrc_mon -S -l none -o json1`
+pub(crate) fn parse_ni_rsrc_mon(output: &str) -> Result<EncoderStats, String> {
+    let log: NetintStats = serde_json::from_str(output)
+        .map_err(|e| format!("Failed to parse JSON from netint ni_rsrc_mon: {e}"))?;
+
+    let devices: Vec<DeviceStats> = log
+        .encoders
+        .iter()
+        .map(|encoder| DeviceStats {
+            utilization: encoder.load,
+            sessions: encoder.instances,
+        })
+        .collect();
+
+    Ok(EncoderStats {
+        utilization: average(&devices),
+        devices,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_encoder_load_per_card() {
+        let output = include_str!("../../tests/fixtures/ni_rsrc_mon.json");
+        let stats = parse_ni_rsrc_mon(output).unwrap();
+        assert_eq!(
+            stats.devices,
+            vec![
+                DeviceStats {
+                    utilization: 47,
+                    sessions: Some(4),
+                },
+                DeviceStats {
+                    utilization: 3,
+                    sessions: Some(1),
+                },
+            ]
+        );
+        assert_eq!(stats.utilization, 25);
+    }
+
+    #[test]
+    fn sessions_are_optional() {
+        let stats = parse_ni_rsrc_mon(r#"{"encoders":[{"LOAD":12}]}"#).unwrap();
+        assert_eq!(
+            stats.devices,
+            vec![DeviceStats {
+                utilization: 12,
+                sessions: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_output() {
+        assert!(parse_ni_rsrc_mon("").is_err());
+        assert!(parse_ni_rsrc_mon("ERROR: Failed to get available xcoders").is_err());
+        assert!(parse_ni_rsrc_mon(r#"{"decoders":[]}"#).is_err());
+        assert!(parse_ni_rsrc_mon(r#"{"encoders":[{"LOAD":"high"}]}"#).is_err());
+    }
+
+    #[test]
+    fn vbv_buffer_is_given_in_milliseconds() {
+        let params = |buffer_size: &str| {
+            let rc = RateControl {
+                mode: RateControlMode::CBR,
+                max_bitrate: None,
+                buffer_size: Some(buffer_size.to_string()),
+                quality: None,
+                muxrate: None,
+            };
+            Netint
+                .quality_args(Codec::H264, "4M", Some(&rc), None)
+                .join(" ")
+        };
+        assert!(params("2M").contains("vbvBufferSize=500"));
+        // Out of range rates are left to the encoder default instead of overflowing
+        assert!(!params("1e20").contains("vbvBufferSize"));
+        assert!(!params("inf").contains("vbvBufferSize"));
+    }
+}
@@ -0,0 +1,301 @@
+use super::{
+    aligned_keyframe_args, average, buffer_size, least_loaded, quality, tuning_args, EncoderBackend,
+};
+use crate::state::{
+    Codec, Deinterlace, DeviceStats, Encoder, EncoderStats, Preset, RateControl, RateControlMode,
+    ScalingMode, Tuning,
+};
+use crate::utils;
+use atoi::atoi;
+use serde::Deserialize;
+use std::process::Command;
+
+// NVIDIA GPUs
+pub(crate) struct Nvenc;
+
+impl EncoderBackend for Nvenc {
+    fn encoder(&self) -> Option<Encoder> {
+        Some(Encoder::NVENC)
+    }
+
+    fn encoder_name(&self, codec: Codec) -> Option<&'static str> {
+        match codec {
+            Codec::H264 => Some("h264_nvenc"),
+            Codec::H265 => Some("hevc_nvenc"),
+            Codec::AV1 => Some("av1_nvenc"),
+        }
+    }
+
+    // CUDA numbers GPUs fastest first by default, nvidia-smi lists them by PCI bus
+    fn env(&self) -> Vec<(String, String)> {
+        vec![("CUDA_DEVICE_ORDER".to_string(), "PCI_BUS_ID".to_string())]
+    }
+
+    fn hw_decode_codecs(&self) -> &'static [&'static str] {
+        &["h264", "hevc", "av1", "vp9", "vp8", "mpeg2video", "mjpeg"]
+    }
+
+    // NVDEC through the cuda hwaccel, frames are copied back to system memory unless kept
+    fn decoder_args(
+        &self,
+        _input_codec: &str,
+        device: Option<usize>,
+        keep_frames: bool,
+    ) -> Vec<String> {
+        let mut args = vec!["-hwaccel".to_string(), "cuda".to_string()];
+        if let Some(device) = device {
+            args.extend(["-hwaccel_device".to_string(), device.to_string()]);
+        }
+        if keep_frames {
+            args.extend(["-hwaccel_output_format".to_string(), "cuda".to_string()]);
+        }
+        args
+    }
+
+    fn hwdownload_filter(&self) -> Option<&'static str> {
+        Some("hwdownload,format=nv12")
+    }
+
+    // No crop on the GPU, so Fill has to go through the CPU
+    fn scale_filter(&self, width: &str, height: &str, mode: ScalingMode) -> Option<String> {
+        match mode {
+            ScalingMode::Stretch => Some(format!("scale_cuda={width}:{height}")),
+            ScalingMode::Fit => Some(format!(
+                "scale_cuda={width}:{height}:force_original_aspect_ratio=decrease:force_divisible_by=2"
+            )),
+            ScalingMode::Fill => None,
+        }
+    }
+
+    fn deinterlace_filter(&self, deinterlace: &Deinterlace) -> Option<String> {
+        Some(format!(
+            "{}_cuda={}",
+            deinterlace.ffmpeg_name(),
+            deinterlace.ffmpeg_args()
+        ))
+    }
+
+    fn encoder_args(&self, codec: Codec, device: Option<usize>) -> Vec<String> {
+        let mut args = match self.encoder_name(codec) {
+            Some(name) => vec!["-c:v".to_string(), name.to_string()],
+            None => return Vec::new(),
+        };
+        if let Some(device) = device {
+            args.extend(["-gpu".to_string(), device.to_string()]);
+        }
+        args
+    }
+
+    // Forced keyframes are plain I-frames and scene cuts add more unless disabled
+    fn aligned_keyframe_args(&self, gop_size: &str) -> Vec<String> {
+        let mut args = aligned_keyframe_args(gop_size);
+        args.extend([
+            "-forced-idr".to_string(),
+            "1".to_string(),
+            "-no-scenecut".to_string(),
+            "1".to_string(),
+        ]);
+        args
+    }
+
+    fn quality_args(
+        &self,
+        _codec: Codec,
+        bitrate: &str,
+        rate_control: Option<&RateControl>,
+        tuning: Option<&Tuning>,
+    ) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+
+        match rate_control {
+            None => args.extend(["-b:v".to_string(), bitrate.to_string()]),
+            Some(rc) => match rc.mode {
+                RateControlMode::CBR => args.extend([
+                    "-rc".to_string(),
+                    "cbr".to_string(),
+                    "-b:v".to_string(),
+                    bitrate.to_string(),
+                    "-maxrate".to_string(),
+                    bitrate.to_string(),
+                    "-bufsize".to_string(),
+                    buffer_size(rc, bitrate),
+                ]),
+                RateControlMode::VBR => {
+                    args.extend([
+                        "-rc".to_string(),
+                        "vbr".to_string(),
+                        "-b:v".to_string(),
+                        bitrate.to_string(),
+                    ]);
+                    if let Some(max_bitrate) = &rc.max_bitrate {
+                        args.extend([
+                            "-maxrate".to_string(),
+                            max_bitrate.clone(),
+                            "-bufsize".to_string(),
+                            buffer_size(rc, max_bitrate),
+                        ]);
+                    }
+                }
+                // Constant quality is CQ on top of VBR without a target bitrate
+                RateControlMode::CRF => args.extend([
+                    "-rc".to_string(),
+                    "vbr".to_string(),
+                    "-cq".to_string(),
+                    quality(rc).to_string(),
+                    "-b:v".to_string(),
+                    "0".to_string(),
+                ]),
+            },
+        }
+
+        if let Some(tuning) = tuning {
+            if let Some(preset) = tuning.preset {
+                let name = match preset {
+                    Preset::UltraFast => "p1",
+                    Preset::Fast => "p3",
+                    Preset::Medium => "p4",
+                    Preset::Slow => "p6",
+                    Preset::VerySlow => "p7",
+                };
+                args.extend(["-preset".to_string(), name.to_string()]);
+            }
+            if tuning.low_latency == Some(true) {
+                args.extend([
+                    "-tune".to_string(),
+                    "ll".to_string(),
+                    "-zerolatency".to_string(),
+                    "1".to_string(),
+                ]);
+            }
+        }
+        args.extend(tuning_args(tuning));
+
+        args
+    }
+
+    fn utilization(&self) -> Option<Result<EncoderStats, String>> {
+        let mut command = Command::new("nvidia-smi");
+        command.arg("-q").arg("-x");
+
+        let output = utils::run_command_capture(command);
+        Some(parse_nvidia_smi(&output))
+    }
+
+    fn pick_device(&self, stats: &EncoderStats, pinned: &[u32]) -> Option<usize> {
+        least_loaded(stats, pinned)
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct NvidiaSmiLog {
+    #[serde(rename = "gpu", default)]
+    gpus: Vec<Gpu>,
+}
+#[derive(Debug, Deserialize, PartialEq)]
+struct Gpu {
+    #[serde(rename = "utilization")]
+    utilization: Utilization,
+    #[serde(rename = "encoder_stats")]
+    encoder_stats: NvencStats,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Utilization {
+    #[serde(rename = "encoder_util")]
+    encoder_util: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct NvencStats {
+    #[serde(rename = "session_count")]
+    session_count: String,
+    #[serde(rename = "average_fps")]
+    average_fps: String,
+    #[serde(rename = "average_latency")]
+    average_latency: String,
+}
+
+// Parse `nvidia-smi -q -x`, one device per <gpu> in PCI bus order
+pub(crate) fn parse_nvidia_smi(output: &str) -> Result<EncoderStats, String> {
+    let log: NvidiaSmiLog = serde_xml_rs::from_str(output).map_err(|e| e.to_string())?;
+
+    let mut devices = Vec::new();
+    for gpu in log.gpus {
+        let utilization = atoi(gpu.utilization.encoder_util.as_bytes())
+            .ok_or("Failed to parse encoder_util".to_string())?;
+        let sessions = atoi(gpu.encoder_stats.session_count.as_bytes())
+            .ok_or("Failed to parse session_count".to_string())?;
+
+        devices.push(DeviceStats {
+            utilization,
+            sessions: Some(sessions),
+        });
+    }
+
+    if devices.is_empty() {
+        return Err("No gpu in nvidia-smi output".to_string());
+    }
+
+    Ok(EncoderStats {
+        utilization: average(&devices),
+        devices,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_gpu() {
+        let output = include_str!("../../tests/fixtures/nvidia-smi-1gpu.xml");
+        let stats = parse_nvidia_smi(output).unwrap();
+        assert_eq!(
+            stats.devices,
+            vec![DeviceStats {
+                utilization: 37,
+                sessions: Some(3),
+            }]
+        );
+        assert_eq!(stats.utilization, 37);
+    }
+
+    #[test]
+    fn parses_every_gpu_in_order() {
+        let output = include_str!("../../tests/fixtures/nvidia-smi-2gpu.xml");
+        let stats = parse_nvidia_smi(output).unwrap();
+        assert_eq!(
+            stats.devices,
+            vec![
+                DeviceStats {
+                    utilization: 64,
+                    sessions: Some(5),
+                },
+                DeviceStats {
+                    utilization: 0,
+                    sessions: Some(0),
+                },
+            ]
+        );
+        assert_eq!(stats.utilization, 32);
+    }
+
+    #[test]
+    fn rejects_malformed_output() {
+        assert!(parse_nvidia_smi("").is_err());
+        assert!(parse_nvidia_smi(
+            "NVIDIA-SMI has failed because it couldn't communicate with the NVIDIA driver."
+        )
+        .is_err());
+        assert!(parse_nvidia_smi(
+            "<nvidia_smi_log><attached_gpus>0</attached_gpus></nvidia_smi_log>"
+        )
+        .is_err());
+
+        let output = include_str!("../../tests/fixtures/nvidia-smi-1gpu.xml").replace(
+            "<encoder_util>37 %</encoder_util>",
+            "<encoder_util>N/A</encoder_util>",
+        );
+        assert!(parse_nvidia_smi(&output).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use super::{aligned_keyframe_args, quality, rate_args, tuning_args, EncoderBackend};
+use crate::state::{Codec, Encoder, Preset, RateControl, RateControlMode, Tuning};
+use std::path::Path;
+
+// Intel Quick Sync Video, derived from a VA-API device on the render node
+pub(crate) struct Qsv {
+    pub(crate) device: String,
+}
+
+impl EncoderBackend for Qsv {
+    fn encoder(&self) -> Option<Encoder> {
+        Some(Encoder::QSV)
+    }
+
+    fn encoder_name(&self, codec: Codec) -> Option<&'static str> {
+        match codec {
+            Codec::H264 => Some("h264_qsv"),
+            Codec::H265 => Some("hevc_qsv"),
+            Codec::AV1 => Some("av1_qsv"),
+        }
+    }
+
+    // Distro ffmpeg builds ship the encoders, so also require the render node
+    fn detect(&self, ffmpeg_encoders: &str) -> bool {
+        !self.supported_codecs(ffmpeg_encoders).is_empty() && Path::new(&self.device).exists()
+    }
+
+    fn input_args(&self, _device: Option<usize>) -> Vec<String> {
+        vec![
+            "-init_hw_device".to_string(),
+            format!("vaapi=va:{}", self.device),
+            "-init_hw_device".to_string(),
+            "qsv=qs@va".to_string(),
+            "-filter_hw_device".to_string(),
+            "qs".to_string(),
+        ]
+    }
+
+    fn hw_decode_codecs(&self) -> &'static [&'static str] {
+        &["h264", "hevc", "av1", "vp9", "mpeg2video", "mjpeg"]
+    }
+
+    // Decoded on the device set up in input_args, frames are copied back to system memory unless kept
+    fn decoder_args(
+        &self,
+        _input_codec: &str,
+        _device: Option<usize>,
+        keep_frames: bool,
+    ) -> Vec<String> {
+        let mut args = vec![
+            "-hwaccel".to_string(),
+            "qsv".to_string(),
+            "-hwaccel_device".to_string(),
+            "qs".to_string(),
+        ];
+        if keep_frames {
+            args.extend(["-hwaccel_output_format".to_string(), "qsv".to_string()]);
+        }
+        args
+    }
+
+    // Forced keyframes are plain I-frames unless forced_idr is set
+    fn aligned_keyframe_args(&self, gop_size: &str) -> Vec<String> {
+        let mut args = aligned_keyframe_args(gop_size);
+        args.extend(["-forced_idr".to_string(), "1".to_string()]);
+        args
+    }
+
+    // CBR/VBR follow from maxrate, constant quality is ICQ
+    fn quality_args(
+        &self,
+        _codec: Codec,
+        bitrate: &str,
+        rate_control: Option<&RateControl>,
+        tuning: Option<&Tuning>,
+    ) -> Vec<String> {
+        let mut args = match rate_control.filter(|rc| rc.mode == RateControlMode::CRF) {
+            Some(rc) => vec!["-global_quality".to_string(), quality(rc).to_string()],
+            None => rate_args(bitrate, rate_control),
+        };
+        args.extend(tuning_args(tuning));
+
+        if let Some(preset) = tuning.and_then(|t| t.preset) {
+            let name = match preset {
+                Preset::UltraFast => "veryfast",
+                Preset::Fast => "fast",
+                Preset::Medium => "medium",
+                Preset::Slow => "slow",
+                Preset::VerySlow => "veryslow",
+            };
+            args.extend(["-preset".to_string(), name.to_string()]);
+        }
+        if tuning.and_then(|t| t.low_latency) == Some(true) {
+            args.extend(["-async_depth".to_string(), "1".to_string()]);
+        }
+
+        args
+    }
+
+    fn hwdownload_filter(&self) -> Option<&'static str> {
+        Some("hwdownload,format=nv12")
+    }
+
+    // The encoder keeps a few surfaces of its own on top of the filter pool
+    fn upload_filter(&self) -> Option<&'static str> {
+        Some("format=nv12,hwupload=extra_hw_frames=64")
+    }
+}
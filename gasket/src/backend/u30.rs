@@ -0,0 +1,261 @@
+use super::{average, buffer_size, least_loaded, quality, tuning_args, EncoderBackend};
+use crate::state::{
+    Codec, DeviceStats, Encoder, EncoderStats, RateControl, RateControlMode, ScalingMode, Tuning,
+};
+use crate::utils;
+use atoi::atoi;
+use serde::Deserialize;
+use serde_json::Value;
+use std::process::Command;
+
+// AMD/Xilinx Alveo U30
+pub(crate) struct U30;
+
+impl EncoderBackend for U30 {
+    fn encoder(&self) -> Option<Encoder> {
+        Some(Encoder::U30)
+    }
+
+    fn encoder_name(&self, codec: Codec) -> Option<&'static str> {
+        match codec {
+            Codec::H264 => Some("mpsoc_vcu_h264"),
+            Codec::H265 => Some("mpsoc_vcu_hevc"),
+            Codec::AV1 => None,
+        }
+    }
+
+    // ffmpeg -xlnx_hwdev 1 -c:v mpsoc_vcu_h264 -stream_loop -1 -i test_loop.mp4 -f mp4 -c:v mpsoc_vcu_hevc -y /dev/null
+    fn input_args(&self, device: Option<usize>) -> Vec<String> {
+        match device {
+            Some(device) => vec!["-xlnx_hwdev".to_string(), device.to_string()],
+            None => Vec::new(),
+        }
+    }
+
+    fn hw_decode_codecs(&self) -> &'static [&'static str] {
+        &["h264", "hevc"]
+    }
+
+    // The VCU decoders share their names with the encoders
+    fn decoder_args(
+        &self,
+        input_codec: &str,
+        _device: Option<usize>,
+        _keep_frames: bool,
+    ) -> Vec<String> {
+        let decoder = match input_codec {
+            "hevc" => "mpsoc_vcu_hevc",
+            _ => "mpsoc_vcu_h264",
+        };
+        vec!["-c:v".to_string(), decoder.to_string()]
+    }
+
+    // The ABR scaler needs both dimensions and doesn't know about aspect ratios
+    fn scale_filter(&self, width: &str, height: &str, mode: ScalingMode) -> Option<String> {
+        if width == "-2" || height == "-2" || mode != ScalingMode::Stretch {
+            return None;
+        }
+        Some(format!(
+            "multiscale_xma=outputs=1:out_1_width={width}:out_1_height={height}:out_1_rate=full"
+        ))
+    }
+
+    // Decoded frames live in device buffers until converted
+    fn download_filter(&self) -> Option<&'static str> {
+        Some("xvbm_convert")
+    }
+
+    fn hwdownload_filter(&self) -> Option<&'static str> {
+        Some("xvbm_convert")
+    }
+
+    // The VCU has its own rate control options, the CPB is given in seconds. It has no presets
+    fn quality_args(
+        &self,
+        _codec: Codec,
+        bitrate: &str,
+        rate_control: Option<&RateControl>,
+        tuning: Option<&Tuning>,
+    ) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+
+        match rate_control {
+            None => args.extend(["-b:v".to_string(), bitrate.to_string()]),
+            Some(rc) => {
+                let (control_rate, peak) = match rc.mode {
+                    RateControlMode::CBR => ("cbr", Some(bitrate)),
+                    RateControlMode::VBR => ("vbr", rc.max_bitrate.as_deref()),
+                    RateControlMode::CRF => ("const-qp", None),
+                };
+                args.extend(["-control-rate".to_string(), control_rate.to_string()]);
+
+                if rc.mode == RateControlMode::CRF {
+                    args.extend(["-slice-qp".to_string(), quality(rc).to_string()]);
+                } else {
+                    args.extend(["-b:v".to_string(), bitrate.to_string()]);
+                }
+
+                if let Some(peak) = peak {
+                    args.extend(["-max-bitrate".to_string(), peak.to_string()]);
+
+                    let cpb = utils::parse_bitrate(&buffer_size(rc, peak));
+                    if let (Some(cpb), Some(peak)) = (cpb, utils::parse_bitrate(peak)) {
+                        if peak > 0 {
+                            args.extend([
+                                "-cpb-size".to_string(),
+                                format!("{:.2}", cpb as f64 / peak as f64),
+                            ]);
+                        }
+                    }
+                }
+            }
+        }
+
+        args.extend(tuning_args(tuning));
+        if tuning.and_then(|t| t.low_latency) == Some(true) {
+            args.extend(["-lookahead_depth".to_string(), "0".to_string()]);
+        }
+
+        args
+    }
+
+    fn utilization(&self) -> Option<Result<EncoderStats, String>> {
+        Some(xrmadm_stats())
+    }
+
+    fn pick_device(&self, stats: &EncoderStats, pinned: &[u32]) -> Option<usize> {
+        least_loaded(stats, pinned)
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct XrmListDevicesResponseData {
+    #[serde(rename = "deviceNumber")]
+    device_number: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct XrmAdmListDevicesResponse {
+    #[serde(rename = "data")]
+    data: XrmListDevicesResponseData,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct XradmListDevices {
+    #[serde(rename = "response")]
+    response: XrmAdmListDevicesResponse,
+}
+
+fn xrmadm_stats() -> Result<EncoderStats, String> {
+    let mut command = Command::new("xrmadm");
+    command.arg("/opt/xilinx/xrm/test/list_cmd.json");
+
+    let output = utils::run_command_capture(command);
+    let num_devices = parse_device_count(&output)?;
+
+    let mut devices = Vec::new();
+
+    std::fs::create_dir_all("./gasket_xrmadm").map_err(|e| e.to_string())?;
+    for dev_id in 0..num_devices {
+        // For some reason xrmadm wants a file path and not a pipe or inline request
+        let path = format!("./gasket_xrmadm/list_device{dev_id}.json");
+
+        std::fs::write(
+            &path,
+            format!(
+                "{{\"request\": {{\"name\": \"list\",\"requestId\": 1,\"device\": {dev_id}}}}}"
+            ),
+        )
+        .map_err(|e| e.to_string())?;
+
+        let mut command = Command::new("xrmadm");
+        command.arg(&path);
+        let output = utils::run_command_capture(command);
+
+        devices.push(DeviceStats {
+            utilization: parse_device_load(&output, dev_id)?,
+            sessions: None,
+        });
+    }
+
+    Ok(EncoderStats {
+        utilization: average(&devices),
+        devices,
+    })
+}
+
+// Parse the xrmadm list_cmd response
+pub(crate) fn parse_device_count(output: &str) -> Result<usize, String> {
+    let list_response: XradmListDevices = serde_json::from_str(output)
+        .map_err(|e| format!("Error deserializing xrmadm list devices output: {e}"))?;
+    atoi::<usize>(list_response.response.data.device_number.as_bytes())
+        .ok_or("Failed to parse deviceNumber".to_string())
+}
+
+// Parse the xrmadm list response of one device into an encoder usage percentage
+pub(crate) fn parse_device_load(output: &str, dev_id: usize) -> Result<u32, String> {
+    let parsed: Value = serde_json::from_str(output)
+        .map_err(|e| format!("Error deserializing xrmadm list device output: {e}"))?;
+
+    let device = parsed["response"]["data"][format!("device_{dev_id}")]
+        .as_object()
+        .ok_or(format!("Missing device_{dev_id} in xrmadm output"))?;
+
+    let mut usage = 0;
+    let mut total = 0;
+
+    for cu_id in 0..70 {
+        if let Some(cu) = device.get(&format!("cu_{}", cu_id)) {
+            let kernel_alias = cu["kernelAlias  "].as_str().unwrap_or_default();
+            // "ENCODER_MPSOC" | "DECODER_MPSOC" => {
+            // Bottleneck is probably encoder not decoder
+            if kernel_alias == "ENCODER_MPSOC" {
+                let load = cu["usedLoad     "].to_string();
+                let parts: Vec<&str> = load.split(" of ").collect();
+                if parts.len() != 2 {
+                    return Err(format!("Unexpected usedLoad {load}"));
+                }
+                usage += atoi::<u32>(utils::to_numbers_only(parts[0].to_string()).as_bytes())
+                    .ok_or("Failed to parse usedLoad usage".to_string())?;
+                total += atoi::<u32>(utils::to_numbers_only(parts[1].to_string()).as_bytes())
+                    .ok_or("Failed to parse usedLoad total".to_string())?;
+            }
+        }
+    }
+
+    if total == 0 {
+        return Err(format!("No encoder load reported for device {dev_id}"));
+    }
+    Ok((usage * 100) / total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_device_count() {
+        let output = include_str!("../../tests/fixtures/xrmadm-list-cmd.json");
+        assert_eq!(parse_device_count(output), Ok(2));
+    }
+
+    #[test]
+    fn parses_encoder_load() {
+        // Only the ENCODER_MPSOC units count, 500000 of 2000000
+        let output = include_str!("../../tests/fixtures/xrmadm-list-device.json");
+        assert_eq!(parse_device_load(output, 1), Ok(25));
+    }
+
+    #[test]
+    fn rejects_malformed_output() {
+        assert!(parse_device_count("").is_err());
+        assert!(parse_device_count(r#"{"response":{"status":"failed"}}"#).is_err());
+        assert!(parse_device_count(r#"{"response":{"data":{"deviceNumber":"two"}}}"#).is_err());
+
+        let output = include_str!("../../tests/fixtures/xrmadm-list-device.json");
+        assert!(parse_device_load("", 1).is_err());
+        assert!(parse_device_load(output, 0).is_err());
+        assert!(parse_device_load(&output.replace("500000 of 1000000", "half"), 1).is_err());
+        assert!(parse_device_load(&output.replace("ENCODER_MPSOC", "LOOKAHEAD_MPSOC"), 1).is_err());
+    }
+}
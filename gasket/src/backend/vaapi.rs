@@ -0,0 +1,98 @@
+use super::{quality, rate_args, tuning_args, EncoderBackend};
+use crate::state::{Codec, Encoder, RateControl, RateControlMode, Tuning};
+use std::path::Path;
+
+// VA-API on a DRM render node, frames filtered on the CPU are uploaded before encoding
+pub(crate) struct Vaapi {
+    pub(crate) device: String,
+}
+
+impl EncoderBackend for Vaapi {
+    fn encoder(&self) -> Option<Encoder> {
+        Some(Encoder::VAAPI)
+    }
+
+    fn encoder_name(&self, codec: Codec) -> Option<&'static str> {
+        match codec {
+            Codec::H264 => Some("h264_vaapi"),
+            Codec::H265 => Some("hevc_vaapi"),
+            Codec::AV1 => Some("av1_vaapi"),
+        }
+    }
+
+    // Distro ffmpeg builds ship the encoders, so also require the render node
+    fn detect(&self, ffmpeg_encoders: &str) -> bool {
+        !self.supported_codecs(ffmpeg_encoders).is_empty() && Path::new(&self.device).exists()
+    }
+
+    fn input_args(&self, _device: Option<usize>) -> Vec<String> {
+        vec![
+            "-init_hw_device".to_string(),
+            format!("vaapi=va:{}", self.device),
+            "-filter_hw_device".to_string(),
+            "va".to_string(),
+        ]
+    }
+
+    fn hw_decode_codecs(&self) -> &'static [&'static str] {
+        &["h264", "hevc", "av1", "vp8", "vp9", "mpeg2video", "mjpeg"]
+    }
+
+    // Decoded on the device set up in input_args, frames are copied back to system memory unless kept
+    fn decoder_args(
+        &self,
+        _input_codec: &str,
+        _device: Option<usize>,
+        keep_frames: bool,
+    ) -> Vec<String> {
+        let mut args = vec![
+            "-hwaccel".to_string(),
+            "vaapi".to_string(),
+            "-hwaccel_device".to_string(),
+            "va".to_string(),
+        ];
+        if keep_frames {
+            args.extend(["-hwaccel_output_format".to_string(), "vaapi".to_string()]);
+        }
+        args
+    }
+
+    // The rate control mode has to be named, constant quality is CQP. There are no presets
+    fn quality_args(
+        &self,
+        _codec: Codec,
+        bitrate: &str,
+        rate_control: Option<&RateControl>,
+        tuning: Option<&Tuning>,
+    ) -> Vec<String> {
+        let mut args = match rate_control {
+            None => rate_args(bitrate, None),
+            Some(rc) if rc.mode == RateControlMode::CRF => vec![
+                "-rc_mode".to_string(),
+                "CQP".to_string(),
+                "-qp".to_string(),
+                quality(rc).to_string(),
+            ],
+            Some(rc) => {
+                let mode = if rc.mode == RateControlMode::CBR {
+                    "CBR"
+                } else {
+                    "VBR"
+                };
+                let mut args = vec!["-rc_mode".to_string(), mode.to_string()];
+                args.extend(rate_args(bitrate, rate_control));
+                args
+            }
+        };
+        args.extend(tuning_args(tuning));
+        args
+    }
+
+    fn hwdownload_filter(&self) -> Option<&'static str> {
+        Some("hwdownload,format=nv12")
+    }
+
+    fn upload_filter(&self) -> Option<&'static str> {
+        Some("format=nv12,hwupload")
+    }
+}
@@ -0,0 +1,83 @@
+use super::{quality, quality_args, tuning_args, EncoderBackend};
+use crate::state::{Codec, Encoder, RateControl, RateControlMode, Tuning};
+
+// Apple VideoToolbox, no utilization available
+pub(crate) struct VideoToolbox;
+
+impl EncoderBackend for VideoToolbox {
+    fn encoder(&self) -> Option<Encoder> {
+        Some(Encoder::VideoToolbox)
+    }
+
+    fn encoder_name(&self, codec: Codec) -> Option<&'static str> {
+        match codec {
+            Codec::H264 => Some("h264_videotoolbox"),
+            Codec::H265 => Some("hevc_videotoolbox"),
+            Codec::AV1 => None,
+        }
+    }
+
+    // No -crf, constant quality is -q:v from 1 to 100 with higher being better (Apple silicon only)
+    fn quality_args(
+        &self,
+        _codec: Codec,
+        bitrate: &str,
+        rate_control: Option<&RateControl>,
+        tuning: Option<&Tuning>,
+    ) -> Vec<String> {
+        match rate_control {
+            Some(rc) if rc.mode == RateControlMode::CRF => {
+                let mut args = vec!["-q:v".to_string(), constant_quality(rc).to_string()];
+                args.extend(tuning_args(tuning));
+                args
+            }
+            _ => quality_args(bitrate, rate_control, tuning),
+        }
+    }
+}
+
+// CRF 0 to 51 spread over q:v 100 to 1
+fn constant_quality(rate_control: &RateControl) -> u32 {
+    100 - quality(rate_control).min(51) * 99 / 51
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_control(mode: RateControlMode, quality: Option<u32>) -> RateControl {
+        RateControl {
+            mode,
+            max_bitrate: None,
+            buffer_size: None,
+            quality,
+            muxrate: None,
+        }
+    }
+
+    #[test]
+    fn maps_crf_to_constant_quality() {
+        let crf = |quality| {
+            VideoToolbox.quality_args(
+                Codec::H264,
+                "4M",
+                Some(&rate_control(RateControlMode::CRF, quality)),
+                None,
+            )
+        };
+        assert_eq!(crf(Some(0)), vec!["-q:v", "100"]);
+        assert_eq!(crf(None), vec!["-q:v", "56"]);
+        assert_eq!(crf(Some(63)), vec!["-q:v", "1"]);
+    }
+
+    #[test]
+    fn keeps_the_bitrate_of_other_modes() {
+        let args = VideoToolbox.quality_args(
+            Codec::H265,
+            "4M",
+            Some(&rate_control(RateControlMode::VBR, None)),
+            None,
+        );
+        assert_eq!(args, vec!["-b:v", "4M"]);
+    }
+}
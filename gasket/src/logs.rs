@@ -0,0 +1,74 @@
+use crate::state::StreamLogLevel;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct LogEntry {
+    // Increasing per stream, use as `since` to page through the buffer
+    pub(crate) seq: u64,
+    // Unix timestamp in milliseconds
+    pub(crate) timestamp: u64,
+    pub(crate) level: StreamLogLevel,
+    pub(crate) message: String,
+}
+
+// Bounded per-stream log, oldest lines are dropped first
+pub(crate) struct LogBuffer {
+    entries: VecDeque<LogEntry>,
+    capacity: usize,
+    next_seq: u64,
+}
+
+impl LogBuffer {
+    pub(crate) fn new(capacity: usize) -> LogBuffer {
+        LogBuffer {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            next_seq: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, level: StreamLogLevel, message: String) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+
+        self.entries.push_back(LogEntry {
+            seq: self.next_seq,
+            timestamp,
+            level,
+            message,
+        });
+        self.next_seq += 1;
+
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    // Most recent entry of a level, e.g. the last thing ffmpeg printed before failing
+    pub(crate) fn last(&self, level: StreamLogLevel) -> Option<&LogEntry> {
+        self.entries.iter().rev().find(|entry| entry.level == level)
+    }
+
+    // Entries after `since` (oldest first, up to limit), or the last `limit` entries without it
+    pub(crate) fn get(&self, since: Option<u64>, limit: usize) -> Vec<LogEntry> {
+        match since {
+            Some(since) => self
+                .entries
+                .iter()
+                .filter(|entry| entry.seq > since)
+                .take(limit)
+                .cloned()
+                .collect(),
+            None => self
+                .entries
+                .iter()
+                .skip(self.entries.len().saturating_sub(limit))
+                .cloned()
+                .collect(),
+        }
+    }
+}
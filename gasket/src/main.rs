@@ -8,12 +8,19 @@ use std::sync::Arc;
 use tokio;
 use tower_http::cors::CorsLayer;
 
+mod alarms;
 mod args;
-mod encoder;
+mod backend;
+mod logs;
 mod monitor;
+mod probe;
+mod progress;
+mod recording;
 mod router;
 mod state;
+mod tap;
 mod transcode;
+mod ts;
 mod utils;
 
 fn setup() {
@@ -66,6 +73,25 @@ async fn main() {
         .route("/stream", get(router::get_streams))
         .route("/stream", post(router::create_stream))
         .route("/stream/:uuid", delete(router::delete_stream))
+        .route("/stream/:uuid/stats", get(router::get_stream_stats))
+        .route("/stream/:uuid/ts", get(router::get_stream_ts))
+        .route("/stream/:uuid/thumbnail", get(router::get_stream_thumbnail))
+        .route(
+            "/stream/:uuid/recording",
+            get(router::get_stream_recordings),
+        )
+        .route(
+            "/stream/:uuid/recording/:name",
+            get(router::get_stream_recording),
+        )
+        .route("/stream/:uuid/logs", get(router::get_stream_logs))
+        .route("/stream/:uuid/logs/tail", get(router::tail_stream_logs))
+        .route("/job", get(router::get_jobs))
+        .route("/job", post(router::create_job))
+        .route("/job/:uuid", get(router::get_job))
+        .route("/job/:uuid", delete(router::delete_job))
+        .route("/job/:uuid/logs", get(router::get_job_logs))
+        .route("/probe", post(router::probe_input))
         .route("/encoder", get(router::get_encoder_status))
         .route("/capabilities", get(router::get_capabilities))
         .route("/livez", get(router::livez))
@@ -1,9 +1,10 @@
-use crate::state::{self, Codec, Encoder};
+use crate::state::{self, Encoder};
+use crate::tap;
 use crate::transcode;
-use crate::{args, encoder};
+use crate::{args, backend, probe, recording, utils};
 use clap::Parser;
 use std::sync::Arc;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, watch};
 
 async fn set_server_running(state: &Arc<state::App>) {
     let mut status = state.server_status.lock().await;
@@ -13,14 +14,312 @@ async fn set_server_running(state: &Arc<state::App>) {
     *status = state::ServerStatus::Ready;
 }
 
-async fn set_stream_exited(state: &Arc<state::App>, uuid: &uuid::Uuid, name: &String) {
-    log::info!("Setting stream {} as Exited", name);
+// Status of a stream whose ffmpeg process is gone, a one-shot input exiting cleanly is done rather than crashed
+fn exit_status(stream: &state::Stream, exit_code: Option<i32>) -> state::StreamStatus {
+    if stream.stop_requested {
+        return state::StreamStatus::Exited;
+    }
+    if exit_code == Some(0) && stream.input_mode() == state::InputMode::Once {
+        return state::StreamStatus::Finished;
+    }
+    state::StreamStatus::Exited
+}
+
+async fn set_stream_exited(
+    state: &Arc<state::App>,
+    uuid: &uuid::Uuid,
+    name: &String,
+    pid: Option<u32>,
+    exit_code: Option<i32>,
+) {
     let mut streams = state.streams.lock().await;
     for stream in streams.iter_mut() {
-        if stream.id == *uuid {
+        // Ignore late messages from a process that has already been restarted
+        if stream.id == *uuid && stream.pid == pid && stream.status != state::StreamStatus::Finished
+        {
+            stream.status = exit_status(stream, exit_code);
+            stream.last_exit_code = exit_code;
+            if stream.status == state::StreamStatus::Finished {
+                stream.finished_at = Some(std::time::Instant::now());
+            }
+            log::info!("Setting stream {} as {:?}", name, stream.status);
+        }
+    }
+}
+
+// Probe the input without holding the streams lock, the stream starts once the result is recorded
+async fn probe_input(state: Arc<state::App>, uuid: uuid::Uuid, input: String) {
+    let info = probe::input_info(&input).await;
+    log::info!("Probed input {}: {:?}", utils::redact_secrets(&input), info);
+
+    let mut streams = state.streams.lock().await;
+    if let Some(stream) = streams.iter_mut().find(|s| s.id == uuid) {
+        stream.probe = state::InputProbe::Done(info);
+    }
+}
+
+// Preferred inputs are probed this often while on a backup input or the slate
+const FAILBACK_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(10);
+
+// Stop the process so the stream is restarted on another input
+async fn request_switch(stream: &mut state::Stream, target: state::InputSource, reason: String) {
+    let message = format!("{}, switching to {}", reason, target);
+    log::warn!("Stream {}: {}", stream.name, message);
+    stream
+        .logs
+        .lock()
+        .await
+        .push(state::StreamLogLevel::Exit, message.clone());
+
+    stream.last_switch = Some(message);
+    stream.switch_to = Some(target);
+    stream.status = state::StreamStatus::Stopping;
+}
+
+// Restart an exited stream on the next input, returns false to leave it to the restart policy
+async fn switch_input(stream: &mut state::Stream) -> bool {
+    if stream.stop_requested || stream.failover().is_none() {
+        return false;
+    }
+
+    let target = match stream.switch_to.take() {
+        Some(target) => target,
+        None => match stream.failover().and_then(|f| f.next(stream.source)) {
+            Some(target) => {
+                let message = format!(
+                    "{} exited with code {:?}, switching to {}",
+                    stream.source, stream.last_exit_code, target
+                );
+                log::warn!("Stream {}: {}", stream.name, message);
+                stream
+                    .logs
+                    .lock()
+                    .await
+                    .push(state::StreamLogLevel::Exit, message.clone());
+                stream.last_switch = Some(message);
+                target
+            }
+            // Out of inputs, start over from the primary
+            None => {
+                stream.source = state::InputSource::Primary;
+                return false;
+            }
+        },
+    };
+
+    stream.source = target;
+    stream.status = state::StreamStatus::Waiting;
+    stream.pid = None;
+    stream.started_at = None;
+    stream.restart_at = None;
+    stream.stop_signal_at = None;
+    stream.killed = false;
+    stream.failback_at = (target != state::InputSource::Primary)
+        .then(|| std::time::Instant::now() + FAILBACK_INTERVAL);
+    true
+}
+
+// Switch inputs when no new frames arrived within the stall timeout
+async fn check_stall(stream: &mut state::Stream) {
+    let Some(timeout) = stream.failover().map(|f| f.stall_timeout()) else {
+        return;
+    };
+
+    if let Some(frame) = stream.latest_stats().map(|s| s.frame) {
+        if stream.last_frame.map(|(f, _)| f) != Some(frame) {
+            stream.last_frame = Some((frame, std::time::Instant::now()));
+        }
+    }
+
+    let since = stream.last_frame.map(|(_, t)| t).or(stream.started_at);
+    if since.is_some_and(|t| t.elapsed() >= timeout) {
+        let target = stream
+            .failover()
+            .and_then(|f| f.next(stream.source))
+            .unwrap_or(state::InputSource::Primary);
+        let reason = format!("{} stalled for {}s", stream.source, timeout.as_secs());
+        request_switch(stream, target, reason).await;
+    }
+}
+
+// Probe the preferred inputs in order, and switch back to the first one delivering video
+async fn failback(
+    state: Arc<state::App>,
+    uuid: uuid::Uuid,
+    source: state::InputSource,
+    candidates: Vec<(state::InputSource, String)>,
+) {
+    let mut found = None;
+    for (candidate, uri) in candidates {
+        let info = probe::ffprobe(&uri, probe::PROBE_TIMEOUT)
+            .await
+            .ok()
+            .and_then(|output| probe::parse_input_info(&output));
+        if info.is_some_and(|i| i.video_codec.is_some()) {
+            found = Some(candidate);
+            break;
+        }
+    }
+
+    let mut streams = state.streams.lock().await;
+    let Some(stream) = streams.iter_mut().find(|s| s.id == uuid) else {
+        return;
+    };
+    // The stream moved on while probing
+    if stream.status != state::StreamStatus::Running || stream.source != source {
+        return;
+    }
+
+    match found {
+        Some(candidate) => {
+            request_switch(stream, candidate, format!("{} is back", candidate)).await;
+        }
+        None => stream.failback_at = Some(std::time::Instant::now() + FAILBACK_INTERVAL),
+    }
+}
+
+enum StopStep {
+    Reaped,
+    Send(nix::sys::signal::Signal),
+    // Waiting for the process to exit
+    Wait,
+}
+
+// SIGTERM first, SIGKILL once the grace period has run out, done when the process is gone
+fn stop_step(
+    pid: nix::unistd::Pid,
+    stop_signal_at: &mut Option<std::time::Instant>,
+    killed: &mut bool,
+    grace_period: tokio::time::Duration,
+) -> StopStep {
+    // Signal 0 only checks the process, a zombie still exists until it has been reaped
+    if let Err(nix::errno::Errno::ESRCH) = nix::sys::signal::kill(pid, None) {
+        return StopStep::Reaped;
+    }
+
+    match stop_signal_at {
+        None => {
+            *stop_signal_at = Some(std::time::Instant::now());
+            StopStep::Send(nix::sys::signal::Signal::SIGTERM)
+        }
+        Some(t) if t.elapsed() >= grace_period && !*killed => {
+            *killed = true;
+            StopStep::Send(nix::sys::signal::Signal::SIGKILL)
+        }
+        Some(_) => StopStep::Wait,
+    }
+}
+
+async fn stop_stream(stream: &mut state::Stream, grace_period: tokio::time::Duration) {
+    let pid = match stream.pid {
+        Some(pid_u32) => nix::unistd::Pid::from_raw(pid_u32 as i32),
+        None => {
+            log::info!("Stream {} has no process, marking as Exited", stream.name);
             stream.status = state::StreamStatus::Exited;
+            return;
+        }
+    };
+
+    let signal = match stop_step(
+        pid,
+        &mut stream.stop_signal_at,
+        &mut stream.killed,
+        grace_period,
+    ) {
+        StopStep::Reaped => {
+            log::info!("Stream {} stopped and reaped", stream.name);
+            stream.status = state::StreamStatus::Exited;
+            return;
+        }
+        StopStep::Send(signal) => signal,
+        StopStep::Wait => return,
+    };
+
+    log::info!("Monitor sending {} to stream {}", signal, stream.name);
+    stream.logs.lock().await.push(
+        state::StreamLogLevel::Exit,
+        format!("Stopping, sent {}", signal),
+    );
+
+    if let Err(e) = nix::sys::signal::kill(pid, signal) {
+        log::error!("Error stopping stream {}: {}", stream.name, e);
+    }
+}
+
+// Same as stop_stream for a cancelled job, its pass logs go once ffmpeg can't write them anymore
+async fn stop_job(job: &mut state::Job, grace_period: tokio::time::Duration) {
+    if let Some(pid_u32) = job.pid {
+        let pid = nix::unistd::Pid::from_raw(pid_u32 as i32);
+        match stop_step(pid, &mut job.stop_signal_at, &mut job.killed, grace_period) {
+            StopStep::Reaped => {}
+            StopStep::Send(signal) => {
+                log::info!("Monitor sending {} to job {}", signal, job.name);
+                job.logs.lock().await.push(
+                    state::StreamLogLevel::Exit,
+                    format!("Stopping, sent {}", signal),
+                );
+                if let Err(e) = nix::sys::signal::kill(pid, signal) {
+                    log::error!("Error stopping job {}: {}", job.name, e);
+                }
+                return;
+            }
+            StopStep::Wait => return,
         }
     }
+
+    log::info!("Job {} cancelled", job.name);
+    job.status = state::JobStatus::Cancelled;
+    job.pid = None;
+    transcode::remove_pass_logs(job.id);
+}
+
+// Processes running at least this long reset the backoff
+const HEALTHY_RUN: tokio::time::Duration = tokio::time::Duration::from_secs(60);
+
+// Put an exited stream back to Waiting if its restart policy allows it, returns false if it should be removed
+async fn schedule_restart(stream: &mut state::Stream) -> bool {
+    if stream.stop_requested {
+        return false;
+    }
+
+    let policy = match stream.options.as_ref().and_then(|o| o.restart.clone()) {
+        Some(policy) => policy,
+        None => return false,
+    };
+
+    if stream
+        .started_at
+        .is_some_and(|t| t.elapsed() >= HEALTHY_RUN)
+    {
+        stream.retries = 0;
+    }
+
+    if !policy.should_restart(stream.last_exit_code, stream.retries) {
+        return false;
+    }
+
+    let delay = policy.delay(stream.retries);
+    let message = format!(
+        "Restarting in {} ms after exit code {:?} (restart {})",
+        delay.as_millis(),
+        stream.last_exit_code,
+        stream.restarts + 1
+    );
+    log::info!("Stream {}: {}", stream.name, message);
+    stream
+        .logs
+        .lock()
+        .await
+        .push(state::StreamLogLevel::Exit, message);
+
+    stream.status = state::StreamStatus::Waiting;
+    stream.pid = None;
+    stream.started_at = None;
+    stream.finished_at = None;
+    stream.restart_at = Some(std::time::Instant::now() + delay);
+    stream.restarts += 1;
+    stream.retries += 1;
+    true
 }
 
 async fn set_server_capabilities(
@@ -47,28 +346,224 @@ pub(crate) async fn start(state: Arc<state::App>) {
     }
 }
 
+// Record the end of a job pass, the next pass is started by update_jobs
+async fn set_job_exited(
+    state: &Arc<state::App>,
+    uuid: uuid::Uuid,
+    pid: Option<u32>,
+    exit_code: Option<i32>,
+) {
+    let mut jobs = state.jobs.lock().await;
+    // Deleted, or a late message from an earlier pass
+    let Some(job) = jobs.iter_mut().find(|j| j.id == uuid && j.pid == pid) else {
+        return;
+    };
+
+    job.pid = None;
+    job.exit_code = exit_code;
+
+    // Reaped, stop_job finishes the cancel
+    if job.status == state::JobStatus::Stopping {
+        return;
+    }
+
+    if exit_code == Some(0) && job.pass < job.passes() {
+        log::info!("Job {} pass {} done", job.name, job.pass);
+        job.pass += 1;
+        job.status = state::JobStatus::Waiting;
+        return;
+    }
+
+    job.elapsed = job.started_at.map(|t| t.elapsed().as_secs());
+    if exit_code == Some(0) {
+        job.status = state::JobStatus::Completed;
+    } else {
+        job.status = state::JobStatus::Failed;
+        job.error = job
+            .logs
+            .lock()
+            .await
+            .last(state::StreamLogLevel::Stderr)
+            .map(|entry| entry.message.clone());
+    }
+    log::info!(
+        "Job {} {:?} with exit code {:?}",
+        job.name,
+        job.status,
+        exit_code
+    );
+    transcode::remove_pass_logs(job.id);
+}
+
+// Probe the job input for its duration and codec, the job starts once the result is recorded
+async fn probe_job(state: Arc<state::App>, uuid: uuid::Uuid, input: String) {
+    let info = probe::input_info(&input).await;
+    if info.as_ref().is_some_and(|i| i.duration.is_none()) {
+        log::warn!(
+            "Input {} has no duration, job progress is unknown",
+            utils::redact_secrets(&input)
+        );
+    }
+
+    let mut jobs = state.jobs.lock().await;
+    if let Some(job) = jobs.iter_mut().find(|j| j.id == uuid) {
+        job.probe = state::InputProbe::Done(info);
+    }
+}
+
+// Start the next pass of a waiting job
+async fn start_job(
+    state: &Arc<state::App>,
+    backend: Option<Arc<dyn backend::EncoderBackend>>,
+    job: &mut state::Job,
+) {
+    log::info!(
+        "Starting job {} pass {}/{}",
+        job.name,
+        job.pass,
+        job.passes()
+    );
+
+    let (tx, _) = broadcast::channel(256);
+    let mut rx = tx.subscribe();
+    let (stats_tx, stats_rx) = watch::channel(None);
+
+    let pid = transcode::job(backend, job.device, job, tx, stats_tx).await;
+    job.pid = pid;
+    job.stats = Some(stats_rx);
+    job.status = state::JobStatus::Running;
+    job.started_at.get_or_insert(std::time::Instant::now());
+
+    let uuid = job.id;
+    let logs = job.logs.clone();
+    let state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(msg) => {
+                    logs.lock()
+                        .await
+                        .push(msg.error.clone(), msg.message.clone());
+                    if msg.error > state::StreamLogLevel::Stderr {
+                        set_job_exited(&state, uuid, pid, msg.exit_code).await;
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(_) => {
+                    set_job_exited(&state, uuid, pid, None).await;
+                    break;
+                }
+            }
+        }
+    });
+}
+
+// Stop cancelled jobs, then probe and start waiting jobs on the devices left over by the streams
+async fn update_jobs(
+    state: &Arc<state::App>,
+    backends: &backend::Backends,
+    grace_period: tokio::time::Duration,
+) {
+    let mut pinned: Vec<u32> = Vec::new();
+    for stream in state.streams.lock().await.iter() {
+        if stream.status == state::StreamStatus::Running {
+            pin(&mut pinned, stream.device);
+        }
+    }
+
+    let mut jobs = state.jobs.lock().await;
+    jobs.retain(|job| job.status != state::JobStatus::Cancelled);
+    for job in jobs
+        .iter_mut()
+        .filter(|job| job.status == state::JobStatus::Stopping)
+    {
+        stop_job(job, grace_period).await;
+    }
+
+    for job in jobs.iter() {
+        if job.status == state::JobStatus::Running {
+            pin(&mut pinned, job.device);
+        }
+    }
+
+    for job in jobs.iter_mut() {
+        if job.status != state::JobStatus::Waiting {
+            continue;
+        }
+
+        match job.probe {
+            state::InputProbe::Pending => {
+                job.probe = state::InputProbe::Running;
+                tokio::spawn(probe_job(state.clone(), job.id, job.input.clone()));
+                continue;
+            }
+            state::InputProbe::Running => continue,
+            state::InputProbe::Done(_) => {}
+        }
+
+        let backend = backends.for_codec(&job.codec);
+
+        // Hardware encoders ignore the statistics of a first pass
+        if job.two_pass && backend.as_ref().and_then(|b| b.encoder()).is_some() {
+            log::warn!(
+                "Job {}: {:?} has no two-pass encoding, encoding in one pass",
+                job.name,
+                backend.as_ref().and_then(|b| b.encoder())
+            );
+            job.two_pass = false;
+        }
+
+        // Both passes run on the same device
+        if job.pass == 1 {
+            job.device = match &backend {
+                Some(backend) if backends.is_primary(backend) => {
+                    backend.pick_device(&*state.encoder_status.lock().await, &pinned)
+                }
+                _ => None,
+            };
+        }
+        pin(&mut pinned, job.device);
+
+        start_job(state, backend, job).await;
+
+        // One start per loop, like streams
+        break;
+    }
+}
+
+// Count a stream on its device
+fn pin(pinned: &mut Vec<u32>, device: Option<usize>) {
+    if let Some(device) = device {
+        if pinned.len() <= device {
+            pinned.resize(device + 1, 0);
+        }
+        pinned[device] += 1;
+    }
+}
+
 // Thread to monitor the state of the system, create new streams and remove old ones depending on the app state
 pub(crate) async fn run(state: Arc<state::App>) {
     let args = args::Args::parse();
 
-    let mut enc: Option<Encoder> = None;
-
-    if !args.cpu_only {
-        let codecs: Vec<Codec>;
-        // set server capabilities
-        (enc, codecs) = transcode::list_hardware_support();
-        let state_clone = state.clone();
-        tokio::spawn(async move {
-            set_server_capabilities(&state_clone, enc, codecs).await;
-        });
-    } else {
+    if args.cpu_only {
         log::info!("--cpu-only provided: Forcing CPU encoding and ignoring any hardware encoders");
-        let state_clone = state.clone();
-        tokio::spawn(async move {
-            set_server_capabilities(&state_clone, None, Codec::all()).await;
-        });
     }
 
+    // set server capabilities
+    let backends = backend::detect();
+    let backend = backends.primary.clone();
+    let codecs = backends.codecs();
+    let enc = backend.as_ref().and_then(|b| b.encoder());
+    let state_clone = state.clone();
+    tokio::spawn(async move {
+        set_server_capabilities(&state_clone, enc, codecs).await;
+    });
+
+    let grace_period = tokio::time::Duration::from_millis(args.stop_grace_period);
+    let finished_ttl = tokio::time::Duration::from_secs(args.finished_ttl);
+    let mut last_retention = std::time::Instant::now();
+
     loop {
         // Set status as running if it's not already
         set_server_running(&state).await;
@@ -77,43 +572,144 @@ pub(crate) async fn run(state: Arc<state::App>) {
         {
             let mut streams = state.streams.lock().await;
 
-            // Remove streams that are exited
-            streams.retain(|stream| stream.status != state::StreamStatus::Exited);
+            // Restart exited streams according to their policy, remove the rest
+            for stream in streams.iter_mut() {
+                let finished_always = stream.status == state::StreamStatus::Finished
+                    && stream
+                        .options
+                        .as_ref()
+                        .and_then(|o| o.restart.as_ref())
+                        .map(|r| r.mode)
+                        == Some(state::RestartMode::Always);
+
+                if stream.status == state::StreamStatus::Exited && switch_input(stream).await {
+                    continue;
+                }
+                if stream.status == state::StreamStatus::Exited || finished_always {
+                    schedule_restart(stream).await;
+                }
+            }
+            // Finished streams are kept a while, so the LB sees them finish rather than disappear
+            let removed = |stream: &state::Stream| {
+                stream.status == state::StreamStatus::Exited
+                    || (stream.status == state::StreamStatus::Finished
+                        && stream
+                            .finished_at
+                            .is_some_and(|t| t.elapsed() >= finished_ttl))
+            };
+            for stream in streams.iter().filter(|stream| removed(stream)) {
+                if let Some(path) = transcode::thumbnail_path(stream.id) {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+            streams.retain(|stream| !removed(stream));
+
+            // Streams per device, so streams started before the next stats poll are spread out
+            let mut pinned: Vec<u32> = Vec::new();
+            for stream in streams.iter() {
+                if stream.status == state::StreamStatus::Running {
+                    pin(&mut pinned, stream.device);
+                }
+            }
+            for job in state.jobs.lock().await.iter() {
+                if job.status == state::JobStatus::Running {
+                    pin(&mut pinned, job.device);
+                }
+            }
 
             // Update streams
             for stream in streams.iter_mut() {
-                // Start the stream if it's waiting
-                if stream.status == state::StreamStatus::Waiting {
+                // Probe the input first, for hardware decoding and its audio tracks
+                if stream.status == state::StreamStatus::Waiting
+                    && stream.probe == state::InputProbe::Pending
+                    && stream.probe_input()
+                {
+                    stream.probe = state::InputProbe::Running;
+                    tokio::spawn(probe_input(state.clone(), stream.id, stream.input_uri()));
+                }
+
+                // Start the stream if it's waiting, and any restart backoff has passed
+                if stream.status == state::StreamStatus::Waiting
+                    && stream.probe != state::InputProbe::Running
+                    && stream
+                        .restart_at
+                        .is_none_or(|t| std::time::Instant::now() >= t)
+                {
+                    // Retried on every start, the port may still be held by the previous process
+                    if stream.tap.is_none()
+                        && stream.source == state::InputSource::Primary
+                        && stream.analyze_input()
+                    {
+                        stream.tap = tap::start(&mut *state.taps.lock().await, &stream.input);
+                    }
+
+                    let input = stream
+                        .source_input(stream.source)
+                        .unwrap_or(stream.input.clone());
+
                     // Start the stream
                     log::info!(
-                        "Starting stream {}, with file {}",
+                        "Starting stream {}, with {} {}",
                         stream.name,
-                        stream.input
+                        stream.source,
+                        utils::redact_secrets(&input)
                     );
 
                     let (tx, rx) = broadcast::channel(256);
                     let mut rx_clone = tx.subscribe();
+                    let (stats_tx, stats_rx) = watch::channel(None);
 
-                    let encoder_stats = state.encoder_status.lock().await;
+                    let stream_backend = backends.for_codec(&stream.codec);
+                    let device = match &stream_backend {
+                        Some(backend) if backends.is_primary(backend) => {
+                            backend.pick_device(&*state.encoder_status.lock().await, &pinned)
+                        }
+                        _ => None,
+                    };
+                    pin(&mut pinned, device);
+
+                    // Only the primary input is probed
+                    let input_codec = match stream.source {
+                        state::InputSource::Primary => {
+                            stream.input_info().and_then(|i| i.video_codec.clone())
+                        }
+                        _ => None,
+                    };
 
                     let pid = transcode::stream(
-                        encoder_stats.clone(),
-                        stream.id.clone(),
-                        stream.name.clone(),
-                        stream.input.clone(),
-                        stream.output.clone(),
-                        stream.codec.clone(),
-                        stream.options.clone(),
+                        stream_backend,
+                        device,
+                        input_codec,
+                        transcode::StreamRun {
+                            uuid: stream.id.clone(),
+                            name: stream.name.clone(),
+                            input: transcode::Input {
+                                uri: input,
+                                source: stream.source,
+                                info: stream.input_info().cloned(),
+                            },
+                            output: stream.output.clone(),
+                            codec: stream.codec.clone(),
+                            options: stream.options.clone(),
+                        },
                         tx,
+                        stats_tx,
                     )
                     .await;
 
                     stream.rx = Some(rx);
+                    stream.stats = Some(stats_rx);
                     stream.pid = pid;
+                    stream.device = device;
                     stream.status = state::StreamStatus::Running;
+                    stream.started_at = Some(std::time::Instant::now());
+                    stream.restart_at = None;
+                    stream.last_frame = None;
 
                     let stream_name = stream.name.clone();
                     let stream_uuid = stream.id.clone();
+                    let stream_logs = stream.logs.clone();
+                    let stream_alarms = stream.alarms.clone();
 
                     let state_clone = state.clone();
                     // Spawn a thread to read the log messages and log them
@@ -121,18 +717,50 @@ pub(crate) async fn run(state: Arc<state::App>) {
                         loop {
                             match rx_clone.recv().await {
                                 Ok(msg) => {
+                                    stream_logs
+                                        .lock()
+                                        .await
+                                        .push(msg.error.clone(), msg.message.clone());
+                                    if msg.error == state::StreamLogLevel::Stderr {
+                                        stream_alarms.lock().await.push(&msg.message);
+                                    }
+
                                     if msg.error > state::StreamLogLevel::Stderr {
                                         log::error!("Stream {}: {}", stream_name, msg.message);
-                                        set_stream_exited(&state_clone, &stream_uuid, &stream_name)
-                                            .await;
+                                        stream_alarms.lock().await.end_all();
+                                        set_stream_exited(
+                                            &state_clone,
+                                            &stream_uuid,
+                                            &stream_name,
+                                            pid,
+                                            msg.exit_code,
+                                        )
+                                        .await;
+                                        break;
                                     } else {
-                                        log::info!("Stream {}: {}", stream_name, msg.message);
+                                        // Kept in the stream log buffer, see GET /stream/:uuid/logs
+                                        log::debug!("Stream {}: {}", stream_name, msg.message);
                                     }
                                 }
+                                // Lines were dropped, the process is still running
+                                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                    log::warn!(
+                                        "Stream {}: Skipped {} log lines",
+                                        stream_name,
+                                        skipped
+                                    );
+                                }
                                 Err(e) => {
                                     log::info!("Stream {}: Error reading log: {}", stream_name, e);
-                                    set_stream_exited(&state_clone, &stream_uuid, &stream_name)
-                                        .await;
+                                    stream_alarms.lock().await.end_all();
+                                    set_stream_exited(
+                                        &state_clone,
+                                        &stream_uuid,
+                                        &stream_name,
+                                        pid,
+                                        None,
+                                    )
+                                    .await;
                                     break;
                                 }
                             }
@@ -143,100 +771,85 @@ pub(crate) async fn run(state: Arc<state::App>) {
                     break;
                 }
 
-                // Update status of the stream if it's running
+                // Fail over when the input stalls, and back once a preferred input answers again
                 if stream.status == state::StreamStatus::Running {
-                    // Check if the process is still running
-                    match stream.pid {
-                        Some(pid_u32) => {
-                            let pid = nix::unistd::Pid::from_raw(pid_u32 as i32);
-                            match nix::sys::wait::waitpid(
-                                pid,
-                                Some(nix::sys::wait::WaitPidFlag::WNOHANG),
-                            ) {
-                                Ok(nix::sys::wait::WaitStatus::Exited(_, _)) => {
-                                    log::info!("Stream {} has exited", stream.name);
-                                    stream.status = state::StreamStatus::Exited;
-                                }
-                                _ => {}
-                            }
-                        }
-                        None => {}
-                    }
+                    check_stall(stream).await;
+                }
+                // Black is raised here, once the dark frames lasted long enough
+                if stream.status == state::StreamStatus::Running {
+                    stream.alarms.lock().await.tick();
+                }
+                if stream.status == state::StreamStatus::Running
+                    && stream
+                        .failback_at
+                        .is_some_and(|t| std::time::Instant::now() >= t)
+                {
+                    stream.failback_at = None;
+                    let candidates = stream
+                        .failover()
+                        .map(|f| f.preferred(stream.source))
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|source| match source {
+                            // A listener can't be probed without taking the caller's connection
+                            state::InputSource::Primary if !stream.probe_input() => None,
+                            state::InputSource::Primary => Some((source, stream.input_uri())),
+                            _ => Some((source, stream.source_input(source)?)),
+                        })
+                        .collect();
+                    tokio::spawn(failback(
+                        state.clone(),
+                        stream.id,
+                        stream.source,
+                        candidates,
+                    ));
                 }
 
-                // Remove the stream if it's Stopping
+                // Stop the stream if it's Stopping: SIGTERM, SIGKILL after the grace period, Exited once reaped
                 if stream.status == state::StreamStatus::Stopping {
-                    log::info!("Monitor stopping stream {}", stream.name);
-                    match stream.pid {
-                        Some(pid_u32) => {
-                            let pid = nix::unistd::Pid::from_raw(pid_u32 as i32);
-                            match nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGTERM) {
-                                Ok(_) => {
-                                    log::info!("Stream {} killed", stream.name);
-                                }
-                                Err(e) => {
-                                    log::error!("Error stopping stream {}: {}", stream.name, e);
-                                }
-                            }
-                        }
-                        None => {
-                            log::error!("Stream {} has no PID", stream.name);
-                        }
-                    }
-
-                    // Mark the stream as exited, we can't be 100% sure it's stopped but we'll assume it has
-                    stream.status = state::StreamStatus::Exited;
+                    stop_stream(stream, grace_period).await;
                 }
             }
         }
 
-        // Log encoder status
-        if enc == Some(Encoder::NVENC) {
-            let stats = encoder::nvenc_stats();
-            match stats {
-                Ok(stats) => {
-                    log::info!("NVENC stats: {:?}", stats);
-                    let mut encoder_status = state.encoder_status.lock().await;
-                    *encoder_status = stats;
-                }
-                Err(e) => {
-                    log::error!("Error getting NVENC stats: {}", e);
-                }
-            }
-        }
+        update_jobs(&state, &backends, grace_period).await;
 
-        if enc == Some(Encoder::U30) {
-            let stats = encoder::xilinx_stats();
-            match stats {
-                Ok(stats) => {
-                    log::info!("Xilinx xrmadm stats: {:?}", stats);
-                    let mut encoder_status = state.encoder_status.lock().await;
-                    *encoder_status = stats;
-                }
-                Err(e) => {
-                    log::error!("Error getting Xilinx stats: {}", e);
-                }
+        // Enforce the recording limits, the directory listings run off the monitor thread
+        if last_retention.elapsed() >= recording::RETENTION_INTERVAL {
+            last_retention = std::time::Instant::now();
+            let recordings: Vec<(String, state::Recording)> = state
+                .streams
+                .lock()
+                .await
+                .iter()
+                .filter_map(|s| {
+                    Some((recording::dir(s.id), s.options.as_ref()?.recording.clone()?))
+                })
+                .collect();
+            if !recordings.is_empty() {
+                tokio::task::spawn_blocking(move || {
+                    for (dir, recording) in recordings {
+                        recording::clean(&dir, &recording);
+                    }
+                });
             }
         }
 
-        if enc == Some(Encoder::NIT2A) {
-            let stats = encoder::netint_stats();
-            match stats {
-                Ok(stats) => {
-                    log::info!("Netint stats: {:?}", stats);
-                    let mut encoder_status = state.encoder_status.lock().await;
-                    *encoder_status = stats;
-                }
-                Err(e) => {
-                    log::error!("Error getting Netint stats: {}", e);
-                }
+        // Log encoder status
+        match backend.as_ref().and_then(|b| b.utilization()) {
+            Some(Ok(stats)) => {
+                log::info!("{:?} stats: {:?}", enc, stats);
+                let mut encoder_status = state.encoder_status.lock().await;
+                *encoder_status = stats;
+            }
+            Some(Err(e)) => {
+                log::error!("Error getting {:?} stats: {}", enc, e);
+            }
+            None => {
+                // set utilization to 100 + 10 * stream count to ensure not all streams are started on the same worker
+                let mut encoder_status = state.encoder_status.lock().await;
+                encoder_status.utilization = 100 + 10 * state.streams.lock().await.len() as u32;
             }
-        }
-
-        if enc.is_none() {
-            // set utilization to 100 + 10 * stream count to ensure not all streams are started on the same worker
-            let mut encoder_status = state.encoder_status.lock().await;
-            encoder_status.utilization = 100 + 10 * state.streams.lock().await.len() as u32;
         }
 
         // Sleep for a while
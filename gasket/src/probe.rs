@@ -0,0 +1,158 @@
+use crate::state::{AudioTrack, DataStream, InputInfo, Program};
+use crate::utils::{self, get_ffprobe_path};
+use serde_json::Value;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command as TokioCommand;
+
+// Used when probing inputs of new streams and as the default of POST /probe
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// ffprobe stream listing of the input as JSON, live inputs that never deliver give up after the timeout
+pub(crate) async fn ffprobe(input: &str, timeout: Duration) -> Result<String, String> {
+    let mut command = TokioCommand::new(get_ffprobe_path());
+    command
+        .arg("-hide_banner")
+        .arg("-v")
+        .arg("error")
+        .arg("-show_format")
+        .arg("-show_programs")
+        .arg("-show_streams")
+        .arg("-of")
+        .arg("json")
+        .arg(input)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let output = match tokio::time::timeout(timeout, command.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => return Err(format!("Failed to run ffprobe: {e}")),
+        Err(_) => return Err(format!("ffprobe timed out after {:?}", timeout)),
+    };
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// Video format, programs, audio tracks and data streams in the ffprobe output
+pub(crate) fn parse_input_info(output: &str) -> Option<InputInfo> {
+    let parsed: Value = serde_json::from_str(output).ok()?;
+    let streams = parsed["streams"].as_array()?;
+
+    let video = streams
+        .iter()
+        .find(|stream| stream["codec_type"] == "video");
+
+    // Number of the MPTS program carrying a stream
+    let program_of = |index: u32| {
+        parsed["programs"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|program| {
+                program["streams"]
+                    .as_array()
+                    .is_some_and(|s| s.iter().any(|s| as_u32(&s["index"]) == Some(index)))
+            })
+            .and_then(|program| as_u32(&program["program_num"]))
+    };
+
+    let audio_tracks = streams
+        .iter()
+        .filter(|stream| stream["codec_type"] == "audio")
+        .enumerate()
+        .map(|(index, stream)| AudioTrack {
+            index: index as u32,
+            codec: stream["codec_name"].as_str().map(String::from),
+            language: stream["tags"]["language"].as_str().map(String::from),
+            channels: as_u32(&stream["channels"]),
+            channel_layout: stream["channel_layout"].as_str().map(String::from),
+            // ffprobe reports the sample rate as a string
+            sample_rate: stream["sample_rate"].as_str().and_then(|r| r.parse().ok()),
+            program: as_u32(&stream["index"]).and_then(program_of),
+        })
+        .collect();
+
+    let programs = parsed["programs"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|program| {
+            Some(Program {
+                program_num: as_u32(&program["program_num"])?,
+                pmt_pid: as_u32(&program["pmt_pid"]),
+                pcr_pid: as_u32(&program["pcr_pid"]),
+                service_name: program["tags"]["service_name"].as_str().map(String::from),
+                service_provider: program["tags"]["service_provider"]
+                    .as_str()
+                    .map(String::from),
+            })
+        })
+        .collect();
+
+    let data_streams = streams
+        .iter()
+        .filter(|stream| stream["codec_type"] == "data")
+        .filter_map(|stream| {
+            let index = as_u32(&stream["index"])?;
+            Some(DataStream {
+                index,
+                codec: stream["codec_name"].as_str().map(String::from),
+                // MPEG-TS PID as hex, e.g. "0x1f6"
+                pid: stream["id"]
+                    .as_str()
+                    .and_then(|id| u32::from_str_radix(id.trim_start_matches("0x"), 16).ok()),
+                program: program_of(index),
+            })
+        })
+        .collect();
+
+    Some(InputInfo {
+        format: parsed["format"]["format_name"].as_str().map(String::from),
+        video_codec: video
+            .and_then(|stream| stream["codec_name"].as_str())
+            .map(String::from),
+        width: video.and_then(|stream| as_u32(&stream["width"])),
+        height: video.and_then(|stream| as_u32(&stream["height"])),
+        // avg_frame_rate is 0/0 on some live inputs
+        framerate: video.and_then(|stream| {
+            parse_rational(stream["avg_frame_rate"].as_str()?)
+                .or_else(|| parse_rational(stream["r_frame_rate"].as_str()?))
+        }),
+        // Reported as a string, missing on live inputs
+        duration: parsed["format"]["duration"]
+            .as_str()
+            .and_then(|d| d.parse().ok()),
+        programs,
+        audio_tracks,
+        data_streams,
+    })
+}
+
+fn as_u32(value: &Value) -> Option<u32> {
+    value.as_u64().and_then(|v| u32::try_from(v).ok())
+}
+
+// "30000/1001" -> 29.97
+fn parse_rational(rate: &str) -> Option<f64> {
+    let (num, den) = rate.split_once('/')?;
+    let (num, den) = (num.parse::<f64>().ok()?, den.parse::<f64>().ok()?);
+    if num <= 0.0 || den <= 0.0 {
+        return None;
+    }
+    Some(num / den)
+}
+
+// Codecs and tracks of the input, None if it couldn't be probed
+pub(crate) async fn input_info(input: &str) -> Option<InputInfo> {
+    match ffprobe(input, PROBE_TIMEOUT).await {
+        Ok(output) => parse_input_info(&output),
+        Err(e) => {
+            log::warn!("Could not probe {}: {}", utils::redact_secrets(input), e);
+            None
+        }
+    }
+}
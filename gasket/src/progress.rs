@@ -0,0 +1,44 @@
+use crate::state::StreamStats;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Parser for ffmpeg `-progress` output, a block of key=value lines ending with progress=continue|end
+#[derive(Default)]
+pub(crate) struct ProgressParser {
+    current: StreamStats,
+}
+
+impl ProgressParser {
+    // Feed one line, returns the snapshot once a block is complete
+    pub(crate) fn push(&mut self, line: &str) -> Option<StreamStats> {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+
+        match key {
+            "frame" => self.current.frame = value.parse().unwrap_or_default(),
+            "fps" => self.current.fps = value.parse().unwrap_or_default(),
+            // 1234.5kbits/s or N/A
+            "bitrate" => {
+                self.current.bitrate = value
+                    .trim_end_matches("kbits/s")
+                    .parse()
+                    .unwrap_or_default()
+            }
+            "total_size" => self.current.total_size = value.parse().unwrap_or_default(),
+            "out_time_us" => self.current.out_time_us = value.parse().unwrap_or_default(),
+            "dup_frames" => self.current.dup_frames = value.parse().unwrap_or_default(),
+            "drop_frames" => self.current.drop_frames = value.parse().unwrap_or_default(),
+            // 1.01x or N/A
+            "speed" => self.current.speed = value.trim_end_matches('x').parse().unwrap_or_default(),
+            "progress" => {
+                self.current.updated = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default();
+                return Some(self.current.clone());
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
@@ -0,0 +1,83 @@
+use crate::args;
+use crate::state::Recording;
+use clap::Parser;
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+// How often the monitor enforces the retention limits
+pub(crate) const RETENTION_INTERVAL: Duration = Duration::from_secs(10);
+
+// A recorded segment as listed by GET /stream/:uuid/recording
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct RecordingFile {
+    pub(crate) name: String,
+    pub(crate) size: u64,
+    // Unix timestamp in seconds of the last write
+    pub(crate) modified: u64,
+}
+
+// Where the recordings of a stream are written, kept after the stream is removed
+pub(crate) fn dir(uuid: Uuid) -> String {
+    format!(
+        "{}/{}",
+        args::Args::parse().recording_dir.trim_end_matches('/'),
+        uuid
+    )
+}
+
+// Only plain file names, so requests can't leave the recording directory
+pub(crate) fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+// Segments of a recording directory, oldest first
+pub(crate) fn list(dir: &str) -> Vec<RecordingFile> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut files: Vec<RecordingFile> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            Some(RecordingFile {
+                name: entry.file_name().into_string().ok()?,
+                size: metadata.len(),
+                modified,
+            })
+        })
+        .collect();
+    files.sort_by(|a, b| (a.modified, &a.name).cmp(&(b.modified, &b.name)));
+    files
+}
+
+// Remove the oldest segments past the count and size limits, the newest one is still being written
+pub(crate) fn clean(dir: &str, recording: &Recording) {
+    let mut files = list(dir);
+    let mut total: u64 = files.iter().map(|f| f.size).sum();
+    let max_size = recording.max_size_mb.map(|mb| mb * 1024 * 1024);
+
+    while files.len() > 1 {
+        let too_many = recording.max_segments.is_some_and(|max| files.len() > max);
+        let too_big = max_size.is_some_and(|max| total > max);
+        if !too_many && !too_big {
+            break;
+        }
+
+        let oldest = files.remove(0);
+        total -= oldest.size;
+        let path = Path::new(dir).join(&oldest.name);
+        match std::fs::remove_file(&path) {
+            Ok(_) => log::info!("Removed recording {:?}", path),
+            Err(e) => log::error!("Could not remove recording {:?}: {}", path, e),
+        }
+    }
+}
@@ -1,9 +1,27 @@
+use crate::alarms::Alarms;
+use crate::args;
+use crate::logs::LogBuffer;
+use crate::probe;
+use crate::recording;
 use crate::state;
 use crate::state::StreamOptions;
+use crate::transcode;
+use crate::ts;
 use crate::utils;
-use axum::{extract::Path, extract::State, http::StatusCode, response::Json};
+use axum::body::Body;
+use axum::http::header;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::IntoResponse;
+use axum::{extract::Path, extract::Query, extract::State, http::StatusCode, response::Json};
+use clap::Parser;
 use serde_json::{json, Value};
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 
 // GET /
@@ -27,12 +45,21 @@ pub(crate) async fn get_streams(State(data): State<Arc<state::App>>) -> Json<Val
         let stream_info = json!({
             "id": stream.id.to_string(),
             "name": stream.name,
-            "input": stream.input,
-            "output": stream.output,
+            "input": utils::redact_secrets(&stream.input),
+            "output": utils::redact_secrets(&stream.output),
             "codec": stream.codec,
             "status": format!("{:?}", stream.status),
-            "options": stream.options,
+            "options": stream.options.as_ref().map(StreamOptions::redacted),
             "pid": stream.pid.unwrap_or(0),
+            "playlist": stream.playlist(),
+            "stats": stream.latest_stats(),
+            "restarts": stream.restarts,
+            "last_exit_code": stream.last_exit_code,
+            "device": stream.device,
+            "audio_tracks": stream.audio_tracks(),
+            "active_input": stream.source.to_string(),
+            "last_switch": stream.last_switch,
+            "alarms": stream.alarms.lock().await.history(),
         });
 
         response.push(stream_info);
@@ -55,7 +82,13 @@ pub(crate) struct AddStreamParams {
 pub(crate) async fn create_stream(
     State(data): State<Arc<state::App>>,
     Json(payload): Json<AddStreamParams>,
-) -> Result<Json<Value>, StatusCode> {
+) -> Result<Json<Value>, (StatusCode, String)> {
+    if let Some(options) = &payload.options {
+        options
+            .validate_renditions()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    }
+
     let mut streams_list = data.streams.lock().await;
 
     // If stream with same ID already exists, return 204
@@ -66,6 +99,11 @@ pub(crate) async fn create_stream(
         }
     }
 
+    let alarms = payload
+        .options
+        .as_ref()
+        .and_then(|o| o.alarms.clone())
+        .unwrap_or_default();
     let stream = state::Stream {
         name: payload.name,
         id: payload.id.unwrap_or(Uuid::new_v4()),
@@ -76,6 +114,26 @@ pub(crate) async fn create_stream(
         status: state::StreamStatus::Waiting,
         pid: None,
         rx: None,
+        stats: None,
+        logs: Arc::new(Mutex::new(LogBuffer::new(args::Args::parse().log_lines))),
+        alarms: Arc::new(Mutex::new(Alarms::new(alarms))),
+        tap: None,
+        stop_requested: false,
+        restarts: 0,
+        retries: 0,
+        last_exit_code: None,
+        started_at: None,
+        restart_at: None,
+        finished_at: None,
+        stop_signal_at: None,
+        killed: false,
+        device: None,
+        probe: state::InputProbe::Pending,
+        source: state::InputSource::Primary,
+        switch_to: None,
+        last_switch: None,
+        last_frame: None,
+        failback_at: None,
     };
     let stream_info = state::StreamInfo::from(&stream);
     streams_list.push(stream);
@@ -84,36 +142,440 @@ pub(crate) async fn create_stream(
     return Ok(Json(json!(stream_info)));
 }
 
-// DELETE /stream/:uuid
+// DELETE /stream/:uuid?wait=true
 // Remove stream  by setting the stream's status to StreamStatus::Stopping, the monitor thread will take care of the rest
+// With wait, respond once the process has been reaped
+#[derive(serde::Deserialize)]
+pub(crate) struct DeleteStreamParams {
+    wait: Option<bool>,
+}
 pub(crate) async fn delete_stream(
     State(data): State<Arc<state::App>>,
     Path(uuid): Path<String>,
+    Query(params): Query<DeleteStreamParams>,
 ) -> Result<Json<Value>, StatusCode> {
     log::info!("Requesting delete of stream {}", uuid);
 
-    let mut streams_list = data.streams.lock().await;
+    let mut stream_info: Option<state::StreamInfo> = None;
+    {
+        let mut streams_list = data.streams.lock().await;
 
-    let mut found: Option<&state::Stream> = None;
+        for stream in streams_list.iter_mut() {
+            if stream.id.to_string() == uuid.to_string() {
+                stream.status = state::StreamStatus::Stopping;
+                stream.stop_requested = true;
+                stream_info = Some(state::StreamInfo::from(&*stream));
+                break;
+            }
+        }
+    }
 
-    for stream in streams_list.iter_mut() {
-        if stream.id.to_string() == uuid.to_string() {
-            stream.status = state::StreamStatus::Stopping;
-            found = Some(&*stream);
-            break;
+    let mut stream_info = match stream_info {
+        Some(stream_info) => stream_info,
+        None => return Err(StatusCode::NOT_FOUND),
+    };
+    log::info!("Stream {} marked as Stopping", uuid);
+
+    if !params.wait.unwrap_or(false) {
+        return Ok(Json(json!(stream_info)));
+    }
+
+    // Grace period, SIGKILL and reaping, with some margin
+    let timeout = tokio::time::Duration::from_millis(args::Args::parse().stop_grace_period)
+        + tokio::time::Duration::from_secs(5);
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    while tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+        let streams_list = data.streams.lock().await;
+        match streams_list.iter().find(|x| x.id.to_string() == uuid) {
+            Some(stream) if stream.status == state::StreamStatus::Stopping => {}
+            // Exited, or already removed by the monitor
+            _ => {
+                stream_info.status = state::StreamStatus::Exited;
+                return Ok(Json(json!(stream_info)));
+            }
         }
     }
 
-    match found {
-        Some(stream) => {
-            let stream_info = Some(state::StreamInfo::from(stream));
-            log::info!("Stream {} marked as Stopping", uuid);
-            return Ok(Json(json!(stream_info)));
+    log::error!("Stream {} did not stop in time", uuid);
+    Err(StatusCode::GATEWAY_TIMEOUT)
+}
+
+// GET /stream/:uuid/stats
+// Latest ffmpeg progress snapshot of a stream, null until the first one arrives
+pub(crate) async fn get_stream_stats(
+    State(data): State<Arc<state::App>>,
+    Path(uuid): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    let streams_list = data.streams.lock().await;
+
+    match streams_list.iter().find(|x| x.id.to_string() == uuid) {
+        Some(stream) => Ok(Json(json!(stream.latest_stats()))),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// GET /stream/:uuid/ts
+// MPEG-TS analysis of a udp input, 404 if the input isn't analyzed
+pub(crate) async fn get_stream_ts(
+    State(data): State<Arc<state::App>>,
+    Path(uuid): Path<String>,
+) -> Result<Json<ts::Report>, (StatusCode, String)> {
+    let streams_list = data.streams.lock().await;
+
+    let Some(stream) = streams_list.iter().find(|x| x.id.to_string() == uuid) else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Stream with id {} not found", uuid),
+        ));
+    };
+    match &stream.tap {
+        Some(tap) => Ok(Json(
+            tap.analyzer
+                .lock()
+                .unwrap()
+                .report(std::time::Instant::now()),
+        )),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            format!(
+                "Input of stream {} is not analyzed, only udp inputs are",
+                uuid
+            ),
+        )),
+    }
+}
+
+// GET /stream/:uuid/thumbnail
+// Latest JPEG snapshot of a stream's decoded input, 404 until the first one is written
+pub(crate) async fn get_stream_thumbnail(
+    State(data): State<Arc<state::App>>,
+    Path(uuid): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let id = {
+        let streams_list = data.streams.lock().await;
+        match streams_list.iter().find(|x| x.id.to_string() == uuid) {
+            Some(stream) => stream.id,
+            None => return Err(StatusCode::NOT_FOUND),
         }
-        None => {
-            return Err(StatusCode::NOT_FOUND);
+    };
+
+    let path = transcode::thumbnail_path(id).ok_or(StatusCode::NOT_FOUND)?;
+    match tokio::fs::read(path).await {
+        Ok(jpeg) => Ok((
+            [
+                (header::CONTENT_TYPE, "image/jpeg"),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            jpeg,
+        )),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// GET /stream/:uuid/recording
+// Recorded segments of a stream, oldest first, also after the stream was removed
+pub(crate) async fn get_stream_recordings(
+    Path(uuid): Path<String>,
+) -> Result<Json<Vec<recording::RecordingFile>>, StatusCode> {
+    let id = Uuid::parse_str(&uuid).map_err(|_| StatusCode::NOT_FOUND)?;
+    let dir = recording::dir(id);
+    if !std::path::Path::new(&dir).is_dir() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let files = tokio::task::spawn_blocking(move || recording::list(&dir))
+        .await
+        .unwrap_or_default();
+    Ok(Json(files))
+}
+
+// GET /stream/:uuid/recording/:name
+// Download of a recorded segment, streamed from disk
+pub(crate) async fn get_stream_recording(
+    Path((uuid, name)): Path<(String, String)>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let id = Uuid::parse_str(&uuid).map_err(|_| StatusCode::NOT_FOUND)?;
+    if !recording::valid_name(&name) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let path = std::path::Path::new(&recording::dir(id)).join(&name);
+    let file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let size = file
+        .metadata()
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?
+        .len();
+
+    let content_type = if name.ends_with(".ts") {
+        "video/mp2t"
+    } else {
+        "application/octet-stream"
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_LENGTH, size.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{name}\""),
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    ))
+}
+
+// GET /job
+pub(crate) async fn get_jobs(State(data): State<Arc<state::App>>) -> Json<Vec<state::JobInfo>> {
+    let jobs = data.jobs.lock().await;
+    Json(jobs.iter().map(state::JobInfo::from).collect())
+}
+
+// GET /job/:uuid
+pub(crate) async fn get_job(
+    State(data): State<Arc<state::App>>,
+    Path(uuid): Path<String>,
+) -> Result<Json<state::JobInfo>, StatusCode> {
+    let jobs = data.jobs.lock().await;
+    match jobs.iter().find(|x| x.id.to_string() == uuid) {
+        Some(job) => Ok(Json(state::JobInfo::from(job))),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// POST /job
+// Queue a file to file transcode, it runs once and ends Completed or Failed
+#[derive(serde::Deserialize)]
+pub(crate) struct AddJobParams {
+    id: Option<Uuid>,
+    name: String,
+    input: String,
+    output: String,
+    codec: String,
+    options: Option<StreamOptions>,
+    two_pass: Option<bool>,
+}
+pub(crate) async fn create_job(
+    State(data): State<Arc<state::App>>,
+    Json(payload): Json<AddJobParams>,
+) -> Result<Json<state::JobInfo>, (StatusCode, String)> {
+    if state::Codec::from_ffmpeg_name(&payload.codec).is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Unknown codec {}", payload.codec),
+        ));
+    }
+    if let Some(options) = &payload.options {
+        options
+            .validate_renditions()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    }
+
+    let mut jobs = data.jobs.lock().await;
+
+    // Same ID as an existing job, return it as is
+    let id = payload.id.unwrap_or(Uuid::new_v4());
+    if let Some(job) = jobs.iter().find(|x| x.id == id) {
+        return Ok(Json(state::JobInfo::from(job)));
+    }
+
+    let job = state::Job {
+        id,
+        name: payload.name,
+        input: payload.input,
+        output: payload.output,
+        codec: payload.codec,
+        options: payload.options,
+        two_pass: payload.two_pass.unwrap_or(false),
+        status: state::JobStatus::Waiting,
+        pass: 1,
+        pid: None,
+        device: None,
+        probe: state::InputProbe::Pending,
+        stats: None,
+        logs: Arc::new(Mutex::new(LogBuffer::new(args::Args::parse().log_lines))),
+        exit_code: None,
+        error: None,
+        started_at: None,
+        elapsed: None,
+        stop_signal_at: None,
+        killed: false,
+    };
+    let job_info = state::JobInfo::from(&job);
+    jobs.push(job);
+
+    log::info!("Job {} added", id);
+    Ok(Json(job_info))
+}
+
+// DELETE /job/:uuid?wait=true
+// Cancel a job by setting its status to JobStatus::Stopping, the monitor takes care of the rest
+// With wait, respond once the process has been reaped
+pub(crate) async fn delete_job(
+    State(data): State<Arc<state::App>>,
+    Path(uuid): Path<String>,
+    Query(params): Query<DeleteStreamParams>,
+) -> Result<Json<state::JobInfo>, StatusCode> {
+    let mut job_info = {
+        let mut jobs = data.jobs.lock().await;
+        let job = jobs
+            .iter_mut()
+            .find(|x| x.id.to_string() == uuid)
+            .ok_or(StatusCode::NOT_FOUND)?;
+        if job.status != state::JobStatus::Stopping {
+            log::info!("Cancelling job {}", job.name);
+            job.status = state::JobStatus::Stopping;
+        }
+        state::JobInfo::from(&*job)
+    };
+
+    if !params.wait.unwrap_or(false) {
+        return Ok(Json(job_info));
+    }
+
+    // Grace period, SIGKILL and reaping, with some margin
+    let timeout = tokio::time::Duration::from_millis(args::Args::parse().stop_grace_period)
+        + tokio::time::Duration::from_secs(5);
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    while tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+        let jobs = data.jobs.lock().await;
+        match jobs.iter().find(|x| x.id.to_string() == uuid) {
+            Some(job) if job.status == state::JobStatus::Stopping => {}
+            // Cancelled, or already removed by the monitor
+            _ => {
+                job_info.status = state::JobStatus::Cancelled;
+                return Ok(Json(job_info));
+            }
         }
     }
+
+    log::error!("Job {} did not stop in time", uuid);
+    Err(StatusCode::GATEWAY_TIMEOUT)
+}
+
+// GET /job/:uuid/logs?since=&limit=
+pub(crate) async fn get_job_logs(
+    State(data): State<Arc<state::App>>,
+    Path(uuid): Path<String>,
+    Query(params): Query<LogsParams>,
+) -> Result<Json<Value>, StatusCode> {
+    let logs = {
+        let jobs = data.jobs.lock().await;
+        match jobs.iter().find(|x| x.id.to_string() == uuid) {
+            Some(job) => job.logs.clone(),
+            None => return Err(StatusCode::NOT_FOUND),
+        }
+    };
+
+    let logs = logs.lock().await;
+    Ok(Json(json!(
+        logs.get(params.since, params.limit.unwrap_or(100))
+    )))
+}
+
+// GET /stream/:uuid/logs?since=&limit=
+// Buffered log lines of a stream, after sequence number `since` or the latest ones
+#[derive(serde::Deserialize)]
+pub(crate) struct LogsParams {
+    since: Option<u64>,
+    limit: Option<usize>,
+}
+pub(crate) async fn get_stream_logs(
+    State(data): State<Arc<state::App>>,
+    Path(uuid): Path<String>,
+    Query(params): Query<LogsParams>,
+) -> Result<Json<Value>, StatusCode> {
+    let logs = {
+        let streams_list = data.streams.lock().await;
+        match streams_list.iter().find(|x| x.id.to_string() == uuid) {
+            Some(stream) => stream.logs.clone(),
+            None => return Err(StatusCode::NOT_FOUND),
+        }
+    };
+
+    let logs = logs.lock().await;
+    Ok(Json(json!(
+        logs.get(params.since, params.limit.unwrap_or(100))
+    )))
+}
+
+// GET /stream/:uuid/logs/tail
+// Server-sent events with new log lines of the running process, ends when it exits
+pub(crate) async fn tail_stream_logs(
+    State(data): State<Arc<state::App>>,
+    Path(uuid): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let streams_list = data.streams.lock().await;
+
+    let rx = match streams_list.iter().find(|x| x.id.to_string() == uuid) {
+        Some(stream) => match &stream.rx {
+            Some(rx) => rx.resubscribe(),
+            // Not started yet
+            None => return Err(StatusCode::CONFLICT),
+        },
+        None => return Err(StatusCode::NOT_FOUND),
+    };
+
+    // Skip lines missed by a slow client instead of ending the tail
+    let events = BroadcastStream::new(rx).filter_map(|msg| {
+        msg.ok().map(|msg| {
+            Ok(Event::default()
+                .event(format!("{:?}", msg.error))
+                .data(msg.message))
+        })
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+// POST /probe
+// Run ffprobe against an input and return what it found
+#[derive(serde::Deserialize)]
+pub(crate) struct ProbeParams {
+    uri: String,
+    input_srt: Option<state::SrtOptions>,
+    // Seconds
+    timeout: Option<u64>,
+}
+pub(crate) async fn probe_input(
+    Json(payload): Json<ProbeParams>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let uri = match &payload.input_srt {
+        // Waiting for a caller would hold the request until the timeout
+        Some(srt) if srt.mode == Some(state::SrtMode::Listener) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Cannot probe an SRT listener input".to_string(),
+            ));
+        }
+        Some(srt) => srt.uri(&payload.uri),
+        None => payload.uri,
+    };
+
+    let timeout = payload
+        .timeout
+        .map(|t| Duration::from_secs(t.clamp(1, 60)))
+        .unwrap_or(probe::PROBE_TIMEOUT);
+
+    let output = probe::ffprobe(&uri, timeout)
+        .await
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+    match probe::parse_input_info(&output) {
+        Some(info) => Ok(Json(json!(info))),
+        None => Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Could not parse ffprobe output".to_string(),
+        )),
+    }
 }
 
 // GET /encoder
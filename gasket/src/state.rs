@@ -1,4 +1,10 @@
+use crate::alarms::Alarms;
+use crate::logs::LogBuffer;
+use crate::tap::{Receivers, Tap};
+use crate::utils;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
@@ -13,16 +19,798 @@ pub(crate) enum StreamStatus {
     Running,
     Stopping,
     Exited,
+    // One-shot input done, ffmpeg exited with status 0
+    Finished,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
 pub(crate) struct StreamOptions {
     pub(crate) pixel_format: Option<String>,
     pub(crate) bitrate: Option<String>,
     pub(crate) framerate: Option<String>,
     pub(crate) gop_size: Option<String>,
+    // Adds the Debug overlay, defaults to true when no overlays are set
     pub(crate) debug_text: Option<bool>,
+    // Burned into the video in order
+    pub(crate) overlays: Option<Vec<Overlay>>,
     pub(crate) output_format: Option<String>,
+    pub(crate) renditions: Option<Vec<Rendition>>,
+    pub(crate) packaging: Option<Packaging>,
+    pub(crate) input_srt: Option<SrtOptions>,
+    pub(crate) output_srt: Option<SrtOptions>,
+    pub(crate) input_mode: Option<InputMode>,
+    pub(crate) restart: Option<RestartPolicy>,
+    pub(crate) failover: Option<Failover>,
+    pub(crate) rate_control: Option<RateControl>,
+    pub(crate) tuning: Option<Tuning>,
+    // Output size, a missing dimension follows the aspect ratio
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    // How the source is mapped onto width x height, Stretch if not set
+    pub(crate) scaling_mode: Option<ScalingMode>,
+    // Removed from the source before scaling
+    pub(crate) crop: Option<Crop>,
+    // Pad color to letterbox Fit outputs to exactly width x height, e.g. "black"
+    pub(crate) pad: Option<String>,
+    pub(crate) audio: Option<AudioOptions>,
+    pub(crate) deinterlace: Option<Deinterlace>,
+    // How the input is converted to framerate, frames are dropped or duplicated by -r if not set
+    pub(crate) framerate_mode: Option<FramerateMode>,
+    // HDR to SDR
+    pub(crate) tonemap: Option<Tonemap>,
+    pub(crate) recording: Option<Recording>,
+    // Black, freeze and silence detection, off if not set
+    pub(crate) alarms: Option<AlarmOptions>,
+    // Relay a udp input through the MPEG-TS analyzer, see GET /stream/:uuid/ts. False if not set
+    pub(crate) analyze_input: Option<bool>,
+    // Program of an MPTS input, ffmpeg picks one by itself if not set
+    pub(crate) program: Option<ProgramSelection>,
+    // Copy the SCTE-35 cues of the input to mpegts outputs
+    pub(crate) scte35: Option<bool>,
+    // Service information and PIDs of mpegts outputs
+    pub(crate) mpegts: Option<MpegtsOptions>,
+}
+
+impl StreamOptions {
+    // Copy for API responses, SRT passphrases are masked, in the settings and in the URIs
+    pub(crate) fn redacted(&self) -> StreamOptions {
+        let mut options = self.clone();
+        for srt in [&mut options.input_srt, &mut options.output_srt]
+            .into_iter()
+            .flatten()
+        {
+            if srt.passphrase.is_some() {
+                srt.passphrase = Some(utils::REDACTED.to_string());
+            }
+        }
+        for rendition in options.renditions.iter_mut().flatten() {
+            rendition.uri = utils::redact_secrets(&rendition.uri);
+        }
+        let backups = options
+            .failover
+            .iter_mut()
+            .flat_map(|f| f.backup_inputs.iter_mut().flatten());
+        for backup in backups {
+            *backup = utils::redact_secrets(backup);
+        }
+        options
+    }
+
+    // Rung keyframes only line up when every rung gop is a multiple of the main gop
+    pub(crate) fn validate_renditions(&self) -> Result<(), String> {
+        let gop_size = self.gop_size.as_deref().unwrap_or("60");
+        let gop = parse_gop(gop_size)?;
+        for rendition in self.renditions.iter().flatten() {
+            if let Some(rendition_gop_size) = &rendition.gop_size {
+                if parse_gop(rendition_gop_size)? % gop != 0 {
+                    return Err(format!(
+                        "Rendition {} gop_size {} is not a multiple of gop_size {}",
+                        rendition.uri, rendition_gop_size, gop_size
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_gop(gop_size: &str) -> Result<u32, String> {
+    match gop_size.parse::<u32>() {
+        Ok(gop) if gop > 0 => Ok(gop),
+        _ => Err(format!("Invalid gop_size {}", gop_size)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DeinterlaceFilter {
+    Yadif,
+    Bwdif,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Deinterlace {
+    // Bwdif if not set
+    pub(crate) filter: Option<DeinterlaceFilter>,
+    // One frame per field, 1080i50 becomes 1080p50 instead of 1080p25
+    pub(crate) field_rate: Option<bool>,
+    // Also deinterlace frames that aren't flagged as interlaced
+    pub(crate) all_frames: Option<bool>,
+}
+impl Deinterlace {
+    pub(crate) fn ffmpeg_name(&self) -> &'static str {
+        match self.filter.unwrap_or(DeinterlaceFilter::Bwdif) {
+            DeinterlaceFilter::Yadif => "yadif",
+            DeinterlaceFilter::Bwdif => "bwdif",
+        }
+    }
+
+    // Shared by the CPU and CUDA variants of both filters
+    pub(crate) fn ffmpeg_args(&self) -> String {
+        let mode = if self.field_rate.unwrap_or(false) {
+            "send_field"
+        } else {
+            "send_frame"
+        };
+        let deint = if self.all_frames.unwrap_or(false) {
+            "all"
+        } else {
+            "interlaced"
+        };
+        format!("mode={mode}:deint={deint}")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FramerateMode {
+    // fps filter
+    DropDup,
+    // Blends neighbouring frames
+    Blend,
+    // Motion compensated interpolation, expensive
+    Interpolate,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TonemapAlgorithm {
+    Hable,
+    Mobius,
+    Reinhard,
+    Clip,
+}
+impl TonemapAlgorithm {
+    pub(crate) fn ffmpeg_name(&self) -> &'static str {
+        match self {
+            TonemapAlgorithm::Hable => "hable",
+            TonemapAlgorithm::Mobius => "mobius",
+            TonemapAlgorithm::Reinhard => "reinhard",
+            TonemapAlgorithm::Clip => "clip",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Tonemap {
+    // Hable if not set
+    pub(crate) algorithm: Option<TonemapAlgorithm>,
+    // Nominal peak luminance of the SDR output in nits, 100 if not set
+    pub(crate) peak: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OverlayKind {
+    // Text template, see Overlay::text
+    Text,
+    // SMPTE timecode counted from the start of the process
+    Timecode,
+    // PNG image
+    Logo,
+    // Local time and build version, the former debug_text overlay
+    Debug,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OverlayPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Overlay {
+    pub(crate) kind: OverlayKind,
+    // Text with {name}, {timestamp} and {build} placeholders, or the label in front of the timecode
+    pub(crate) text: Option<String>,
+    // PNG file on the worker
+    pub(crate) file: Option<String>,
+    // TopLeft if not set
+    pub(crate) position: Option<OverlayPosition>,
+    // Distance to the edges in pixels, 10 if not set
+    pub(crate) margin: Option<u32>,
+    // Font size, or logo width in pixels
+    pub(crate) size: Option<u32>,
+    // ffmpeg color, e.g. "white" or "yellow@0.8"
+    pub(crate) color: Option<String>,
+    // Color of a box behind the text
+    pub(crate) background: Option<String>,
+    // Font file on the worker
+    pub(crate) font: Option<String>,
+    // Logo opacity in percent, 100 if not set
+    pub(crate) opacity: Option<u32>,
+    // Timecode frame rate, the output framerate or 25 if not set
+    pub(crate) rate: Option<String>,
+}
+impl Overlay {
+    // The overlay debug_text stands for
+    pub(crate) fn debug() -> Overlay {
+        Overlay {
+            kind: OverlayKind::Debug,
+            text: None,
+            file: None,
+            position: None,
+            margin: None,
+            size: None,
+            color: None,
+            background: None,
+            font: None,
+            opacity: None,
+            rate: None,
+        }
+    }
+}
+
+// Variant names are the JSON values
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AudioCodec {
+    Copy,
+    AAC,
+    AC3,
+    Opus,
+}
+impl AudioCodec {
+    pub(crate) fn ffmpeg_name(&self) -> &'static str {
+        match self {
+            AudioCodec::Copy => "copy",
+            AudioCodec::AAC => "aac",
+            AudioCodec::AC3 => "ac3",
+            AudioCodec::Opus => "libopus",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub(crate) struct AudioOptions {
+    // AAC if not set
+    pub(crate) codec: Option<AudioCodec>,
+    pub(crate) bitrate: Option<String>,
+    // e.g. "mono", "stereo", "5.1"
+    pub(crate) channel_layout: Option<String>,
+    pub(crate) sample_rate: Option<u32>,
+    // EBU R128 integrated loudness target in LUFS, e.g. -23
+    pub(crate) loudness: Option<i32>,
+    // Input audio tracks to carry, by position among the audio streams
+    pub(crate) tracks: Option<Vec<u32>>,
+    // Input audio tracks to carry, by ISO 639-2 language tag
+    pub(crate) languages: Option<Vec<String>>,
+}
+
+// Audio stream of the input, from ffprobe
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct AudioTrack {
+    // Position among the audio streams, as used in AudioOptions::tracks. Counted in the whole
+    // input here, see InputInfo::program_audio_tracks
+    pub(crate) index: u32,
+    pub(crate) codec: Option<String>,
+    pub(crate) language: Option<String>,
+    pub(crate) channels: Option<u32>,
+    pub(crate) channel_layout: Option<String>,
+    pub(crate) sample_rate: Option<u32>,
+    // Number of the MPTS program carrying it
+    pub(crate) program: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ScalingMode {
+    // Keep the aspect ratio, inside width x height
+    Fit,
+    // Keep the aspect ratio, covering width x height and cropping the rest
+    Fill,
+    // Exactly width x height
+    Stretch,
+}
+
+// Pixels removed from each edge
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Crop {
+    pub(crate) top: Option<u32>,
+    pub(crate) bottom: Option<u32>,
+    pub(crate) left: Option<u32>,
+    pub(crate) right: Option<u32>,
+}
+
+// Variant names are the JSON values
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RateControlMode {
+    // Constant bitrate, maxrate pinned to the bitrate
+    CBR,
+    // Variable bitrate capped at max_bitrate
+    VBR,
+    // Constant quality (CRF, CQ on NVENC), bitrate is ignored
+    CRF,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RateControl {
+    pub(crate) mode: RateControlMode,
+    // Peak bitrate for VBR, e.g. "8M"
+    pub(crate) max_bitrate: Option<String>,
+    // VBV/CPB buffer in bits, defaults to one second at the peak bitrate
+    pub(crate) buffer_size: Option<String>,
+    // CRF/CQ value, lower is better
+    pub(crate) quality: Option<u32>,
+    // Fixed mpegts mux rate including null packet stuffing, for strict CBR
+    pub(crate) muxrate: Option<String>,
+}
+impl RateControl {
+    // Same settings for a ladder rung, absolute rates scaled by the rung's share of the main bitrate
+    pub(crate) fn for_rung(&self, bitrate: &str, rung_bitrate: &str) -> RateControl {
+        let ratio = match (
+            utils::parse_bitrate(bitrate),
+            utils::parse_bitrate(rung_bitrate),
+        ) {
+            (Some(main), Some(rung)) if main > 0 => Some(rung as f64 / main as f64),
+            _ => None,
+        };
+        let scale = |rate: &Option<String>| {
+            let rate = utils::parse_bitrate(rate.as_deref()?)?;
+            Some(((rate as f64 * ratio?) as u64).to_string())
+        };
+
+        RateControl {
+            mode: self.mode,
+            max_bitrate: scale(&self.max_bitrate),
+            buffer_size: scale(&self.buffer_size),
+            quality: self.quality,
+            muxrate: scale(&self.muxrate),
+        }
+    }
+}
+
+// Encoder speed/quality tradeoff, mapped to each encoder's own preset names
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Preset {
+    UltraFast,
+    Fast,
+    Medium,
+    Slow,
+    VerySlow,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Tuning {
+    pub(crate) preset: Option<Preset>,
+    // No lookahead or frame reordering delay
+    pub(crate) low_latency: Option<bool>,
+    // e.g. "high", "main", "main10"
+    pub(crate) profile: Option<String>,
+    // e.g. "4.1"
+    pub(crate) level: Option<String>,
+    pub(crate) b_frames: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RestartMode {
+    Never,
+    OnFailure,
+    Always,
+}
+
+// Local restart of the ffmpeg process when it exits, without the stream no restarts are done by the worker
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RestartPolicy {
+    pub(crate) mode: RestartMode,
+    // Consecutive restarts before giving up, unlimited if not set
+    pub(crate) max_retries: Option<u32>,
+    // First delay in milliseconds, doubled on every consecutive restart
+    pub(crate) backoff: Option<u64>,
+    // Upper bound of the delay in milliseconds
+    pub(crate) max_backoff: Option<u64>,
+}
+impl RestartPolicy {
+    pub(crate) fn should_restart(&self, exit_code: Option<i32>, retries: u32) -> bool {
+        if self.max_retries.is_some_and(|max| retries >= max) {
+            return false;
+        }
+        match self.mode {
+            RestartMode::Never => false,
+            // Killed by a signal counts as a failure
+            RestartMode::OnFailure => exit_code != Some(0),
+            RestartMode::Always => true,
+        }
+    }
+
+    pub(crate) fn delay(&self, retries: u32) -> Duration {
+        let backoff = self.backoff.unwrap_or(1000);
+        let max_backoff = self.max_backoff.unwrap_or(60_000);
+        let delay = backoff.saturating_mul(2u64.saturating_pow(retries));
+        Duration::from_millis(delay.min(max_backoff))
+    }
+}
+
+// Switch to backup inputs when the input stalls or fails, and to a slate once they are all gone
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Failover {
+    // Tried in order after the primary input
+    pub(crate) backup_inputs: Option<Vec<String>>,
+    // Seconds without new frames before switching, 5 if not set
+    pub(crate) stall_timeout: Option<u32>,
+    // Image or video file on the worker, looped with silent audio
+    pub(crate) slate: Option<String>,
+}
+impl Failover {
+    pub(crate) fn stall_timeout(&self) -> Duration {
+        Duration::from_secs(self.stall_timeout.unwrap_or(5) as u64)
+    }
+
+    // Source to switch to when `source` is gone, None once the backups and the slate are used up
+    pub(crate) fn next(&self, source: InputSource) -> Option<InputSource> {
+        let backups = self.backup_inputs.as_ref().map_or(0, |b| b.len());
+        let next = match source {
+            InputSource::Primary => InputSource::Backup(0),
+            InputSource::Backup(i) => InputSource::Backup(i + 1),
+            InputSource::Slate => return None,
+        };
+        match next {
+            InputSource::Backup(i) if i < backups => Some(next),
+            _ => self.slate.as_ref().map(|_| InputSource::Slate),
+        }
+    }
+
+    // Inputs preferred over `source`, in order
+    pub(crate) fn preferred(&self, source: InputSource) -> Vec<InputSource> {
+        let backups = self.backup_inputs.as_ref().map_or(0, |b| b.len());
+        std::iter::once(InputSource::Primary)
+            .chain((0..backups).map(InputSource::Backup))
+            .filter(|s| *s < source)
+            .collect()
+    }
+}
+
+// Input the current process reads from, ordered by preference
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum InputSource {
+    Primary,
+    Backup(usize),
+    Slate,
+}
+impl std::fmt::Display for InputSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputSource::Primary => write!(f, "primary input"),
+            InputSource::Backup(i) => write!(f, "backup input {}", i + 1),
+            InputSource::Slate => write!(f, "slate"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum InputMode {
+    // Loop a file forever at native framerate (-stream_loop -1 -re)
+    Loop,
+    // Network source, read as it arrives
+    Live,
+    // Read a file once as fast as possible, then finish
+    Once,
+}
+impl InputMode {
+    // Default when the stream doesn't set one: network sources and HLS playlists are live, files
+    // are looped, including files served over http(s)
+    pub(crate) fn from_uri(uri: &str) -> InputMode {
+        let Some((scheme, rest)) = uri.split_once("://") else {
+            return InputMode::Loop;
+        };
+        match scheme.to_lowercase().as_str() {
+            "udp" | "rtp" | "srt" | "rtmp" | "rtmps" | "rtsp" | "tcp" => InputMode::Live,
+            "http" | "https" => {
+                let path = rest.split(['?', '#']).next().unwrap_or_default();
+                if path.to_lowercase().ends_with(".m3u8") {
+                    InputMode::Live
+                } else {
+                    InputMode::Loop
+                }
+            }
+            _ => InputMode::Loop,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SrtMode {
+    Caller,
+    Listener,
+    Rendezvous,
+}
+impl SrtMode {
+    pub(crate) fn ffmpeg_name(&self) -> &'static str {
+        match self {
+            SrtMode::Caller => "caller",
+            SrtMode::Listener => "listener",
+            SrtMode::Rendezvous => "rendezvous",
+        }
+    }
+}
+
+// SRT connection settings, added to the input/output URI as query parameters
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SrtOptions {
+    pub(crate) mode: Option<SrtMode>,
+    // Latency in milliseconds
+    pub(crate) latency: Option<u32>,
+    pub(crate) passphrase: Option<String>,
+    pub(crate) streamid: Option<String>,
+}
+impl SrtOptions {
+    // Build the srt:// URI ffmpeg expects from a host:port or srt:// URI
+    pub(crate) fn uri(&self, uri: &str) -> String {
+        let mut uri = if uri.contains("://") {
+            uri.to_string()
+        } else {
+            format!("srt://{uri}")
+        };
+
+        let mut params: Vec<String> = Vec::new();
+        if let Some(mode) = self.mode {
+            params.push(format!("mode={}", mode.ffmpeg_name()));
+        }
+        // ffmpeg takes the latency in microseconds
+        if let Some(latency) = self.latency {
+            params.push(format!("latency={}", latency as u64 * 1000));
+        }
+        if let Some(passphrase) = &self.passphrase {
+            params.push(format!("passphrase={}", utils::url_encode(passphrase)));
+        }
+        if let Some(streamid) = &self.streamid {
+            params.push(format!("streamid={}", utils::url_encode(streamid)));
+        }
+
+        for param in params {
+            uri.push(if uri.contains('?') { '&' } else { '?' });
+            uri.push_str(&param);
+        }
+        uri
+    }
+}
+
+// Extra rung of an adaptive bitrate ladder, encoded from the same decode as the main output
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Rendition {
+    // Output URI of the rung (udp://host_ip:port)
+    pub(crate) uri: String,
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    pub(crate) bitrate: Option<String>,
+    // Must be a multiple of the main gop size to keep keyframes aligned
+    pub(crate) gop_size: Option<String>,
+}
+
+// Variant names are the JSON values
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PackagingFormat {
+    HLS,
+    CMAF,
+    DASH,
+}
+
+// Segmented output, the output URI is a local directory or an http(s) base URL written to with PUT
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Packaging {
+    pub(crate) format: PackagingFormat,
+    // Segment length in seconds
+    pub(crate) segment_duration: Option<u32>,
+    // Number of segments kept in the playlist, 0 keeps all of them
+    pub(crate) window_size: Option<u32>,
+}
+impl Packaging {
+    pub(crate) fn playlist_name(&self) -> &'static str {
+        match self.format {
+            PackagingFormat::HLS | PackagingFormat::CMAF => "index.m3u8",
+            PackagingFormat::DASH => "manifest.mpd",
+        }
+    }
+
+    // Location of the playlist/manifest inside the output directory
+    pub(crate) fn playlist(&self, output: &str) -> String {
+        format!("{}/{}", output.trim_end_matches('/'), self.playlist_name())
+    }
+}
+
+// Copy of the encoded output in time based segments under --recording-dir/{uuid}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Recording {
+    // Segment length in seconds, 600 if not set
+    pub(crate) segment_duration: Option<u32>,
+    // strftime template of the segment names, the extension picks the container
+    pub(crate) filename: Option<String>,
+    // Oldest segments are removed past either limit, see monitor::clean_recordings
+    pub(crate) max_segments: Option<usize>,
+    pub(crate) max_size_mb: Option<u64>,
+}
+impl Recording {
+    pub(crate) fn segment_duration(&self) -> u32 {
+        self.segment_duration.unwrap_or(600)
+    }
+
+    pub(crate) fn filename(&self) -> &str {
+        self.filename.as_deref().unwrap_or("%Y%m%d-%H%M%S.ts")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AlarmKind {
+    Black,
+    Freeze,
+    Silence,
+}
+
+// Condition has to last duration seconds before its alarm is raised
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Detector {
+    // On unless false
+    pub(crate) enabled: Option<bool>,
+    pub(crate) duration: Option<u32>,
+    // Black: percent of dark pixels in a picture, freeze and silence: noise tolerance in dB
+    pub(crate) threshold: Option<i32>,
+}
+
+// Detectors run on a separate output of the stream's ffmpeg, see alarms.rs. They watch the
+// decoded input, so a fault in the encoder or the output doesn't raise an alarm
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub(crate) struct AlarmOptions {
+    // False turns all detection off
+    pub(crate) enabled: Option<bool>,
+    pub(crate) black: Option<Detector>,
+    pub(crate) freeze: Option<Detector>,
+    pub(crate) silence: Option<Detector>,
+}
+impl AlarmOptions {
+    // Duration and threshold of an enabled detector, defaults filled in
+    pub(crate) fn detector(&self, kind: AlarmKind) -> Option<(u32, i32)> {
+        if self.enabled == Some(false) {
+            return None;
+        }
+
+        let (detector, duration, threshold) = match kind {
+            AlarmKind::Black => (&self.black, 2, 98),
+            AlarmKind::Freeze => (&self.freeze, 5, -60),
+            AlarmKind::Silence => (&self.silence, 5, -60),
+        };
+        match detector {
+            Some(d) if d.enabled == Some(false) => None,
+            Some(d) => Some((
+                d.duration.unwrap_or(duration),
+                d.threshold.unwrap_or(threshold),
+            )),
+            // Only the detectors that are set run
+            None => None,
+        }
+    }
+}
+
+// By number, or by the service name found when probing the input
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ProgramSelection {
+    pub(crate) number: Option<u32>,
+    pub(crate) service_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct MpegtsOptions {
+    // Stream name and gasket-{build} if not set
+    pub(crate) service_name: Option<String>,
+    pub(crate) service_provider: Option<String>,
+    // Program number of the service
+    pub(crate) service_id: Option<u32>,
+    pub(crate) transport_stream_id: Option<u32>,
+    pub(crate) original_network_id: Option<u32>,
+    pub(crate) pmt_pid: Option<u32>,
+    // Streams without a PID of their own are numbered from here in output order: video, audio, SCTE-35
+    pub(crate) start_pid: Option<u32>,
+    pub(crate) video_pid: Option<u32>,
+    // In the order of the output audio tracks
+    pub(crate) audio_pids: Option<Vec<u32>>,
+}
+
+// What ffprobe found in the input
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub(crate) struct InputInfo {
+    // Container format, e.g. "mpegts"
+    pub(crate) format: Option<String>,
+    // Codec of the first video stream
+    pub(crate) video_codec: Option<String>,
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    // Frames per second of the first video stream
+    pub(crate) framerate: Option<f64>,
+    // Seconds, only known for files
+    pub(crate) duration: Option<f64>,
+    pub(crate) programs: Vec<Program>,
+    pub(crate) audio_tracks: Vec<AudioTrack>,
+    pub(crate) data_streams: Vec<DataStream>,
+}
+
+impl InputInfo {
+    pub(crate) fn program_by_name(&self, service_name: &str) -> Option<&Program> {
+        self.programs
+            .iter()
+            .find(|p| p.service_name.as_deref() == Some(service_name))
+    }
+
+    // Program a selection refers to, None if a service name isn't found
+    pub(crate) fn program_number(&self, selection: &ProgramSelection) -> Option<u32> {
+        match (selection.number, selection.service_name.as_deref()) {
+            (Some(number), _) => Some(number),
+            (None, Some(name)) => self.program_by_name(name).map(|p| p.program_num),
+            (None, None) => None,
+        }
+    }
+
+    // Audio tracks numbered like ffmpeg's p:N:a:i once a program is selected
+    pub(crate) fn program_audio_tracks(&self, program: Option<u32>) -> Vec<AudioTrack> {
+        let Some(program) = program else {
+            return self.audio_tracks.clone();
+        };
+        self.audio_tracks
+            .iter()
+            .filter(|t| t.program == Some(program))
+            .enumerate()
+            .map(|(index, track)| AudioTrack {
+                index: index as u32,
+                ..track.clone()
+            })
+            .collect()
+    }
+
+    // SCTE-35 streams in a program, or in the whole input
+    pub(crate) fn scte35_streams(&self, program: Option<u32>) -> Vec<&DataStream> {
+        self.data_streams
+            .iter()
+            .filter(|d| d.codec.as_deref() == Some("scte_35"))
+            .filter(|d| program.is_none() || d.program == program)
+            .collect()
+    }
+}
+
+// Data stream of the input, e.g. SCTE-35 cues, from ffprobe
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DataStream {
+    // Position among all streams of the input
+    pub(crate) index: u32,
+    pub(crate) codec: Option<String>,
+    pub(crate) pid: Option<u32>,
+    // Number of the MPTS program carrying it
+    pub(crate) program: Option<u32>,
+}
+
+// MPEG-TS program (service) of the input, from ffprobe
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Program {
+    pub(crate) program_num: u32,
+    pub(crate) pmt_pid: Option<u32>,
+    pub(crate) pcr_pid: Option<u32>,
+    pub(crate) service_name: Option<String>,
+    pub(crate) service_provider: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum InputProbe {
+    Pending,
+    Running,
+    // None if probing failed
+    Done(Option<InputInfo>),
 }
 
 // Internal stream state
@@ -53,6 +841,85 @@ pub(crate) struct Stream {
 
     // Log channel
     pub(crate) rx: Option<tokio::sync::broadcast::Receiver<StreamLogMessage>>,
+
+    // Latest ffmpeg progress snapshot
+    pub(crate) stats: Option<tokio::sync::watch::Receiver<Option<StreamStats>>>,
+
+    // Recent log lines, kept across restarts of the process
+    pub(crate) logs: Arc<Mutex<LogBuffer>>,
+
+    // Detection alarms parsed from the log lines, history kept across restarts
+    pub(crate) alarms: Arc<Mutex<Alarms>>,
+
+    // Input relay with the MPEG-TS analyzer, kept across restarts
+    pub(crate) tap: Option<Tap>,
+
+    // Set by DELETE, the stream is never restarted after that
+    pub(crate) stop_requested: bool,
+
+    // Total number of local restarts
+    pub(crate) restarts: u32,
+
+    // Restarts since the process last ran long enough to be considered healthy
+    pub(crate) retries: u32,
+
+    // Exit code of the last process, None if it was killed by a signal
+    pub(crate) last_exit_code: Option<i32>,
+
+    // When the current process was started
+    pub(crate) started_at: Option<Instant>,
+
+    // Waiting streams are not started before this
+    pub(crate) restart_at: Option<Instant>,
+
+    // When the stream became Finished, it is removed --finished-ttl seconds later
+    pub(crate) finished_at: Option<Instant>,
+
+    // When SIGTERM was sent to the stopping process
+    pub(crate) stop_signal_at: Option<Instant>,
+
+    // SIGKILL sent after the grace period ran out
+    pub(crate) killed: bool,
+
+    // Accelerator device the current process is pinned to
+    pub(crate) device: Option<usize>,
+
+    // Input codecs and tracks, probed once before the first start
+    pub(crate) probe: InputProbe,
+
+    // Input of the current process, see Failover
+    pub(crate) source: InputSource,
+
+    // Set when the process is stopped to switch inputs
+    pub(crate) switch_to: Option<InputSource>,
+
+    // Last reason the input was switched
+    pub(crate) last_switch: Option<String>,
+
+    // Last frame count seen and when it changed, to detect a stalled input
+    pub(crate) last_frame: Option<(u64, Instant)>,
+
+    // Preferred inputs are checked again after this while on a backup or the slate
+    pub(crate) failback_at: Option<Instant>,
+}
+
+// Runtime stats parsed from ffmpeg -progress
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub(crate) struct StreamStats {
+    pub(crate) frame: u64,
+    pub(crate) fps: f64,
+    // Output bitrate in kbit/s
+    pub(crate) bitrate: f64,
+    // Bytes written so far
+    pub(crate) total_size: u64,
+    // Output timestamp in microseconds
+    pub(crate) out_time_us: i64,
+    pub(crate) dup_frames: u64,
+    pub(crate) drop_frames: u64,
+    // Encoding speed relative to realtime
+    pub(crate) speed: f64,
+    // Unix timestamp of the snapshot
+    pub(crate) updated: u64,
 }
 
 // DTO/Clonable Stream info
@@ -65,6 +932,11 @@ pub(crate) struct StreamInfo {
     pub(crate) codec: String,
     pub(crate) status: StreamStatus,
     pub(crate) pid: Option<u32>,
+    pub(crate) playlist: Option<String>,
+    pub(crate) restarts: u32,
+    pub(crate) last_exit_code: Option<i32>,
+    pub(crate) device: Option<usize>,
+    pub(crate) audio_tracks: Option<Vec<AudioTrack>>,
 }
 
 // Convert Stream to StreamInfo
@@ -73,15 +945,108 @@ impl From<&Stream> for StreamInfo {
         return StreamInfo {
             id: stream.id,
             name: stream.name.clone(),
-            input: stream.input.clone(),
-            output: stream.output.clone(),
+            input: utils::redact_secrets(&stream.input),
+            output: utils::redact_secrets(&stream.output),
             codec: stream.codec.clone(),
             status: stream.status.clone(),
             pid: stream.pid,
+            playlist: stream.playlist(),
+            restarts: stream.restarts,
+            last_exit_code: stream.last_exit_code,
+            device: stream.device,
+            audio_tracks: stream.audio_tracks(),
         };
     }
 }
 
+impl Stream {
+    pub(crate) fn input_info(&self) -> Option<&InputInfo> {
+        match &self.probe {
+            InputProbe::Done(info) => info.as_ref(),
+            _ => None,
+        }
+    }
+
+    // Input audio tracks numbered as AudioOptions::tracks refers to them
+    pub(crate) fn audio_tracks(&self) -> Option<Vec<AudioTrack>> {
+        let info = self.input_info()?;
+        let program = self
+            .options
+            .as_ref()
+            .and_then(|o| o.program.as_ref())
+            .and_then(|selection| info.program_number(selection));
+        Some(info.program_audio_tracks(program))
+    }
+
+    // Probing a listener input would take the caller's connection
+    pub(crate) fn probe_input(&self) -> bool {
+        let srt_listener = self
+            .options
+            .as_ref()
+            .and_then(|o| o.input_srt.as_ref())
+            .is_some_and(|srt| srt.mode == Some(SrtMode::Listener));
+        !srt_listener && !self.input.contains("listen=1")
+    }
+
+    pub(crate) fn latest_stats(&self) -> Option<StreamStats> {
+        self.stats.as_ref().and_then(|rx| rx.borrow().clone())
+    }
+
+    pub(crate) fn input_mode(&self) -> InputMode {
+        self.options
+            .as_ref()
+            .and_then(|o| o.input_mode)
+            .unwrap_or(InputMode::from_uri(&self.input))
+    }
+
+    // Input URI as passed to ffmpeg, with SRT settings or through the analyzer relay
+    pub(crate) fn input_uri(&self) -> String {
+        match self.options.as_ref().and_then(|o| o.input_srt.as_ref()) {
+            Some(srt) => srt.uri(&self.input),
+            None => self.primary_input(),
+        }
+    }
+
+    fn primary_input(&self) -> String {
+        match &self.tap {
+            Some(tap) => tap.uri.clone(),
+            None => self.input.clone(),
+        }
+    }
+
+    pub(crate) fn analyze_input(&self) -> bool {
+        self.options
+            .as_ref()
+            .and_then(|o| o.analyze_input)
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn failover(&self) -> Option<&Failover> {
+        self.options.as_ref().and_then(|o| o.failover.as_ref())
+    }
+
+    // URI or file of an input source, SRT settings only apply to the primary input
+    pub(crate) fn source_input(&self, source: InputSource) -> Option<String> {
+        let failover = self.failover();
+        match source {
+            InputSource::Primary => Some(self.primary_input()),
+            InputSource::Backup(i) => failover
+                .and_then(|f| f.backup_inputs.as_ref())
+                .and_then(|b| b.get(i))
+                .cloned(),
+            InputSource::Slate => failover.and_then(|f| f.slate.clone()),
+        }
+    }
+
+    // Playlist location when the stream is packaged as HLS/CMAF/DASH
+    pub(crate) fn playlist(&self) -> Option<String> {
+        self.options
+            .as_ref()
+            .and_then(|o| o.packaging.as_ref())
+            .map(|p| p.playlist(&self.output))
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd)]
 pub(crate) enum StreamLogLevel {
     Stdout,
@@ -94,6 +1059,141 @@ pub(crate) struct StreamLogMessage {
     pub(crate) stream_id: Uuid,
     pub(crate) message: String,
     pub(crate) error: StreamLogLevel,
+    // Set on the Exit message when ffmpeg exited normally
+    pub(crate) exit_code: Option<i32>,
+}
+
+///
+///
+/// Job state
+///
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum JobStatus {
+    Waiting,
+    Running,
+    // Last pass exited with status 0
+    Completed,
+    Failed,
+    // Cancelled by DELETE, the process is being stopped
+    Stopping,
+    // Process reaped after a cancel, removed by the monitor
+    Cancelled,
+}
+
+// File to file transcode that runs to the end once, never restarted by the worker
+pub(crate) struct Job {
+    pub(crate) id: Uuid,
+    pub(crate) name: String,
+    pub(crate) input: String,
+    pub(crate) output: String,
+    pub(crate) codec: String,
+    pub(crate) options: Option<StreamOptions>,
+    // Analysis pass before the encode, only the software encoders use it
+    pub(crate) two_pass: bool,
+    pub(crate) status: JobStatus,
+    // Current pass, 1 or 2
+    pub(crate) pass: u8,
+    pub(crate) pid: Option<u32>,
+    pub(crate) device: Option<usize>,
+    // Input duration for the progress, and the codec for hardware decoding
+    pub(crate) probe: InputProbe,
+    pub(crate) stats: Option<tokio::sync::watch::Receiver<Option<StreamStats>>>,
+    pub(crate) logs: Arc<Mutex<LogBuffer>>,
+    pub(crate) exit_code: Option<i32>,
+    // Last ffmpeg error line of a failed job
+    pub(crate) error: Option<String>,
+    pub(crate) started_at: Option<Instant>,
+    // Seconds from the start of the first pass to the end of the last one
+    pub(crate) elapsed: Option<u64>,
+    // When SIGTERM was sent to the stopping process
+    pub(crate) stop_signal_at: Option<Instant>,
+    // SIGKILL sent after the grace period ran out
+    pub(crate) killed: bool,
+}
+
+// DTO of a job, see GET /job
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct JobInfo {
+    pub(crate) id: Uuid,
+    pub(crate) name: String,
+    pub(crate) input: String,
+    pub(crate) output: String,
+    pub(crate) codec: String,
+    pub(crate) options: Option<StreamOptions>,
+    pub(crate) two_pass: bool,
+    pub(crate) status: JobStatus,
+    pub(crate) pass: u8,
+    // Percent of all passes, None until the input duration is known
+    pub(crate) progress: Option<f64>,
+    pub(crate) device: Option<usize>,
+    pub(crate) stats: Option<StreamStats>,
+    pub(crate) exit_code: Option<i32>,
+    pub(crate) error: Option<String>,
+    pub(crate) elapsed: Option<u64>,
+}
+
+impl From<&Job> for JobInfo {
+    fn from(job: &Job) -> JobInfo {
+        JobInfo {
+            id: job.id,
+            name: job.name.clone(),
+            input: utils::redact_secrets(&job.input),
+            output: utils::redact_secrets(&job.output),
+            codec: job.codec.clone(),
+            options: job.options.as_ref().map(StreamOptions::redacted),
+            two_pass: job.two_pass,
+            status: job.status,
+            pass: job.pass,
+            progress: job.progress(),
+            device: job.device,
+            stats: job.latest_stats(),
+            exit_code: job.exit_code,
+            error: job.error.clone(),
+            elapsed: job.elapsed(),
+        }
+    }
+}
+
+impl Job {
+    pub(crate) fn passes(&self) -> u8 {
+        if self.two_pass {
+            2
+        } else {
+            1
+        }
+    }
+
+    pub(crate) fn input_info(&self) -> Option<&InputInfo> {
+        match &self.probe {
+            InputProbe::Done(info) => info.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn latest_stats(&self) -> Option<StreamStats> {
+        self.stats.as_ref().and_then(|rx| rx.borrow().clone())
+    }
+
+    // Every pass reads the whole input, so each one is an equal share
+    pub(crate) fn progress(&self) -> Option<f64> {
+        match self.status {
+            JobStatus::Completed => return Some(100.0),
+            JobStatus::Waiting => return Some(0.0),
+            _ => {}
+        }
+
+        let duration_us = self.input_info()?.duration? * 1_000_000.0;
+        let out_time_us = self.latest_stats().map_or(0, |s| s.out_time_us.max(0)) as f64;
+        let pass = (out_time_us / duration_us).min(1.0);
+        let done = (self.pass - 1) as f64 + pass;
+        Some((done / self.passes() as f64 * 1000.0).round() / 10.0)
+    }
+
+    pub(crate) fn elapsed(&self) -> Option<u64> {
+        self.elapsed
+            .or(self.started_at.map(|t| t.elapsed().as_secs()))
+    }
 }
 
 ///
@@ -104,34 +1204,26 @@ pub(crate) struct StreamLogMessage {
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub(crate) struct EncoderStats {
     pub utilization: u32,
-    pub devices: Vec<u32>,
+    pub devices: Vec<DeviceStats>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub(crate) struct DeviceStats {
+    pub utilization: u32,
+    // Encode sessions open on the device, None if the backend can't tell
+    pub sessions: Option<u32>,
 }
 
+// Variant names are the JSON values
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
 pub(crate) enum Encoder {
     NVENC,
     VideoToolbox,
     U30,
     NIT2A,
-}
-impl Encoder {
-    pub fn all() -> Vec<Encoder> {
-        return vec![
-            Encoder::NVENC,
-            Encoder::VideoToolbox,
-            Encoder::U30,
-            Encoder::NIT2A,
-        ];
-    }
-
-    pub fn ffmpeg_name(&self) -> &'static str {
-        match self {
-            Encoder::NVENC => "nvenc",
-            Encoder::VideoToolbox => "videotoolbox",
-            Encoder::U30 => "mpsoc_vcu",
-            Encoder::NIT2A => "ni_quadra",
-        }
-    }
+    QSV,
+    VAAPI,
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
@@ -151,6 +1243,9 @@ impl Codec {
     pub fn all() -> Vec<Codec> {
         return vec![Codec::H264, Codec::H265, Codec::AV1];
     }
+    pub fn from_ffmpeg_name(name: &str) -> Option<Codec> {
+        Codec::all().into_iter().find(|c| c.ffmpeg_name() == name)
+    }
 }
 
 ///
@@ -167,15 +1262,19 @@ pub(crate) enum ServerStatus {
 pub(crate) struct App {
     // Streams (array)
     pub(crate) streams: Mutex<Vec<Stream>>,
+    pub(crate) jobs: Mutex<Vec<Job>>,
     pub(crate) server_status: Mutex<ServerStatus>,
     pub(crate) encoder_status: Mutex<EncoderStats>,
     pub(crate) encoder: Mutex<Option<Encoder>>,
     pub(crate) codecs: Mutex<Vec<Codec>>,
+    // Input sockets of the analyzed streams, shared by streams reading the same address
+    pub(crate) taps: Mutex<Receivers>,
 }
 
 pub(crate) fn new_app() -> App {
     return App {
         streams: Mutex::new(Vec::new()),
+        jobs: Mutex::new(Vec::new()),
         server_status: Mutex::new(ServerStatus::Starting),
         encoder_status: Mutex::new(EncoderStats {
             utilization: 100,
@@ -183,5 +1282,31 @@ pub(crate) fn new_app() -> App {
         }),
         encoder: Mutex::new(None),
         codecs: Mutex::new(Vec::new()),
+        taps: Mutex::new(Receivers::default()),
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infers_the_input_mode_from_the_uri() {
+        for uri in [
+            "udp://239.0.0.1:5000",
+            "SRT://10.0.0.1:9000?mode=caller",
+            "rtmps://live.example.com/app/key",
+            "https://cdn.example.com/live/index.m3u8",
+            "http://cdn.example.com/live/INDEX.M3U8?token=abc",
+        ] {
+            assert_eq!(InputMode::from_uri(uri), InputMode::Live, "{uri}");
+        }
+        for uri in [
+            "/media/clip.mp4",
+            "https://cdn.example.com/media/clip.mp4",
+            "http://cdn.example.com/media/clip.mp4?name=index.m3u8",
+        ] {
+            assert_eq!(InputMode::from_uri(uri), InputMode::Loop, "{uri}");
+        }
+    }
+}
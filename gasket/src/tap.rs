@@ -0,0 +1,267 @@
+use crate::ts::Analyzer;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+use tokio::net::UdpSocket;
+
+// ffmpeg options that still apply once it reads from the relay, anything about the
+// original socket (sources, localaddr, reuse, ...) is dropped
+const RELAY_OPTIONS: [&str; 4] = ["fifo_size", "overrun_nonfatal", "buffer_size", "timeout"];
+
+// Never the source of a relayed datagram, see reserve_port
+const DISCARD: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9);
+
+// Address ffmpeg would listen on for a udp:// input: host, port and multicast interface
+type ListenAddress = (Ipv4Addr, u16, Option<Ipv4Addr>);
+
+// Socket on an input address, analyzed once and relayed to every stream reading it
+struct Receiver {
+    address: ListenAddress,
+    analyzer: Arc<Mutex<Analyzer>>,
+    // Loopback ports of the taps, one per stream
+    targets: Arc<Mutex<Vec<SocketAddr>>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// Receivers of the running taps, a receiver goes away with the last tap using it
+#[derive(Default)]
+pub(crate) struct Receivers(Vec<Weak<Receiver>>);
+
+// A UDP input received by the worker, analyzed and relayed to ffmpeg on the loopback
+pub(crate) struct Tap {
+    // What ffmpeg reads instead of the input
+    pub(crate) uri: String,
+    pub(crate) analyzer: Arc<Mutex<Analyzer>>,
+    receiver: Arc<Receiver>,
+    target: SocketAddr,
+    // Keeps the relay port ours while ffmpeg restarts
+    _reserved: Socket,
+}
+
+impl Drop for Tap {
+    fn drop(&mut self) {
+        self.receiver
+            .targets
+            .lock()
+            .unwrap()
+            .retain(|target| *target != self.target);
+    }
+}
+
+fn listen_address(input: &str) -> Option<ListenAddress> {
+    let rest = input.strip_prefix("udp://")?;
+    let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let (host, port) = address.rsplit_once(':')?;
+    let host = host.trim_start_matches('@');
+    let host = if host.is_empty() {
+        Ipv4Addr::UNSPECIFIED
+    } else {
+        host.parse().ok()?
+    };
+    let localaddr = query
+        .split('&')
+        .find_map(|option| option.strip_prefix("localaddr="))
+        .and_then(|addr| addr.parse().ok());
+    Some((host, port.parse().ok()?, localaddr))
+}
+
+// ffmpeg has to share the reserved port, so reuse is always set
+fn relay_query(input: &str) -> String {
+    let query = input.split_once('?').map_or("", |(_, query)| query);
+    let mut options: Vec<&str> = query
+        .split('&')
+        .filter(|option| {
+            RELAY_OPTIONS
+                .iter()
+                .any(|name| option.split('=').next() == Some(*name))
+        })
+        .collect();
+    options.push("reuse=1");
+    format!("?{}", options.join("&"))
+}
+
+// Other receivers of the input on this host (ffmpeg, a second worker, ...) can bind it too
+fn bind_reusable(address: SocketAddrV4) -> std::io::Result<Socket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    Ok(socket)
+}
+
+// Loopback port for ffmpeg, kept bound so nothing else takes it between restarts. ffmpeg binds
+// it with reuse=1, and as this socket is connected elsewhere the relayed datagrams only reach ffmpeg
+fn reserve_port() -> std::io::Result<(Socket, SocketAddr)> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into())?;
+    socket.connect(&DISCARD.into())?;
+    let port = socket
+        .local_addr()?
+        .as_socket()
+        .map(|address| address.port())
+        .unwrap_or_default();
+    Ok((socket, SocketAddr::from((Ipv4Addr::LOCALHOST, port))))
+}
+
+fn start_receiver(address: ListenAddress) -> std::io::Result<Receiver> {
+    let (host, port, localaddr) = address;
+
+    // Like ffmpeg, a unicast input listens on the port on every interface
+    let socket =
+        UdpSocket::from_std(bind_reusable(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port))?.into())?;
+    if host.is_multicast() {
+        socket.join_multicast_v4(host, localaddr.unwrap_or(Ipv4Addr::UNSPECIFIED))?;
+    }
+    let relay = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    relay.set_nonblocking(true)?;
+    let relay = UdpSocket::from_std(relay)?;
+
+    let analyzer = Arc::new(Mutex::new(Analyzer::new()));
+    let targets: Arc<Mutex<Vec<SocketAddr>>> = Arc::new(Mutex::new(Vec::new()));
+    let task_analyzer = analyzer.clone();
+    let task_targets = targets.clone();
+    let task = tokio::spawn(async move {
+        let mut buf = vec![0u8; 65536];
+        loop {
+            let len = match socket.recv(&mut buf).await {
+                Ok(len) => len,
+                Err(e) => {
+                    log::warn!("Input analysis receive error: {}", e);
+                    continue;
+                }
+            };
+            task_analyzer
+                .lock()
+                .unwrap()
+                .push(&buf[..len], Instant::now());
+            let targets = task_targets.lock().unwrap().clone();
+            for target in targets {
+                // Nobody listens while ffmpeg restarts, those datagrams are dropped
+                let _ = relay.send_to(&buf[..len], target).await;
+            }
+        }
+    });
+
+    Ok(Receiver {
+        address,
+        analyzer,
+        targets,
+        task,
+    })
+}
+
+// Relay the input through its receiver, shared with any other stream reading the same
+// address. None if the input isn't plain IPv4 udp or it can't be bound
+pub(crate) fn start(receivers: &mut Receivers, input: &str) -> Option<Tap> {
+    let address = listen_address(input)?;
+
+    receivers.0.retain(|receiver| receiver.strong_count() > 0);
+    let receiver = match receivers
+        .0
+        .iter()
+        .filter_map(Weak::upgrade)
+        .find(|receiver| receiver.address == address)
+    {
+        Some(receiver) => receiver,
+        None => match start_receiver(address) {
+            Ok(receiver) => {
+                let receiver = Arc::new(receiver);
+                receivers.0.push(Arc::downgrade(&receiver));
+                receiver
+            }
+            Err(e) => {
+                log::error!("Could not bind input {} for analysis: {}", input, e);
+                return None;
+            }
+        },
+    };
+
+    let (reserved, target) = match reserve_port() {
+        Ok(reserved) => reserved,
+        Err(e) => {
+            log::error!("Could not reserve a relay port for {}: {}", input, e);
+            return None;
+        }
+    };
+    receiver.targets.lock().unwrap().push(target);
+
+    let uri = format!("udp://{}{}", target, relay_query(input));
+    log::info!("Analyzing input {}, relayed to {}", input, uri);
+    Some(Tap {
+        uri,
+        analyzer: receiver.analyzer.clone(),
+        receiver,
+        target,
+        _reserved: reserved,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // What ffmpeg does for udp://127.0.0.1:port?reuse=1
+    fn bind_like_ffmpeg(port: u16) -> UdpSocket {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+        socket.set_reuse_address(true).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        socket
+            .bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into())
+            .unwrap();
+        UdpSocket::from_std(socket.into()).unwrap()
+    }
+
+    #[test]
+    fn relays_only_the_ffmpeg_options() {
+        assert_eq!(
+            relay_query("udp://239.1.1.1:5000?localaddr=10.0.0.2&fifo_size=100000&reuse=1"),
+            "?fifo_size=100000&reuse=1"
+        );
+        assert_eq!(relay_query("udp://@:5000"), "?reuse=1");
+    }
+
+    #[tokio::test]
+    async fn streams_on_one_address_share_the_receiver() {
+        let input = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = input.local_addr().unwrap().port();
+        drop(input);
+        let uri = format!("udp://127.0.0.1:{port}");
+
+        let mut receivers = Receivers::default();
+        let first = start(&mut receivers, &uri).unwrap();
+        let second = start(&mut receivers, &uri).unwrap();
+        assert!(Arc::ptr_eq(&first.receiver, &second.receiver));
+        assert_ne!(first.target, second.target);
+
+        // The reserved port can still be bound by ffmpeg, and the relay reaches it
+        let ffmpeg = bind_like_ffmpeg(first.target.port());
+        let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let packet = [0x47u8; 188];
+        sender.send_to(&packet, ("127.0.0.1", port)).await.unwrap();
+        let mut buf = [0u8; 2048];
+        let len = tokio::time::timeout(Duration::from_secs(2), ffmpeg.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..len], &packet);
+
+        // The receiver stops with the last tap
+        drop(first);
+        assert_eq!(second.receiver.targets.lock().unwrap().len(), 1);
+        drop(second);
+        assert!(receivers
+            .0
+            .iter()
+            .all(|receiver| receiver.strong_count() == 0));
+    }
+}
@@ -1,10 +1,18 @@
 use crate::args;
-use crate::state::{Codec, Encoder, EncoderStats, StreamLogLevel, StreamLogMessage, StreamOptions};
+use crate::backend::{self, EncoderBackend};
+use crate::progress::ProgressParser;
+use crate::recording;
+use crate::state::{
+    AlarmKind, AlarmOptions, AudioCodec, AudioOptions, Codec, Crop, Deinterlace, FramerateMode,
+    InputInfo, InputMode, InputSource, Job, MpegtsOptions, Overlay, OverlayKind, OverlayPosition,
+    Packaging, PackagingFormat, ProgramSelection, RateControl, Recording, Rendition, ScalingMode,
+    SrtOptions, StreamLogLevel, StreamLogMessage, StreamOptions, StreamStats, Tonemap,
+};
 use crate::utils::{self, get_ffmpeg_path};
 use clap::Parser;
 use log;
-use std::env;
 use std::process::{Command, Stdio};
+use std::sync::Arc;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command as TokioCommand;
 use uuid::Uuid;
@@ -26,261 +34,1379 @@ pub(crate) fn list_hardware_encoders() -> bool {
     return utils::run_command(command);
 }
 
-pub(crate) fn get_encoder(filtered: Vec<&str>, encoder_name: &str) -> String {
-    let os_filtered = filtered
-        .iter()
-        .filter(|line| line.contains(encoder_name))
-        .collect::<Vec<&&str>>();
-    if os_filtered.len() == 0 {
-        log::info!("Could not find {} encoder", encoder_name);
-        return "".to_string();
+// drawtext expansion of the local time, e.g. 2024-01-01 12:00:00
+const LOCALTIME: &str = "%{localtime\\:%Y-%m-%d %H\\\\\\:%M\\\\\\:%S}";
+
+// Timestamp and build version burned into the video, backend set when the frames are on the device
+fn debug_text_filter(backend: Option<&dyn EncoderBackend>) -> String {
+    let drawtext = backend
+        .and_then(|b| b.drawtext_filter())
+        .unwrap_or("drawtext");
+
+    [
+        drawtext,
+        "=text='",
+        LOCALTIME,
+        "':fontcolor=yellow:fontsize=100:x=10:y=10:box=1:boxcolor=black@0.5:boxborderw=5,",
+        drawtext,
+        "=text='gasket ",
+        &utils::get_build_info().replace(":", "\\:"),
+        "':fontcolor=white:fontsize=50:x=W-tw-10:y=H-th-10:box=1:boxcolor=black@0.5:boxborderw=5",
+    ]
+    .concat()
+}
+
+// Literal text for drawtext, inside single quotes
+fn escape_text(text: &str) -> String {
+    text.replace(['\\', '\''], "")
+        .replace(':', "\\:")
+        .replace('%', "\\%")
+}
+
+// x and y of an item of width x height inside the frame W x H
+fn overlay_position(
+    position: Option<OverlayPosition>,
+    margin: u32,
+    width: &str,
+    height: &str,
+) -> (String, String) {
+    let left = margin.to_string();
+    let right = format!("W-{width}-{margin}");
+    let top = margin.to_string();
+    let bottom = format!("H-{height}-{margin}");
+    match position.unwrap_or(OverlayPosition::TopLeft) {
+        OverlayPosition::TopLeft => (left, top),
+        OverlayPosition::TopRight => (right, top),
+        OverlayPosition::BottomLeft => (left, bottom),
+        OverlayPosition::BottomRight => (right, bottom),
+        OverlayPosition::Center => (format!("(W-{width})/2"), format!("(H-{height})/2")),
+    }
+}
+
+// Filters of one overlay, chainable with the other video filters. Logos read the image with the movie source
+// and are blended in a sub-graph, index keeps its labels unique
+fn overlay_filter(
+    overlay: &Overlay,
+    index: usize,
+    name: &str,
+    backend: Option<&dyn EncoderBackend>,
+    framerate: Option<&str>,
+) -> String {
+    let margin = overlay.margin.unwrap_or(10);
+
+    if overlay.kind == OverlayKind::Debug {
+        return debug_text_filter(backend);
+    }
+
+    if overlay.kind == OverlayKind::Logo {
+        let mut logo = format!(
+            "movie={},format=rgba",
+            overlay.file.as_deref().unwrap_or_default()
+        );
+        if let Some(size) = overlay.size {
+            logo.push_str(&format!(",scale={size}:-1"));
+        }
+        if let Some(opacity) = overlay.opacity.filter(|o| *o < 100) {
+            logo.push_str(&format!(
+                ",colorchannelmixer=aa={:.2}",
+                opacity as f64 / 100.0
+            ));
+        }
+        let (x, y) = overlay_position(overlay.position, margin, "w", "h");
+        return format!(
+            "null[main{index}];{logo}[logo{index}];[main{index}][logo{index}]overlay={x}:{y}"
+        );
+    }
+
+    let drawtext = backend
+        .and_then(|b| b.drawtext_filter())
+        .unwrap_or("drawtext");
+
+    let text = escape_text(overlay.text.as_deref().unwrap_or_default())
+        .replace("{name}", &escape_text(name))
+        .replace("{build}", &escape_text(&utils::get_build_info()))
+        .replace("{timestamp}", LOCALTIME);
+
+    let mut args = Vec::new();
+    if let Some(font) = &overlay.font {
+        args.push(format!("fontfile='{}'", font));
+    }
+    args.push(format!("text='{}'", text));
+    if overlay.kind == OverlayKind::Timecode {
+        let rate = overlay.rate.as_deref().or(framerate).unwrap_or("25");
+        args.push("timecode='00\\:00\\:00\\:00'".to_string());
+        args.push(format!("timecode_rate={rate}"));
+    }
+    args.push(format!(
+        "fontcolor={}",
+        overlay.color.as_deref().unwrap_or("white")
+    ));
+    args.push(format!("fontsize={}", overlay.size.unwrap_or(48)));
+    let (x, y) = overlay_position(overlay.position, margin, "tw", "th");
+    args.push(format!("x={x}:y={y}"));
+    if let Some(background) = &overlay.background {
+        args.push(format!("box=1:boxcolor={background}:boxborderw=5"));
     }
 
-    let encoder: String = os_filtered[0].split(" ").collect::<Vec<&str>>()[1].to_string();
-    log::info!("Using {} encoder: {}", encoder_name, encoder);
-    return encoder;
+    format!("{drawtext}={}", args.join(":"))
 }
 
-// List hardware encoders, depending on OS choose the hw encoder as string
-pub(crate) fn choose_encoder(codec: String) -> String {
-    let args = args::Args::parse();
+// Filter graph splitting the decoded video into the main output [v0] and one branch per rendition [v1]..[vN]
+fn ladder_filter(input: &str, head: &[String], branches: &[Vec<String>]) -> String {
+    let mut graph = format!("[{input}:v]");
+    for filter in head {
+        graph.push_str(filter);
+        graph.push(',');
+    }
+    graph.push_str(&format!("split={}", branches.len()));
+    for i in 0..branches.len() {
+        graph.push_str(&format!("[s{i}]"));
+    }
 
-    if args.cpu_only {
-        let c = codec.clone();
-        match c.as_str() {
-            "h264" => return "libx264".to_string(),
-            "hevc" => return "libx265".to_string(),
-            "av1" => return "libaom-av1".to_string(),
-            _ => {
-                return "".to_string();
-            }
+    for (i, filters) in branches.iter().enumerate() {
+        if filters.is_empty() {
+            graph.push_str(&format!(";[s{i}]null[v{i}]"));
+        } else {
+            graph.push_str(&format!(";[s{i}]{}[v{i}]", filters.join(",")));
         }
     }
 
-    let mut command = Command::new(get_ffmpeg_path());
-    command.arg("-hide_banner").arg("-encoders");
+    graph
+}
 
-    let result = utils::run_command_capture(command);
+// Output size of one rung and how the source is mapped onto it
+struct Scale<'a> {
+    width: Option<u32>,
+    height: Option<u32>,
+    mode: ScalingMode,
+    pad: Option<&'a str>,
+}
 
-    // split result per lines
-    let lines: Vec<&str> = result.split("\n").collect();
+impl Scale<'_> {
+    // Target size for the scaler, -2 keeps the aspect ratio for a missing dimension. None if nothing to scale
+    fn size(&self) -> Option<(String, String)> {
+        if self.width.is_none() && self.height.is_none() {
+            return None;
+        }
 
-    // filter lines that contain the codec
-    let filtered = lines
-        .iter()
-        .filter(|line| line.contains(&codec))
-        .map(|line| line.trim())
-        .collect::<Vec<&str>>();
-
-    // if no codec is found, return empty string
-    if filtered.len() == 0 {
-        return "".to_string();
-    }
-
-    let os = env::consts::OS;
-    log::info!("Using OS {}", os); // Prints the current OS.
-
-    // match on OS
-    match os {
-        "macos" => {
-            log::info!("Checking presence of videotoolbox for codec: {}", codec);
-            let encoder = get_encoder(filtered, "videotoolbox");
-            return encoder;
-        }
-        "linux" => {
-            let encoder = get_encoder(filtered.clone(), "nvenc");
-            if encoder != "" {
-                log::info!("Found nvenc {}", codec);
-                return encoder;
+        let width = self
+            .width
+            .map(|w| w.to_string())
+            .unwrap_or("-2".to_string());
+        let height = self
+            .height
+            .map(|h| h.to_string())
+            .unwrap_or("-2".to_string());
+        Some((width, height))
+    }
+
+    // The mode only matters when both dimensions are given
+    fn mode(&self) -> ScalingMode {
+        match (self.width, self.height) {
+            (Some(_), Some(_)) => self.mode,
+            _ => ScalingMode::Stretch,
+        }
+    }
+
+    fn padded(&self) -> bool {
+        self.pad.is_some() && self.mode() == ScalingMode::Fit
+    }
+
+    // Whether the backend can do all of it on device frames
+    fn on_device(&self, backend: &dyn EncoderBackend) -> bool {
+        match self.size() {
+            Some((width, height)) => {
+                !self.padded() && backend.scale_filter(&width, &height, self.mode()).is_some()
             }
+            None => true,
+        }
+    }
 
-            let encoder = get_encoder(filtered.clone(), "mpsoc_vcu");
-            if encoder != "" {
-                log::info!("Found u30 {}", codec);
-                return encoder;
+    // Scale filters, backend set when the frames are on the device
+    fn filters(&self, backend: Option<&dyn EncoderBackend>) -> Vec<String> {
+        let (width, height) = match self.size() {
+            Some(size) => size,
+            None => return Vec::new(),
+        };
+
+        if let Some(scale) = backend.and_then(|b| b.scale_filter(&width, &height, self.mode())) {
+            return vec![scale];
+        }
+
+        match self.mode() {
+            ScalingMode::Stretch => vec![format!("scale={width}:{height}")],
+            ScalingMode::Fit => {
+                let mut filters = vec![format!(
+                    "scale={width}:{height}:force_original_aspect_ratio=decrease:force_divisible_by=2"
+                )];
+                if let Some(color) = self.pad.filter(|_| self.padded()) {
+                    filters.push(format!(
+                        "pad={width}:{height}:(ow-iw)/2:(oh-ih)/2:color={color}"
+                    ));
+                }
+                filters
             }
+            ScalingMode::Fill => vec![
+                format!("scale={width}:{height}:force_original_aspect_ratio=increase"),
+                format!("crop={width}:{height}"),
+            ],
+        }
+    }
+}
+
+// Input audio streams carried by an output, all of them unless tracks or languages are selected
+fn audio_maps(input: &str, audio: Option<&AudioOptions>) -> Vec<String> {
+    let tracks = audio.and_then(|a| a.tracks.as_ref());
+    let languages = audio.and_then(|a| a.languages.as_ref());
+
+    // Optional maps, a missing track doesn't fail the stream
+    let mut maps: Vec<String> = tracks
+        .into_iter()
+        .flatten()
+        .map(|track| format!("{input}:a:{track}?"))
+        .chain(
+            languages
+                .into_iter()
+                .flatten()
+                .map(|language| format!("{input}:a:m:language:{language}?")),
+        )
+        .collect();
+    if maps.is_empty() {
+        maps.push(format!("{input}:a?"));
+    }
+
+    maps.into_iter()
+        .flat_map(|map| ["-map".to_string(), map])
+        .collect()
+}
+
+// Audio encoding of one output
+fn audio_args(audio: &AudioOptions) -> Vec<String> {
+    let codec = audio.codec.unwrap_or(AudioCodec::AAC);
+    let mut args = vec!["-c:a".to_string(), codec.ffmpeg_name().to_string()];
+    if codec == AudioCodec::Copy {
+        return args;
+    }
+
+    if let Some(bitrate) = &audio.bitrate {
+        args.extend(["-b:a".to_string(), bitrate.clone()]);
+    }
+
+    let mut filters = Vec::new();
+    if let Some(loudness) = audio.loudness {
+        filters.push(format!("loudnorm=I={loudness}:TP=-1.5:LRA=11"));
+    }
+    if let Some(channel_layout) = &audio.channel_layout {
+        filters.push(format!("aformat=channel_layouts={channel_layout}"));
+    }
+    if !filters.is_empty() {
+        args.extend(["-af".to_string(), filters.join(",")]);
+    }
+
+    // loudnorm resamples to 192 kHz, bring it back down
+    let sample_rate = match audio.loudness {
+        Some(_) => Some(audio.sample_rate.unwrap_or(48000)),
+        None => audio.sample_rate,
+    };
+    if let Some(sample_rate) = sample_rate {
+        args.extend(["-ar".to_string(), sample_rate.to_string()]);
+    }
+
+    args
+}
+
+// CPU deinterlacer, used when the backend has no device variant
+fn deinterlace_filter(deinterlace: &Deinterlace) -> String {
+    format!(
+        "{}={}",
+        deinterlace.ffmpeg_name(),
+        deinterlace.ffmpeg_args()
+    )
+}
+
+// Converts to the output framerate in the filter graph, instead of -r
+fn framerate_filter(mode: FramerateMode, framerate: &str) -> String {
+    match mode {
+        FramerateMode::DropDup => format!("fps={framerate}"),
+        FramerateMode::Blend => format!("framerate=fps={framerate}"),
+        FramerateMode::Interpolate => {
+            format!("minterpolate=fps={framerate}:mi_mode=mci:mc_mode=aobmc:me_mode=bidir:vsbmc=1")
+        }
+    }
+}
+
+// PQ/HLG to BT.709 through linear light, needs ffmpeg built with zimg
+fn tonemap_filter(tonemap: &Tonemap) -> String {
+    let algorithm = tonemap.algorithm.map_or("hable", |a| a.ffmpeg_name());
+    format!(
+        "zscale=t=linear:npl={},format=gbrpf32le,zscale=p=bt709,tonemap=tonemap={algorithm}:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p",
+        tonemap.peak.unwrap_or(100)
+    )
+}
+
+// Crop of the source, None if nothing is removed
+fn crop_filter(crop: &Crop) -> Option<String> {
+    let top = crop.top.unwrap_or(0);
+    let bottom = crop.bottom.unwrap_or(0);
+    let left = crop.left.unwrap_or(0);
+    let right = crop.right.unwrap_or(0);
+
+    if top + bottom + left + right == 0 {
+        return None;
+    }
+    Some(format!(
+        "crop=iw-{}:ih-{}:{left}:{top}",
+        left + right,
+        top + bottom
+    ))
+}
 
-            let encoder = get_encoder(filtered.clone(), "ni_quadra");
-            if encoder != "" {
-                log::info!("Found netint {}", codec);
-                return encoder;
+// Muxer, its options and the destination of a single output
+struct Muxer {
+    format: String,
+    options: Vec<(String, String)>,
+    uri: String,
+}
+impl Muxer {
+    fn new(format: &str, uri: String) -> Muxer {
+        Muxer {
+            format: format.to_string(),
+            options: Vec::new(),
+            uri,
+        }
+    }
+
+    fn option(mut self, key: &str, value: impl ToString) -> Muxer {
+        self.options.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    // One slave of the tee muxer, escaped for both levels of its parser
+    fn tee_slave(&self) -> String {
+        let options: Vec<String> = std::iter::once(("f".to_string(), self.format.clone()))
+            .chain(self.options.iter().cloned())
+            .map(|(key, value)| format!("{key}={}", tee_escape(&value, "\\':[]|")))
+            .collect();
+        tee_escape(&format!("[{}]{}", options.join(":"), self.uri), "\\'|")
+    }
+}
+
+fn tee_escape(value: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Muxer of a single output
+fn output_muxer(
+    output_format: &str,
+    packaging: Option<&Packaging>,
+    srt: Option<&SrtOptions>,
+    muxrate: Option<&str>,
+    mpegts: Option<&MpegtsOptions>,
+    uri: &str,
+) -> Muxer {
+    if let Some(packaging) = packaging {
+        return packaging_muxer(packaging, uri);
+    }
+
+    let uri = match srt {
+        Some(srt) => srt.uri(uri),
+        None => uri.to_string(),
+    };
+    let mut muxer = Muxer::new(output_format, uri);
+
+    if output_format == "mpegts" {
+        // Constant rate padded with null packets
+        if let Some(muxrate) = muxrate {
+            muxer = muxer.option("muxrate", muxrate);
+        }
+
+        if let Some(mpegts) = mpegts {
+            for (key, value) in [
+                ("mpegts_service_id", mpegts.service_id),
+                ("mpegts_transport_stream_id", mpegts.transport_stream_id),
+                ("mpegts_original_network_id", mpegts.original_network_id),
+                ("mpegts_pmt_start_pid", mpegts.pmt_pid),
+                ("mpegts_start_pid", mpegts.start_pid),
+            ] {
+                if let Some(value) = value {
+                    muxer = muxer.option(key, value);
+                }
             }
+        }
+    }
+
+    muxer
+}
+
+// Output arguments of a muxer, teed into the recording segments if there are any
+fn output_args(
+    command: &mut TokioCommand,
+    muxer: Muxer,
+    name: &str,
+    mpegts: Option<&MpegtsOptions>,
+    recording: Option<&Muxer>,
+) {
+    if muxer.format == "mpegts" {
+        let service_provider = mpegts
+            .and_then(|m| m.service_provider.clone())
+            .unwrap_or("gasket-".to_string() + &utils::get_build_info());
+        let service_name = mpegts
+            .and_then(|m| m.service_name.as_deref())
+            .unwrap_or(name);
+        command
+            .arg("-metadata")
+            .arg(format!("service_provider={service_provider}"))
+            .arg("-metadata")
+            .arg(format!("service_name={service_name}"));
 
-            log::error!("No hardware encoder found for codec: {}", codec);
-            return "".to_string();
+        // Video is the first output stream, the audio tracks follow
+        if let Some(video_pid) = mpegts.and_then(|m| m.video_pid) {
+            command.arg("-streamid").arg(format!("0:{video_pid}"));
+        }
+        let audio_pids = mpegts.and_then(|m| m.audio_pids.as_ref());
+        for (i, pid) in audio_pids.into_iter().flatten().enumerate() {
+            command.arg("-streamid").arg(format!("{}:{pid}", i + 1));
         }
-        _ => {
-            return "".to_string();
+    }
+
+    if let Some(recording) = recording {
+        command.arg("-f").arg("tee").arg(format!(
+            "{}|{}",
+            muxer.tee_slave(),
+            recording.tee_slave()
+        ));
+        return;
+    }
+
+    command.arg("-f").arg(&muxer.format);
+    for (key, value) in &muxer.options {
+        command.arg(format!("-{key}")).arg(value);
+    }
+    command.arg(&muxer.uri);
+}
+
+// HLS/CMAF/DASH segmenting into a local directory, or an http(s) server accepting PUT
+fn packaging_muxer(packaging: &Packaging, uri: &str) -> Muxer {
+    let segment_duration = packaging.segment_duration.unwrap_or(4);
+    let window_size = packaging.window_size.unwrap_or(6);
+    let dir = uri.trim_end_matches('/');
+    let http = dir.starts_with("http://") || dir.starts_with("https://");
+
+    let mut muxer = match packaging.format {
+        PackagingFormat::HLS | PackagingFormat::CMAF => {
+            let (segment_type, extension) = if packaging.format == PackagingFormat::CMAF {
+                ("fmp4", "m4s")
+            } else {
+                ("mpegts", "ts")
+            };
+
+            // Only clean up segments we can delete ourselves
+            let mut flags = "independent_segments".to_string();
+            if !http && window_size > 0 {
+                flags.push_str("+delete_segments");
+            }
+
+            let muxer = Muxer::new("hls", packaging.playlist(uri))
+                .option("hls_time", segment_duration)
+                .option("hls_list_size", window_size)
+                .option("hls_flags", flags)
+                .option("hls_segment_type", segment_type)
+                .option(
+                    "hls_segment_filename",
+                    format!("{dir}/segment_%05d.{extension}"),
+                );
+
+            if packaging.format == PackagingFormat::CMAF {
+                muxer.option("hls_fmp4_init_filename", "init.mp4")
+            } else {
+                muxer
+            }
         }
+        PackagingFormat::DASH => Muxer::new("dash", packaging.playlist(uri))
+            .option("seg_duration", segment_duration)
+            .option("window_size", window_size)
+            .option("use_template", 1)
+            .option("use_timeline", 1),
+    };
+
+    if http {
+        muxer = muxer.option("method", "PUT").option("http_persistent", 1);
     }
+
+    muxer
 }
 
-pub(crate) fn list_hardware_support() -> (Option<Encoder>, Vec<Codec>) {
-    // for each codec, check, run choose_encoder(codec) and see if we get something back
-    let codecs = Codec::all();
-    let mut supported: Vec<Codec> = Vec::new();
-    let mut hw_encoder: Option<Encoder> = None;
+// Segments cut on the wall clock, named after their start time
+fn recording_muxer(recording: &Recording, dir: &str) -> Muxer {
+    Muxer::new("segment", format!("{dir}/{}", recording.filename()))
+        .option("segment_time", recording.segment_duration())
+        .option("segment_atclocktime", 1)
+        .option("strftime", 1)
+        .option("reset_timestamps", 1)
+        // A full disk stops the recording, not the output
+        .option("onfail", "ignore")
+}
 
-    for codec in codecs.iter() {
-        let encoder = choose_encoder(codec.ffmpeg_name().to_string());
+// Local packaging targets have to exist before ffmpeg writes segments into them
+fn create_output_dirs(output: &str, options: Option<&StreamOptions>) {
+    if options.and_then(|o| o.packaging.as_ref()).is_none() {
+        return;
+    }
 
-        if encoder == "" {
+    let renditions = options.and_then(|o| o.renditions.as_ref());
+    let uris =
+        std::iter::once(output).chain(renditions.into_iter().flatten().map(|r| r.uri.as_str()));
+    for uri in uris {
+        let dir = uri.trim_end_matches('/');
+        if dir.starts_with("http://") || dir.starts_with("https://") {
             continue;
         }
 
-        for hw_encoder_enum in Encoder::all().iter() {
-            if encoder.contains(hw_encoder_enum.ffmpeg_name()) {
-                hw_encoder = Some(hw_encoder_enum.clone());
-            }
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            log::error!("Could not create output directory {}: {}", dir, e);
         }
+    }
+}
 
-        supported.push(codec.clone());
+// Where the snapshots of a stream are written, None if they are disabled
+pub(crate) fn thumbnail_path(uuid: Uuid) -> Option<String> {
+    let args = args::Args::parse();
+    if args.thumbnail_interval == 0 {
+        return None;
     }
-    return (hw_encoder, supported);
+    Some(format!(
+        "{}/{}.jpg",
+        args.thumbnail_dir.trim_end_matches('/'),
+        uuid
+    ))
 }
 
-pub(crate) async fn stream(
-    encoder_status: EncoderStats,
-    uuid: Uuid,
-    name: String,
-    input: String,
+// Accelerator a stream runs on
+pub(crate) struct Hardware<'a> {
+    pub(crate) backend: &'a dyn EncoderBackend,
+    pub(crate) device: Option<usize>,
+    // Input video codec from ffprobe, None decodes on the CPU
+    pub(crate) input_codec: Option<String>,
+}
+
+// What a stream process encodes, filled from the stream by the monitor
+pub(crate) struct StreamRun {
+    pub(crate) uuid: Uuid,
+    pub(crate) name: String,
+    pub(crate) input: Input,
+    pub(crate) output: String,
+    // ffmpeg name of the output codec
+    pub(crate) codec: String,
+    pub(crate) options: Option<StreamOptions>,
+}
+
+// Input of the process, see Failover
+pub(crate) struct Input {
+    pub(crate) uri: String,
+    pub(crate) source: InputSource,
+    // What ffprobe found, only the primary input is probed
+    pub(crate) info: Option<InputInfo>,
+}
+
+// Periodic snapshot of the decoded input, see GET /stream/:uuid/thumbnail
+pub(crate) struct Thumbnail {
+    pub(crate) path: String,
+    // Seconds between snapshots
+    pub(crate) interval: u64,
+}
+
+// Files written by the process besides its output, prepared by stream()
+pub(crate) struct Sidecars {
+    pub(crate) thumbnail: Option<Thumbnail>,
+    // Segments of StreamOptions::recording go here
+    pub(crate) recording_dir: String,
+    // Two-pass encoding statistics, see Job
+    pub(crate) pass: Option<Pass>,
+    // Black, freeze and silence detectors, see alarms.rs
+    pub(crate) detection: Option<AlarmOptions>,
+}
+
+pub(crate) struct Pass {
+    pub(crate) number: u8,
+    // Every output gets its own statistics file with this prefix
+    pub(crate) log_prefix: String,
+}
+
+fn pass_log_prefix(uuid: Uuid) -> String {
+    format!("{}/gasket-{}", std::env::temp_dir().display(), uuid)
+}
+
+// Statistics files of both passes, e.g. gasket-{uuid}-0.log and gasket-{uuid}-0.log.mbtree for x264
+pub(crate) fn remove_pass_logs(uuid: Uuid) {
+    let prefix = format!("gasket-{uuid}-");
+    let Ok(entries) = std::fs::read_dir(std::env::temp_dir()) else {
+        return;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+fn pass_args(pass: Option<&Pass>, output: usize) -> Vec<String> {
+    match pass {
+        Some(pass) => vec![
+            "-pass".to_string(),
+            pass.number.to_string(),
+            "-passlogfile".to_string(),
+            format!("{}-{output}", pass.log_prefix),
+        ],
+        None => Vec::new(),
+    }
+}
+
+// Extra output overwriting a low-res JPEG, download moves device frames to system memory first
+fn thumbnail_args(input: &str, thumbnail: &Thumbnail, download: Option<&str>) -> Vec<String> {
+    // Decimated first so only the snapshots leave the device
+    let mut filters = vec![format!("fps=1/{}", thumbnail.interval)];
+    filters.extend(download.map(String::from));
+    filters.push("scale=320:-2".to_string());
+
+    [
+        "-map",
+        &format!("{input}:v:0"),
+        "-vf",
+        &filters.join(","),
+        "-an",
+        "-q:v",
+        "5",
+        "-f",
+        "image2",
+        "-update",
+        "1",
+        &thumbnail.path,
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+// Extra null output running the detectors, their events are parsed from stderr by alarms.rs
+fn detection_args(input: &str, alarms: &AlarmOptions, download: Option<&str>) -> Vec<String> {
+    let black = alarms.detector(AlarmKind::Black);
+    let freeze = alarms.detector(AlarmKind::Freeze);
+    let silence = alarms.detector(AlarmKind::Silence);
+    if black.is_none() && freeze.is_none() && silence.is_none() {
+        return Vec::new();
+    }
+
+    // Decimated first so only the analysed frames leave the device. The output still needs
+    // a video stream when only silence is detected
+    let rate = if black.is_none() && freeze.is_none() {
+        1
+    } else {
+        5
+    };
+    let mut filters = vec![format!("fps={rate}")];
+    filters.extend(download.map(String::from));
+    filters.push("scale=320:-2".to_string());
+    if let Some((duration, threshold)) = black {
+        filters.push(format!(
+            "blackdetect=d={duration}:pic_th={}",
+            threshold as f64 / 100.0
+        ));
+        // blackdetect only logs a dark run once it is over, the frame metadata marks its start
+        filters.push("metadata=mode=print:key=lavfi.black_start".to_string());
+        filters.push("metadata=mode=print:key=lavfi.black_end".to_string());
+    }
+    if let Some((duration, threshold)) = freeze {
+        filters.push(format!("freezedetect=d={duration}:n={threshold}dB"));
+    }
+
+    let mut args = vec![
+        "-map".to_string(),
+        format!("{input}:v:0"),
+        "-vf".to_string(),
+        filters.join(","),
+    ];
+    if let Some((duration, threshold)) = silence {
+        args.extend([
+            "-map".to_string(),
+            format!("{input}:a:0?"),
+            "-af".to_string(),
+            format!("silencedetect=d={duration}:n={threshold}dB"),
+        ]);
+    }
+    args.extend(["-f", "null", "-"].map(String::from));
+    args
+}
+
+// Program number of a selection, a service name needs the probe of the input
+fn input_program(selection: &ProgramSelection, info: Option<&InputInfo>) -> Option<u32> {
+    if let Some(number) = selection.number {
+        return Some(number);
+    }
+
+    let service_name = selection.service_name.as_deref()?;
+    let program = info.and_then(|info| info.program_number(selection));
+    if program.is_none() {
+        log::warn!(
+            "No program named {} in the input, streams are picked from all programs",
+            service_name
+        );
+    }
+    program
+}
+
+// SCTE-35 cues copied as is into mpegts outputs, the whole data of the program if the input
+// wasn't probed. Probed streams are mapped by PID, their index only holds in the whole input
+fn scte35_maps(
+    options: Option<&StreamOptions>,
+    output_format: &str,
+    program: Option<u32>,
+    input: &str,
+    info: Option<&InputInfo>,
+) -> Vec<String> {
+    let scte35 = options.and_then(|o| o.scte35).unwrap_or(false);
+    let packaging = options.and_then(|o| o.packaging.as_ref());
+    if !scte35 || output_format != "mpegts" || packaging.is_some() {
+        return Vec::new();
+    }
+
+    let maps = match info {
+        Some(info) => info
+            .scte35_streams(program)
+            .iter()
+            .filter_map(|d| match (d.pid, program) {
+                (Some(pid), _) => Some(format!("{input}:i:{pid}")),
+                (None, None) => Some(format!("{input}:{}", d.index)),
+                (None, Some(_)) => None,
+            })
+            .collect(),
+        None => vec![format!("{input}:d?")],
+    };
+    if maps.is_empty() {
+        return Vec::new();
+    }
+
+    let mut args: Vec<String> = maps
+        .into_iter()
+        .flat_map(|map| ["-map".to_string(), map])
+        .collect();
+    args.extend(["-c:d", "copy"].map(String::from));
+    args
+}
+
+// Looped slate image or video with silent audio, as a single lavfi input so it maps like any other input
+fn slate_input(slate: &str) -> String {
+    format!("movie={slate}:loop=0,setpts=N/FRAME_RATE/TB[out0];anullsrc=r=48000:cl=stereo[out1]")
+}
+
+// Full ffmpeg command line of a stream, nothing is spawned or touched on disk
+pub(crate) fn build_command(
+    hw: Option<&Hardware>,
+    name: &str,
+    input: Input,
     output: String,
-    codec: String,
-    options: Option<StreamOptions>,
-    tx: tokio::sync::broadcast::Sender<StreamLogMessage>,
-) -> Option<u32> {
+    codec: Option<Codec>,
+    options: Option<&StreamOptions>,
+    sidecars: &Sidecars,
+) -> TokioCommand {
+    let Input {
+        uri: input,
+        source,
+        info,
+    } = input;
+    let thumbnail = sidecars.thumbnail.as_ref();
+
     // unwrap options, set default values if None
-    let pixel_format = options
-        .as_ref()
-        .and_then(|o| o.pixel_format.clone());
+    let pixel_format = options.and_then(|o| o.pixel_format.clone());
     let bitrate = options
-        .as_ref()
         .and_then(|o| o.bitrate.clone())
         .unwrap_or("500k".to_string());
-    let framerate = options.as_ref().and_then(|o| o.framerate.clone());
+    let framerate = options.and_then(|o| o.framerate.clone());
     let gop_size = options
-        .as_ref()
         .and_then(|o| o.gop_size.clone())
         .unwrap_or("60".to_string());
+    // Setting overlays replaces the default debug overlay
+    let mut overlays = options.and_then(|o| o.overlays.clone()).unwrap_or_default();
     let debug_text = options
-        .as_ref()
-        .and_then(|o| o.debug_text.clone())
-        .unwrap_or(true);
+        .and_then(|o| o.debug_text)
+        .unwrap_or(options.and_then(|o| o.overlays.as_ref()).is_none());
+    if debug_text {
+        overlays.insert(0, Overlay::debug());
+    }
     let output_format = options
-        .as_ref()
         .and_then(|o| o.output_format.clone())
         .unwrap_or("mpegts".to_string());
+    let renditions = options
+        .and_then(|o| o.renditions.clone())
+        .unwrap_or_default();
+    let crop = options.and_then(|o| o.crop.as_ref()).and_then(crop_filter);
+
+    // Main output first, then the renditions
+    let scaling_mode = options
+        .and_then(|o| o.scaling_mode)
+        .unwrap_or(ScalingMode::Stretch);
+    let pad = options.and_then(|o| o.pad.as_deref());
+    let mut scales = vec![Scale {
+        width: options.and_then(|o| o.width),
+        height: options.and_then(|o| o.height),
+        mode: scaling_mode,
+        pad,
+    }];
+    scales.extend(renditions.iter().map(|r| Scale {
+        width: r.width,
+        height: r.height,
+        mode: scaling_mode,
+        pad,
+    }));
+
+    let backend = hw.map(|h| h.backend);
+    let device = hw.and_then(|h| h.device);
 
-    let encoder = choose_encoder(codec);
+    // Decode on the device when it supports the input codec, and keep the frames there
+    // if every filter can run on it
+    let decode_codec = hw.and_then(|h| {
+        h.input_codec
+            .as_deref()
+            .filter(|c| h.backend.hw_decode_codecs().contains(c))
+    });
+    let deinterlace = options.and_then(|o| o.deinterlace.as_ref());
+    let tonemap = options.and_then(|o| o.tonemap.as_ref());
+    let framerate_mode = options
+        .and_then(|o| o.framerate_mode)
+        .filter(|_| framerate.is_some());
+
+    // Filters without a device variant, any of them moves the frames back to the CPU
+    let mut cpu_filters = Vec::new();
+    if let (Some(backend), Some(_)) = (backend, decode_codec) {
+        if crop.is_some() {
+            cpu_filters.push("crop");
+        }
+        if deinterlace.is_some_and(|d| backend.deinterlace_filter(d).is_none()) {
+            cpu_filters.push("deinterlace");
+        }
+        if tonemap.is_some_and(|t| backend.tonemap_filter(t).is_none()) {
+            cpu_filters.push("tonemap");
+        }
+        // fps only drops and duplicates, so it doesn't care where the frames are
+        if framerate_mode.is_some_and(|m| m != FramerateMode::DropDup) {
+            cpu_filters.push("framerate conversion");
+        }
+        if overlays.iter().any(|o| o.kind == OverlayKind::Logo) {
+            cpu_filters.push("logo overlay");
+        }
+        if !overlays.is_empty() && backend.drawtext_filter().is_none() {
+            cpu_filters.push("drawtext");
+        }
+        if !scales.iter().all(|scale| scale.on_device(backend)) {
+            cpu_filters.push("scale");
+        }
+
+        if !cpu_filters.is_empty() {
+            log::warn!(
+                "No {:?} device filter for {}, decoded frames are filtered on the CPU",
+                backend.encoder(),
+                cpu_filters.join(", ")
+            );
+        }
+    }
+    let frames_on_device = decode_codec.is_some() && cpu_filters.is_empty();
+    let filter_backend = backend.filter(|_| frames_on_device);
 
-    let mut hwdev = "";
-    if encoder.contains("mpsoc_vcu") {
-        hwdev = "u30";
+    // Filters applied once to the decoded video
+    let mut head = Vec::new();
+    if decode_codec.is_some() && !frames_on_device {
+        if let Some(download) = backend.and_then(|b| b.download_filter()) {
+            head.push(download.to_string());
+        }
+    }
+    if let Some(deinterlace) = deinterlace {
+        head.push(
+            filter_backend
+                .and_then(|b| b.deinterlace_filter(deinterlace))
+                .unwrap_or_else(|| deinterlace_filter(deinterlace)),
+        );
+    }
+    if let Some(tonemap) = tonemap {
+        head.push(
+            filter_backend
+                .and_then(|b| b.tonemap_filter(tonemap))
+                .unwrap_or_else(|| tonemap_filter(tonemap)),
+        );
     }
-    if encoder.contains("ni_quadra") {
-        hwdev = "ni";
+    if let (Some(mode), Some(framerate)) = (framerate_mode, &framerate) {
+        head.push(framerate_filter(mode, framerate));
     }
+    head.extend(crop);
+    head.extend(overlays.iter().enumerate().map(|(i, overlay)| {
+        overlay_filter(overlay, i, name, filter_backend, framerate.as_deref())
+    }));
+    // Frames kept on the device are already there
+    let upload = backend
+        .filter(|_| !frames_on_device)
+        .and_then(|b| b.upload_filter());
+
+    // Per output: scaling and moving frames onto the device
+    let branches: Vec<Vec<String>> = scales
+        .iter()
+        .map(|scale| {
+            let mut filters = scale.filters(filter_backend);
+            filters.extend(upload.map(String::from));
+            filters
+        })
+        .collect();
 
     let mut command = TokioCommand::new(get_ffmpeg_path());
 
+    if let Some(backend) = backend {
+        command.envs(backend.env());
+    }
+
     // Less verbose output
     command.arg("-hide_banner");
 
-    // Pick device with lowest usage
-    // TODO also set codec for input video
-    // ffmpeg -xlnx_hwdev 1 -c:v mpsoc_vcu_h264 -stream_loop -1 -i test_loop.mp4 -f mp4 -c:v mpsoc_vcu_hevc -y /dev/null
-    if hwdev == "u30" {
-        command.arg("-xlnx_hwdev").arg(
-            encoder_status
-                .devices
-                .iter()
-                .enumerate()
-                .min_by_key(|&(_, &value)| value)
-                .expect("No devices in stats list")
-                .0
-                .to_string(),
-        );
-    }
+    // Machine readable progress on stdout instead of the stats line on stderr
+    command.arg("-progress").arg("pipe:1").arg("-nostats");
 
-    // Loop input
-    command.arg("-stream_loop").arg("-1");
+    // The input mode is set for the primary input, backups go by their uri
+    let input_mode = match source {
+        InputSource::Primary => options
+            .and_then(|o| o.input_mode)
+            .unwrap_or(InputMode::from_uri(&input)),
+        _ => InputMode::from_uri(&input),
+    };
 
-    // Set incoming framerate
-    command.arg("-re");
+    if source == InputSource::Slate {
+        // Generated at native framerate, the movie source loops by itself
+        command.arg("-re").arg("-f").arg("lavfi");
+    } else if input_mode == InputMode::Loop {
+        // Loop input
+        command.arg("-stream_loop").arg("-1");
 
-    if framerate.is_some() {
-        command.arg("-r").arg(framerate.clone().unwrap());
+        // Set incoming framerate
+        command.arg("-re");
     }
 
-    if hwdev == "ni" {
-        command
-            .arg("-c:v")
-            .arg("h264_ni_quadra_dec")
-            .arg("-xcoder-params")
-            .arg("out=hw");
+    // Forces the input framerate, unless it is converted in the filter graph
+    if let Some(framerate) = framerate.as_ref().filter(|_| framerate_mode.is_none()) {
+        command.arg("-r").arg(framerate);
     }
 
+    // Device selection and hardware decoding
+    if let Some(backend) = backend {
+        command.args(backend.input_args(device));
+
+        if let Some(input_codec) = decode_codec {
+            command.args(backend.decoder_args(input_codec, device, frames_on_device));
+        }
+    }
+
+    // SRT settings go in the input URI
+    let input = match (source, options.and_then(|o| o.input_srt.as_ref())) {
+        (InputSource::Slate, _) => slate_input(&input),
+        (InputSource::Primary, Some(srt)) => srt.uri(&input),
+        _ => input,
+    };
+
     command
         // Input stream
         .arg("-i")
         .arg(input);
 
-    command.arg("-b:v").arg(bitrate);
+    let packaging = options.and_then(|o| o.packaging.clone());
+    let output_srt = options.and_then(|o| o.output_srt.clone());
 
-    if hwdev == "ni" {
-        command.arg("-noautoscale");
+    if let Some(backend) = backend {
+        command.args(backend.global_output_args(frames_on_device));
     }
 
-    // if encoder is not empty, use it
-    if encoder != "" {
-        command.arg("-c:v").arg(encoder);
-    }
+    let encoder_args = match (backend, codec) {
+        (Some(backend), Some(codec)) => backend.encoder_args(codec, device),
+        _ => Vec::new(),
+    };
+
+    // Bitrate, rate control and tuning in the encoder's own flags
+    let rate_control = options.and_then(|o| o.rate_control.clone());
+    let recording = options
+        .and_then(|o| o.recording.as_ref())
+        .map(|r| recording_muxer(r, &sidecars.recording_dir));
+    // Streams of the selected program of an MPTS primary input, the whole input otherwise
+    let program = match (source, options.and_then(|o| o.program.as_ref())) {
+        (InputSource::Primary, Some(selection)) => input_program(selection, info.as_ref()),
+        _ => None,
+    };
+    let input_spec = match program {
+        Some(number) => format!("0:p:{number}"),
+        None => "0".to_string(),
+    };
+    let data_maps = scte35_maps(
+        options,
+        &output_format,
+        program,
+        &input_spec,
+        info.as_ref().filter(|_| source == InputSource::Primary),
+    );
+    // Segments without a .ts name can't carry the SCTE-35 stream, it only goes to the main output
+    let recording = recording.map(|muxer| match options.and_then(|o| o.recording.as_ref()) {
+        Some(r) if !data_maps.is_empty() && !r.filename().ends_with(".ts") => {
+            muxer.option("select", "v,a")
+        }
+        _ => muxer,
+    });
+    // The tee muxer has no default streams or codecs, and ffmpeg only picks streams by itself from
+    // the whole input, so the audio is mapped and encoded explicitly
+    let audio = options.and_then(|o| o.audio.clone()).or_else(|| {
+        (recording.is_some() || program.is_some() || !data_maps.is_empty())
+            .then(AudioOptions::default)
+    });
+    let mpegts = options.and_then(|o| o.mpegts.as_ref());
+    let tuning = options.and_then(|o| o.tuning.as_ref());
+    let quality_args = |bitrate: &str, rate_control: Option<&RateControl>| match (backend, codec) {
+        (Some(backend), Some(codec)) => backend.quality_args(codec, bitrate, rate_control, tuning),
+        _ => backend::quality_args(bitrate, rate_control, tuning),
+    };
+
+    if renditions.is_empty() {
+        command.args(quality_args(&bitrate, rate_control.as_ref()));
+        command.args(pass_args(sidecars.pass.as_ref(), 0));
+
+        command.args(&encoder_args);
+
+        // Chroma subsampling
+        if let Some(pixel_format) = &pixel_format {
+            command.arg("-pix_fmt").arg(pixel_format);
+        }
+
+        if let Some(framerate) = &framerate {
+            command.arg("-r").arg(framerate);
+        }
+
+        // Video filters for cropping, debug text, scaling and moving frames between the CPU and the device
+        let mut filters = head;
+        filters.extend(branches[0].iter().cloned());
+
+        match &audio {
+            // ffmpeg picks one video and one audio stream by itself
+            None => {
+                if !filters.is_empty() {
+                    if frames_on_device {
+                        command.arg("-filter_complex").arg(filters.join(","));
+                    } else {
+                        command.arg("-vf").arg(filters.join(","));
+                    }
+                }
+            }
+            // Explicit maps for the selected audio tracks, so the video has to be mapped too
+            Some(audio) => {
+                if !filters.is_empty() && frames_on_device {
+                    command
+                        .arg("-filter_complex")
+                        .arg(format!("[{input_spec}:v]{}[v0]", filters.join(",")))
+                        .arg("-map")
+                        .arg("[v0]");
+                } else {
+                    command.arg("-map").arg(format!("{input_spec}:v:0"));
+                    if !filters.is_empty() {
+                        command.arg("-vf").arg(filters.join(","));
+                    }
+                }
+
+                command.args(audio_maps(&input_spec, Some(audio)));
+                command.args(audio_args(audio));
+                command.args(&data_maps);
+            }
+        }
+
+        // Gop size (keyframe interval)
+        command.arg("-g").arg(gop_size);
+
+        // Output format and destination
+        let muxer = output_muxer(
+            &output_format,
+            packaging.as_ref(),
+            output_srt.as_ref(),
+            rate_control.as_ref().and_then(|rc| rc.muxrate.as_deref()),
+            mpegts,
+            &output,
+        );
+        output_args(&mut command, muxer, name, mpegts, recording.as_ref());
+    } else {
+        // Ladder: decode once, split the frames and encode every rung in the same process
+        command
+            .arg("-filter_complex")
+            .arg(ladder_filter(&input_spec, &head, &branches));
+
+        // The main output is the top rung, renditions follow in order
+        let mut rungs = vec![Rendition {
+            uri: output,
+            width: None,
+            height: None,
+            bitrate: Some(bitrate.clone()),
+            gop_size: Some(gop_size.clone()),
+        }];
+        rungs.extend(renditions);
+
+        for (i, rung) in rungs.iter().enumerate() {
+            let rung_gop_size = rung.gop_size.clone().unwrap_or(gop_size.clone());
+            let rung_bitrate = rung.bitrate.clone().unwrap_or("500k".to_string());
+            let rung_rate_control = rate_control
+                .as_ref()
+                .map(|rc| rc.for_rung(&bitrate, &rung_bitrate));
+
+            command.arg("-map").arg(format!("[v{i}]"));
+            command.args(audio_maps(&input_spec, audio.as_ref()));
+            if let Some(audio) = &audio {
+                command.args(audio_args(audio));
+            }
+            command.args(&data_maps);
 
-    // Chroma subsampling
-    if pixel_format.is_some(){
-        command.arg("-pix_fmt").arg(pixel_format.unwrap());
+            command.args(quality_args(&rung_bitrate, rung_rate_control.as_ref()));
+            command.args(pass_args(sidecars.pass.as_ref(), i));
+
+            command.args(&encoder_args);
+
+            if let Some(pixel_format) = &pixel_format {
+                command.arg("-pix_fmt").arg(pixel_format);
+            }
+
+            if let Some(framerate) = &framerate {
+                command.arg("-r").arg(framerate);
+            }
+
+            // Keyframes forced on the same frames in every rung, so rung keyframes line up
+            command.arg("-g").arg(&rung_gop_size);
+            command.args(match backend {
+                Some(backend) => backend.aligned_keyframe_args(&rung_gop_size),
+                None => backend::aligned_keyframe_args(&rung_gop_size),
+            });
+
+            let muxer = output_muxer(
+                &output_format,
+                packaging.as_ref(),
+                output_srt.as_ref(),
+                rung_rate_control
+                    .as_ref()
+                    .and_then(|rc| rc.muxrate.as_deref()),
+                mpegts,
+                &rung.uri,
+            );
+            // Only the top rung is recorded
+            let recording = recording.as_ref().filter(|_| i == 0);
+            output_args(&mut command, muxer, name, mpegts, recording);
+        }
     }
 
-    if framerate.is_some() {
-        command.arg("-r").arg(framerate.clone().unwrap());
+    // Frames kept on the device need a download for the thumbnail and detection outputs, skipped if the backend has none
+    let download = match decode_codec.and(backend) {
+        Some(backend) if frames_on_device => backend.hwdownload_filter(),
+        Some(backend) => backend.download_filter(),
+        None => None,
+    };
+    if let Some(thumbnail) = thumbnail.filter(|_| !frames_on_device || download.is_some()) {
+        command.args(thumbnail_args(&input_spec, thumbnail, download));
+    }
+    if let Some(detection) = sidecars
+        .detection
+        .as_ref()
+        .filter(|_| !frames_on_device || download.is_some())
+    {
+        command.args(detection_args(&input_spec, detection, download));
     }
 
-    // Gop size (keyframe interval)
     command
-        .arg("-g")
-        .arg(gop_size)
-        // Output format
-        .arg("-f")
-        .arg(output_format.clone());
+}
 
-    if output_format == "mpegts" {
-        command
-            .arg("-metadata")
-            .arg("service_provider=gasket-".to_string() + &utils::get_build_info())
-            .arg("-metadata")
-            .arg("service_name=".to_string() + &name.to_string());
+pub(crate) async fn stream(
+    backend: Option<Arc<dyn EncoderBackend>>,
+    device: Option<usize>,
+    input_codec: Option<String>,
+    run: StreamRun,
+    tx: tokio::sync::broadcast::Sender<StreamLogMessage>,
+    stats_tx: tokio::sync::watch::Sender<Option<StreamStats>>,
+) -> Option<u32> {
+    let StreamRun {
+        uuid,
+        name,
+        input,
+        output,
+        codec,
+        options,
+    } = run;
+    let codec = Codec::from_ffmpeg_name(&codec);
+    create_output_dirs(&output, options.as_ref());
+
+    if let (Some(backend), Some(input_codec)) = (&backend, &input_codec) {
+        if !backend.hw_decode_codecs().contains(&input_codec.as_str()) {
+            log::info!(
+                "Input codec {} can't be decoded on {:?}, decoding on the CPU",
+                input_codec,
+                backend.encoder()
+            );
+        }
     }
 
-    // Video filter for debug text
-    if debug_text {
-        if hwdev == "ni" {
-            command.arg("-filter_complex")
-            .arg(["ni_quadra_drawtext=text='%{localtime\\:%Y-%m-%d %H\\\\\\:%M\\\\\\:%S}':fontcolor=yellow:fontsize=100:x=10:y=10:box=1:boxcolor=black@0.5:boxborderw=5,ni_quadra_drawtext=text='gasket ", 
-                &utils::get_build_info().replace(":", "\\:"),
-                "':fontcolor=white:fontsize=50:x=W-tw-10:y=H-th-10:box=1:boxcolor=black@0.5:boxborderw=5"].concat());
-        } else {
-            command.arg("-vf")
-            .arg(["drawtext=text='%{localtime\\:%Y-%m-%d %H\\\\\\:%M\\\\\\:%S}':fontcolor=yellow:fontsize=100:x=10:y=10:box=1:boxcolor=black@0.5:boxborderw=5,drawtext=text='gasket ", 
-                &utils::get_build_info().replace(":", "\\:"),
-                "':fontcolor=white:fontsize=50:x=W-tw-10:y=H-th-10:box=1:boxcolor=black@0.5:boxborderw=5"].concat());
+    let hw = backend.as_deref().map(|backend| Hardware {
+        backend,
+        device,
+        input_codec,
+    });
+
+    // Stale snapshots of a previous process would make ffmpeg ask before overwriting
+    let thumbnail = thumbnail_path(uuid).map(|path| {
+        if let Some(dir) = std::path::Path::new(&path).parent() {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                log::error!("Could not create thumbnail directory {:?}: {}", dir, e);
+            }
+        }
+        let _ = std::fs::remove_file(&path);
+        Thumbnail {
+            path,
+            interval: args::Args::parse().thumbnail_interval,
+        }
+    });
+
+    // Segments are named by ffmpeg, so only the directory is created here
+    let recording_dir = recording::dir(uuid);
+    if options.as_ref().is_some_and(|o| o.recording.is_some()) {
+        if let Err(e) = std::fs::create_dir_all(&recording_dir) {
+            log::error!(
+                "Could not create recording directory {}: {}",
+                recording_dir,
+                e
+            );
+        }
+    }
+
+    let sidecars = Sidecars {
+        thumbnail,
+        recording_dir,
+        pass: None,
+        detection: options.as_ref().and_then(|o| o.alarms.clone()),
+    };
+    let command = build_command(
+        hw.as_ref(),
+        &name,
+        input,
+        output,
+        codec,
+        options.as_ref(),
+        &sidecars,
+    );
+
+    spawn(command, uuid, tx, stats_tx)
+}
+
+// Container of a job output when the options don't set one
+fn job_format(output: &str) -> &'static str {
+    let extension = output
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_lowercase());
+    match extension.as_deref() {
+        Some("mp4" | "m4v") => "mp4",
+        Some("mov") => "mov",
+        Some("mkv") => "matroska",
+        Some("webm") => "webm",
+        Some("flv") => "flv",
+        _ => "mpegts",
+    }
+}
+
+// Results of an earlier attempt would make ffmpeg ask before overwriting
+fn remove_stale_outputs(output: &str, options: &StreamOptions) {
+    let renditions = options.renditions.iter().flatten().map(|r| r.uri.as_str());
+    for uri in std::iter::once(output).chain(renditions) {
+        if uri.contains("://") {
+            continue;
+        }
+        let path = match &options.packaging {
+            Some(packaging) => packaging.playlist(uri),
+            None => uri.to_string(),
+        };
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+// Current pass of a job, the first of two passes only writes the encoder statistics
+pub(crate) async fn job(
+    backend: Option<Arc<dyn EncoderBackend>>,
+    device: Option<usize>,
+    job: &Job,
+    tx: tokio::sync::broadcast::Sender<StreamLogMessage>,
+    stats_tx: tokio::sync::watch::Sender<Option<StreamStats>>,
+) -> Option<u32> {
+    let codec = Codec::from_ffmpeg_name(&job.codec);
+
+    // The input is read once as fast as possible, and a job only has that one input
+    let mut options = job.options.clone().unwrap_or_default();
+    options.input_mode = Some(InputMode::Once);
+    options.failover = None;
+    options.recording = None;
+    if options.output_format.is_none() {
+        options.output_format = Some(job_format(&job.output).to_string());
+    }
+
+    let mut output = job.output.clone();
+    if job.pass < job.passes() {
+        options.output_format = Some("null".to_string());
+        options.packaging = None;
+        for rendition in options.renditions.iter_mut().flatten() {
+            rendition.uri = "/dev/null".to_string();
         }
+        output = "/dev/null".to_string();
+    } else {
+        create_output_dirs(&output, Some(&options));
+        remove_stale_outputs(&output, &options);
     }
 
-    // Output to destination
-    command.arg(output);
+    let hw = backend.as_deref().map(|backend| Hardware {
+        backend,
+        device,
+        input_codec: job.input_info().and_then(|i| i.video_codec.clone()),
+    });
 
+    let pass = job.two_pass.then(|| Pass {
+        number: job.pass,
+        log_prefix: pass_log_prefix(job.id),
+    });
+    let sidecars = Sidecars {
+        thumbnail: None,
+        recording_dir: recording::dir(job.id),
+        pass,
+        detection: None,
+    };
+    let command = build_command(
+        hw.as_ref(),
+        &job.name,
+        Input {
+            uri: job.input.clone(),
+            source: InputSource::Primary,
+            info: job.input_info().cloned(),
+        },
+        output,
+        codec,
+        Some(&options),
+        &sidecars,
+    );
+
+    spawn(command, job.id, tx, stats_tx)
+}
+
+// Start the process, forwarding progress to stats_tx and output lines and the exit to tx
+fn spawn(
+    mut command: TokioCommand,
+    uuid: Uuid,
+    tx: tokio::sync::broadcast::Sender<StreamLogMessage>,
+    stats_tx: tokio::sync::watch::Sender<Option<StreamStats>>,
+) -> Option<u32> {
     // get command as string, for logging
-    log::info!("Running command: {:?}", command);
+    log::info!(
+        "Running command: {}",
+        utils::redact_secrets(&format!("{:?}", command))
+    );
 
     command.stdout(Stdio::piped());
     command.stderr(Stdio::piped());
@@ -297,45 +1423,303 @@ pub(crate) async fn stream(
 
     log::info!("Stream {} started with PID: {:?}", uuid, pid);
 
-    tokio::spawn(async move {
-        let status = child.wait().await.expect("failed to wait on child");
-        log::info!("Stream {} exited with status: {}", uuid, status);
-        tx_exit
-            .send(StreamLogMessage {
-                stream_id: uuid,
-                message: format!("Stream {} exited with status: {}", uuid, status),
-                error: StreamLogLevel::Stdout,
-            })
-            .unwrap();
-    });
-
-    // Send stdout and stderr to the tx broadcaster
+    // Parse progress from stdout, send anything else and stderr to the tx broadcaster
     let tx_out = tx.clone();
-    tokio::spawn(async move {
+    let stdout_task = tokio::spawn(async move {
+        let mut progress = ProgressParser::default();
         while let Some(line) = reader_out.next_line().await.unwrap() {
+            if line.contains('=') {
+                if let Some(stats) = progress.push(&line) {
+                    stats_tx.send_replace(Some(stats));
+                }
+                continue;
+            }
+
             tx_out
                 .send(StreamLogMessage {
                     stream_id: uuid,
                     message: line,
                     error: StreamLogLevel::Stdout,
+                    exit_code: None,
                 })
                 .unwrap();
         }
     });
 
     let tx_err = tx.clone();
-    tokio::spawn(async move {
+    let stderr_task = tokio::spawn(async move {
         while let Some(line) = reader_err.next_line().await.unwrap() {
+            // ffmpeg echoes the input and output URIs
             tx_err
                 .send(StreamLogMessage {
                     stream_id: uuid,
-                    message: line,
+                    message: utils::redact_secrets(&line),
                     error: StreamLogLevel::Stderr,
+                    exit_code: None,
                 })
                 .unwrap();
         }
     });
 
+    // The only reaper of the process, the monitor learns about the exit from this message. It is
+    // sent after the last output line, so a failed job's error is its final stderr line
+    tokio::spawn(async move {
+        let (message, exit_code) = match child.wait().await {
+            Ok(status) => (
+                format!("Stream {} exited with status: {}", uuid, status),
+                status.code(),
+            ),
+            Err(e) => (
+                format!("Stream {} could not be waited on: {}", uuid, e),
+                None,
+            ),
+        };
+        log::info!("{}", message);
+        let _ = stdout_task.await;
+        let _ = stderr_task.await;
+        let _ = tx_exit.send(StreamLogMessage {
+            stream_id: uuid,
+            message,
+            error: StreamLogLevel::Exit,
+            exit_code,
+        });
+    });
+
     // Return handles to stdout and stderr
     return pid;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Cpu, Qsv, Vaapi};
+    use crate::state::{AudioTrack, DataStream, Detector};
+
+    const DEVICE: &str = "/dev/dri/renderD128";
+
+    fn args(backend: &dyn EncoderBackend, input_codec: &str, options: &StreamOptions) -> String {
+        let hw = Hardware {
+            backend,
+            device: None,
+            input_codec: Some(input_codec.to_string()),
+        };
+        let input = Input {
+            uri: "udp://239.0.0.1:5000".to_string(),
+            source: InputSource::Primary,
+            info: None,
+        };
+        let sidecars = Sidecars {
+            thumbnail: None,
+            recording_dir: String::new(),
+            pass: None,
+            detection: None,
+        };
+        let command = build_command(
+            Some(&hw),
+            "test",
+            input,
+            "udp://239.0.0.2:5000".to_string(),
+            Some(Codec::H264),
+            Some(options),
+            &sidecars,
+        );
+        command
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn no_overlay() -> StreamOptions {
+        StreamOptions {
+            debug_text: Some(false),
+            ..Default::default()
+        }
+    }
+
+    fn scaled() -> StreamOptions {
+        StreamOptions {
+            width: Some(1280),
+            height: Some(720),
+            ..no_overlay()
+        }
+    }
+
+    #[test]
+    fn cpu_decodes_and_encodes_in_software() {
+        let args = args(&Cpu, "h264", &scaled());
+        assert!(!args.contains("-hwaccel"));
+        assert!(!args.contains("hwupload"));
+        assert!(args.contains("-vf scale=1280:720"));
+        assert!(args.contains("-c:v libx264"));
+    }
+
+    #[test]
+    fn qsv_keeps_decoded_frames_on_the_device() {
+        let qsv = Qsv {
+            device: DEVICE.to_string(),
+        };
+        let args = args(&qsv, "hevc", &no_overlay());
+        assert!(args.contains(
+            "-init_hw_device vaapi=va:/dev/dri/renderD128 -init_hw_device qsv=qs@va -filter_hw_device qs \
+             -hwaccel qsv -hwaccel_device qs -hwaccel_output_format qsv -i udp://239.0.0.1:5000"
+        ));
+        assert!(!args.contains("hwupload"));
+        assert!(args.contains("-c:v h264_qsv"));
+    }
+
+    #[test]
+    fn qsv_uploads_frames_filtered_on_the_cpu() {
+        let qsv = Qsv {
+            device: DEVICE.to_string(),
+        };
+        let args = args(&qsv, "h264", &scaled());
+        assert!(args.contains("-hwaccel qsv -hwaccel_device qs -i"));
+        assert!(!args.contains("-hwaccel_output_format"));
+        assert!(args.contains("scale=1280:720,format=nv12,hwupload=extra_hw_frames=64"));
+    }
+
+    #[test]
+    fn vaapi_keeps_decoded_frames_on_the_device() {
+        let vaapi = Vaapi {
+            device: DEVICE.to_string(),
+        };
+        let args = args(&vaapi, "h264", &no_overlay());
+        assert!(args.contains(
+            "-init_hw_device vaapi=va:/dev/dri/renderD128 -filter_hw_device va \
+             -hwaccel vaapi -hwaccel_device va -hwaccel_output_format vaapi -i udp://239.0.0.1:5000"
+        ));
+        assert!(!args.contains("hwupload"));
+        assert!(args.contains("-c:v h264_vaapi"));
+    }
+
+    #[test]
+    fn vaapi_decodes_unsupported_codecs_on_the_cpu() {
+        let vaapi = Vaapi {
+            device: DEVICE.to_string(),
+        };
+        let args = args(&vaapi, "mpeg4", &scaled());
+        assert!(!args.contains("-hwaccel"));
+        assert!(args.contains("scale=1280:720,format=nv12,hwupload"));
+        assert!(args.contains("-c:v h264_vaapi"));
+    }
+
+    #[test]
+    fn thumbnails_are_decimated_before_the_download() {
+        let thumbnail = Thumbnail {
+            path: "/tmp/thumbnail.jpg".to_string(),
+            interval: 5,
+        };
+        let args = thumbnail_args("0", &thumbnail, Some("hwdownload,format=nv12")).join(" ");
+        assert!(args.starts_with("-map 0:v:0 -vf fps=1/5,hwdownload,format=nv12,scale=320:-2 "));
+    }
+
+    #[test]
+    fn only_configured_detectors_run() {
+        assert!(detection_args("0", &AlarmOptions::default(), None).is_empty());
+
+        let alarms = AlarmOptions {
+            black: Some(Detector {
+                enabled: None,
+                duration: None,
+                threshold: None,
+            }),
+            ..Default::default()
+        };
+        let args = detection_args("0", &alarms, Some("hwdownload,format=nv12")).join(" ");
+        assert!(args.starts_with(
+            "-map 0:v:0 -vf fps=5,hwdownload,format=nv12,scale=320:-2,blackdetect=d=2:pic_th=0.98,"
+        ));
+        assert!(!args.contains("freezedetect"));
+        assert!(!args.contains("silencedetect"));
+    }
+
+    fn mpts() -> InputInfo {
+        let track = |program| AudioTrack {
+            index: 0,
+            codec: Some("aac".to_string()),
+            language: None,
+            channels: Some(2),
+            channel_layout: None,
+            sample_rate: Some(48000),
+            program: Some(program),
+        };
+        let scte35 = |index, pid, program| DataStream {
+            index,
+            codec: Some("scte_35".to_string()),
+            pid: Some(pid),
+            program: Some(program),
+        };
+        InputInfo {
+            audio_tracks: vec![
+                AudioTrack {
+                    index: 0,
+                    ..track(1)
+                },
+                AudioTrack {
+                    index: 1,
+                    ..track(2)
+                },
+                AudioTrack {
+                    index: 2,
+                    ..track(2)
+                },
+            ],
+            data_streams: vec![scte35(2, 0x1f6, 1), scte35(6, 0x2f6, 2)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn scte35_is_mapped_through_the_program() {
+        let options = StreamOptions {
+            scte35: Some(true),
+            ..Default::default()
+        };
+        let info = mpts();
+        assert_eq!(
+            scte35_maps(Some(&options), "mpegts", Some(2), "0:p:2", Some(&info)).join(" "),
+            "-map 0:p:2:i:758 -c:d copy"
+        );
+        assert_eq!(
+            scte35_maps(Some(&options), "mpegts", None, "0", Some(&info)).join(" "),
+            "-map 0:i:502 -map 0:i:758 -c:d copy"
+        );
+    }
+
+    #[test]
+    fn audio_tracks_are_numbered_per_program() {
+        let info = mpts();
+        let indexes = |program| {
+            info.program_audio_tracks(program)
+                .iter()
+                .map(|t| t.index)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(indexes(None), vec![0, 1, 2]);
+        assert_eq!(indexes(Some(2)), vec![0, 1]);
+        assert_eq!(indexes(Some(3)), Vec::<u32>::new());
+    }
+
+    #[tokio::test]
+    async fn exit_is_sent_after_the_last_output_line() {
+        let mut command = TokioCommand::new("sh");
+        command.args(["-c", "echo first >&2; echo last >&2; exit 3"]);
+        let (tx, mut rx) = tokio::sync::broadcast::channel(16);
+        let (stats_tx, _stats_rx) = tokio::sync::watch::channel(None);
+        spawn(command, Uuid::new_v4(), tx, stats_tx);
+
+        let mut stderr = Vec::new();
+        let exit = loop {
+            let msg = rx.recv().await.unwrap();
+            match msg.error {
+                StreamLogLevel::Stderr => stderr.push(msg.message),
+                StreamLogLevel::Exit => break msg,
+                _ => {}
+            }
+        };
+        assert_eq!(stderr, vec!["first", "last"]);
+        assert_eq!(exit.exit_code, Some(3));
+    }
+}
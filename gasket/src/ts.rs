@@ -0,0 +1,847 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+
+// MPEG-TS analysis in the spirit of ETSI TR 101 290 priority 1 and 2, fed with the raw input bytes.
+// Nothing here touches the network, see tap.rs
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0x0000;
+const NULL_PID: u16 = 0x1FFF;
+
+// Sync is lost after this many bad sync bytes in a row, and regained after as many good ones
+const SYNC_PACKETS: u32 = 5;
+// Longest gap between two PAT or PMT sections
+const PSI_INTERVAL: Duration = Duration::from_millis(500);
+// Longest gap in a PID referenced by a PMT
+const PID_INTERVAL: Duration = Duration::from_secs(5);
+// Longest gap between two PCRs, and the jump counted as a discontinuity, in 27 MHz ticks
+const PCR_REPETITION: u64 = 40 * 27_000;
+const PCR_DISCONTINUITY: u64 = 100 * 27_000;
+// PCRs wrap around after 2^33 ticks of the 90 kHz base
+const PCR_WRAP: u64 = (1 << 33) * 300;
+// Window of the bitrates and PCR jitter
+const WINDOW: Duration = Duration::from_secs(1);
+// How often the PSI and PID gaps are checked
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Error counters, named after the TR 101 290 indicators
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Errors {
+    // Priority 1
+    pub(crate) sync_loss: u64,
+    pub(crate) sync_byte: u64,
+    pub(crate) pat: u64,
+    pub(crate) continuity_count: u64,
+    pub(crate) pmt: u64,
+    pub(crate) pid: u64,
+    // Priority 2
+    pub(crate) transport: u64,
+    pub(crate) crc: u64,
+    pub(crate) pcr_repetition: u64,
+    pub(crate) pcr_discontinuity: u64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ElementaryStream {
+    pub(crate) pid: u16,
+    pub(crate) stream_type: u8,
+    pub(crate) kind: &'static str,
+}
+
+// Program from the PAT, with what its PMT lists
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Program {
+    pub(crate) number: u16,
+    pub(crate) pmt_pid: u16,
+    pub(crate) pcr_pid: Option<u16>,
+    pub(crate) streams: Vec<ElementaryStream>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub(crate) struct PcrReport {
+    pub(crate) count: u64,
+    // Milliseconds between the last two PCRs, and the longest gap so far
+    pub(crate) interval_ms: Option<f64>,
+    pub(crate) max_interval_ms: f64,
+    // Largest difference between the PCR and the arrival time over the last window, in milliseconds
+    pub(crate) jitter_ms: f64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub(crate) struct PidReport {
+    pub(crate) pid: u16,
+    pub(crate) kind: &'static str,
+    pub(crate) packets: u64,
+    // Bits per second over the last window
+    pub(crate) bitrate: u64,
+    pub(crate) continuity_errors: u64,
+    pub(crate) scrambled: bool,
+    pub(crate) pcr: Option<PcrReport>,
+}
+
+// Snapshot of an Analyzer, see GET /stream/:uuid/ts
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub(crate) struct Report {
+    pub(crate) packets: u64,
+    pub(crate) bitrate: u64,
+    pub(crate) synced: bool,
+    // Seconds since the last packet
+    pub(crate) idle: Option<f64>,
+    pub(crate) errors: Errors,
+    pub(crate) programs: Vec<Program>,
+    pub(crate) pids: Vec<PidReport>,
+}
+
+#[derive(Default)]
+struct Pcr {
+    count: u64,
+    // Last PCR and when its packet arrived
+    last: Option<(u64, Instant)>,
+    interval: Option<u64>,
+    max_interval: u64,
+    jitter: f64,
+    window_jitter: f64,
+}
+
+#[derive(Default)]
+struct Pid {
+    packets: u64,
+    window_bytes: u64,
+    bitrate: u64,
+    // Continuity counter of the last packet with a payload, and whether it was already repeated
+    last_cc: Option<u8>,
+    duplicate: bool,
+    continuity_errors: u64,
+    scrambled: bool,
+    last_seen: Option<Instant>,
+    // Counted once per gap
+    missing: bool,
+    pcr: Option<Pcr>,
+    // PSI section being reassembled
+    section: Vec<u8>,
+}
+
+// Last PMT of a program and when it arrived, gaps are counted from the PAT that announced it
+struct ProgramState {
+    program: Program,
+    announced: Instant,
+    last_pmt: Option<Instant>,
+    missing: bool,
+}
+
+pub(crate) struct Analyzer {
+    // Bytes of a packet split over two pushes, or skipped while resyncing
+    carry: Vec<u8>,
+    synced: bool,
+    good: u32,
+    bad: u32,
+    packets: u64,
+    window_bytes: u64,
+    bitrate: u64,
+    window_start: Option<Instant>,
+    last_check: Option<Instant>,
+    last_packet: Option<Instant>,
+    // PAT gaps are counted from the first packet until one arrives
+    last_pat: Option<Instant>,
+    pat_missing: bool,
+    errors: Errors,
+    pids: BTreeMap<u16, Pid>,
+    // By program number
+    programs: BTreeMap<u16, ProgramState>,
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Analyzer::new()
+    }
+}
+
+// CRC-32/MPEG-2 of a PSI section, zero over a section including its CRC
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// Common stream_type values of a PMT
+pub(crate) fn stream_type_name(stream_type: u8) -> &'static str {
+    match stream_type {
+        0x01 | 0x02 => "mpeg2video",
+        0x03 | 0x04 => "mp2",
+        0x0F => "aac",
+        0x11 => "aac_latm",
+        0x15 => "id3",
+        0x1B => "h264",
+        0x24 => "hevc",
+        0x81 => "ac3",
+        0x86 => "scte35",
+        0x87 => "eac3",
+        0x06 => "private",
+        _ => "unknown",
+    }
+}
+
+// PCR of a packet, in 27 MHz ticks
+fn pcr(packet: &[u8]) -> Option<u64> {
+    let adaptation = packet[3] & 0x20 != 0;
+    if !adaptation || packet[4] < 7 || packet[5] & 0x10 == 0 {
+        return None;
+    }
+
+    let b = &packet[6..12];
+    let base = ((b[0] as u64) << 25)
+        | ((b[1] as u64) << 17)
+        | ((b[2] as u64) << 9)
+        | ((b[3] as u64) << 1)
+        | ((b[4] as u64) >> 7);
+    let extension = (((b[4] & 0x01) as u64) << 8) | b[5] as u64;
+    Some(base * 300 + extension)
+}
+
+impl Analyzer {
+    pub(crate) fn new() -> Analyzer {
+        Analyzer {
+            carry: Vec::with_capacity(PACKET_SIZE),
+            synced: true,
+            good: 0,
+            bad: 0,
+            packets: 0,
+            window_bytes: 0,
+            bitrate: 0,
+            window_start: None,
+            last_check: None,
+            last_packet: None,
+            last_pat: None,
+            pat_missing: false,
+            errors: Errors::default(),
+            pids: BTreeMap::new(),
+            programs: BTreeMap::new(),
+        }
+    }
+
+    // Raw bytes as received, e.g. one UDP datagram of 7 packets or a chunk of a .ts file
+    pub(crate) fn push(&mut self, data: &[u8], now: Instant) {
+        self.carry.extend_from_slice(data);
+
+        let mut offset = 0;
+        while self.carry.len() - offset >= PACKET_SIZE {
+            if self.carry[offset] != SYNC_BYTE {
+                // Resync on the next sync byte followed by another one a packet later, a 0x47
+                // in the garbage doesn't count. Every packet length skipped is a bad sync byte
+                let carry = &self.carry;
+                let next = (offset + 1..carry.len())
+                    .find(|p| {
+                        carry[*p] == SYNC_BYTE
+                            && carry.get(p + PACKET_SIZE).is_none_or(|b| *b == SYNC_BYTE)
+                    })
+                    .unwrap_or(carry.len());
+                let bad = (next - offset).div_ceil(PACKET_SIZE) as u32;
+                offset = next;
+
+                self.errors.sync_byte += bad as u64;
+                self.good = 0;
+                self.bad += bad;
+                if self.synced && self.bad >= SYNC_PACKETS {
+                    self.synced = false;
+                    self.errors.sync_loss += 1;
+                }
+                continue;
+            }
+
+            self.bad = 0;
+            self.good += 1;
+            if !self.synced && self.good >= SYNC_PACKETS {
+                self.synced = true;
+            }
+
+            let packet: [u8; PACKET_SIZE] =
+                self.carry[offset..offset + PACKET_SIZE].try_into().unwrap();
+            self.packet(&packet, now);
+            offset += PACKET_SIZE;
+        }
+        self.carry.drain(..offset);
+
+        self.tick(now);
+    }
+
+    fn packet(&mut self, packet: &[u8; PACKET_SIZE], now: Instant) {
+        self.packets += 1;
+        self.window_bytes += PACKET_SIZE as u64;
+        self.last_packet = Some(now);
+        self.window_start.get_or_insert(now);
+        self.last_check.get_or_insert(now);
+        self.last_pat.get_or_insert(now);
+
+        let pid_number = (((packet[1] & 0x1F) as u16) << 8) | packet[2] as u16;
+        let pid = self.pids.entry(pid_number).or_default();
+        pid.packets += 1;
+        pid.window_bytes += PACKET_SIZE as u64;
+        pid.last_seen = Some(now);
+        pid.missing = false;
+
+        // Corrupted on the way, nothing else in the packet can be trusted
+        if packet[1] & 0x80 != 0 {
+            self.errors.transport += 1;
+            return;
+        }
+        if pid_number == NULL_PID {
+            return;
+        }
+
+        let payload_unit_start = packet[1] & 0x40 != 0;
+        let scrambled = packet[3] & 0xC0 != 0;
+        let adaptation = packet[3] & 0x20 != 0;
+        let has_payload = packet[3] & 0x10 != 0;
+        let cc = packet[3] & 0x0F;
+        pid.scrambled = scrambled;
+
+        let discontinuity = adaptation && packet[4] > 0 && packet[5] & 0x80 != 0;
+
+        // The counter only moves with a payload, a packet may be repeated once
+        if has_payload {
+            if let Some(last) = pid.last_cc {
+                if discontinuity {
+                    pid.duplicate = false;
+                } else if cc == last && !pid.duplicate {
+                    pid.duplicate = true;
+                } else if cc != (last + 1) & 0x0F {
+                    pid.continuity_errors += 1;
+                    self.errors.continuity_count += 1;
+                    pid.duplicate = false;
+                } else {
+                    pid.duplicate = false;
+                }
+            }
+            pid.last_cc = Some(cc);
+        }
+
+        if let Some(value) = pcr(packet) {
+            let state = pid.pcr.get_or_insert_with(Pcr::default);
+            state.count += 1;
+            if let Some((last, arrived)) = state.last.filter(|_| !discontinuity) {
+                let interval = (value + PCR_WRAP - last) % PCR_WRAP;
+                if interval > PCR_DISCONTINUITY {
+                    self.errors.pcr_discontinuity += 1;
+                } else {
+                    if interval > PCR_REPETITION {
+                        self.errors.pcr_repetition += 1;
+                    }
+                    state.interval = Some(interval);
+                    state.max_interval = state.max_interval.max(interval);
+
+                    let pcr_ms = interval as f64 / 27_000.0;
+                    let arrival_ms = now.duration_since(arrived).as_secs_f64() * 1000.0;
+                    state.window_jitter = state.window_jitter.max((arrival_ms - pcr_ms).abs());
+                }
+            }
+            state.last = Some((value, now));
+        }
+
+        if !has_payload {
+            return;
+        }
+        let start = if adaptation {
+            5 + packet[4] as usize
+        } else {
+            4
+        };
+        if start >= PACKET_SIZE {
+            return;
+        }
+
+        let is_psi = pid_number == PAT_PID
+            || self
+                .programs
+                .values()
+                .any(|p| p.program.pmt_pid == pid_number);
+        if is_psi {
+            if pid_number == PAT_PID && scrambled {
+                self.errors.pat += 1;
+                return;
+            }
+            if scrambled {
+                self.errors.pmt += 1;
+                return;
+            }
+            self.psi(pid_number, &packet[start..], payload_unit_start, now);
+        }
+    }
+
+    // Reassemble sections of a PSI PID, a packet may end one section and start the next
+    fn psi(&mut self, pid_number: u16, payload: &[u8], payload_unit_start: bool, now: Instant) {
+        let mut sections = Vec::new();
+        {
+            let pid = self.pids.entry(pid_number).or_default();
+            let mut data = payload;
+            if payload_unit_start {
+                let pointer = data[0] as usize;
+                if pointer + 1 > data.len() {
+                    pid.section.clear();
+                    return;
+                }
+                if !pid.section.is_empty() {
+                    pid.section.extend_from_slice(&data[1..pointer + 1]);
+                    sections.push(std::mem::take(&mut pid.section));
+                }
+                data = &data[pointer + 1..];
+                pid.section.clear();
+                pid.section.extend_from_slice(data);
+            } else if !pid.section.is_empty() {
+                pid.section.extend_from_slice(data);
+            }
+
+            // Complete sections, stuffing bytes end the packet
+            loop {
+                let section = &pid.section;
+                if section.len() < 3 || section[0] == 0xFF {
+                    break;
+                }
+                let length = 3 + ((((section[1] & 0x0F) as usize) << 8) | section[2] as usize);
+                if section.len() < length {
+                    break;
+                }
+                sections.push(pid.section[..length].to_vec());
+                pid.section.drain(..length);
+            }
+            if pid.section.first() == Some(&0xFF) {
+                pid.section.clear();
+            }
+        }
+
+        for section in sections {
+            self.section(pid_number, &section, now);
+        }
+    }
+
+    fn section(&mut self, pid_number: u16, section: &[u8], now: Instant) {
+        if section.len() < 3 {
+            return;
+        }
+        let length = 3 + ((((section[1] & 0x0F) as usize) << 8) | section[2] as usize);
+        if section.len() < length || length < 12 {
+            return;
+        }
+        let section = &section[..length];
+        let table_id = section[0];
+
+        if pid_number == PAT_PID && table_id != 0x00 {
+            self.errors.pat += 1;
+            return;
+        }
+        if crc32(section) != 0 {
+            self.errors.crc += 1;
+            return;
+        }
+
+        // Entries between the 8 byte header and the CRC
+        let body = &section[8..length - 4];
+        if pid_number == PAT_PID {
+            self.last_pat = Some(now);
+            self.pat_missing = false;
+
+            let mut numbers = Vec::new();
+            for entry in body.chunks_exact(4) {
+                let number = ((entry[0] as u16) << 8) | entry[1] as u16;
+                let pmt_pid = (((entry[2] & 0x1F) as u16) << 8) | entry[3] as u16;
+                // Program 0 points at the NIT
+                if number == 0 {
+                    continue;
+                }
+                numbers.push(number);
+                let state = self.programs.entry(number).or_insert(ProgramState {
+                    program: Program {
+                        number,
+                        pmt_pid,
+                        pcr_pid: None,
+                        streams: Vec::new(),
+                    },
+                    announced: now,
+                    last_pmt: None,
+                    missing: false,
+                });
+                if state.program.pmt_pid != pmt_pid {
+                    state.program.pmt_pid = pmt_pid;
+                    state.announced = now;
+                    state.last_pmt = None;
+                }
+            }
+            self.programs.retain(|number, _| numbers.contains(number));
+            return;
+        }
+
+        // PMT, another table on a PMT PID is left alone
+        if table_id != 0x02 {
+            return;
+        }
+        let number = ((section[3] as u16) << 8) | section[4] as u16;
+        let Some(state) = self.programs.get_mut(&number) else {
+            return;
+        };
+        if state.program.pmt_pid != pid_number || body.len() < 4 {
+            return;
+        }
+
+        let pcr_pid = (((body[0] & 0x1F) as u16) << 8) | body[1] as u16;
+        let info_length = (((body[2] & 0x0F) as usize) << 8) | body[3] as usize;
+        let mut streams = Vec::new();
+        let mut rest = body.get(4 + info_length..).unwrap_or_default();
+        while rest.len() >= 5 {
+            let stream_type = rest[0];
+            let pid = (((rest[1] & 0x1F) as u16) << 8) | rest[2] as u16;
+            let es_info_length = (((rest[3] & 0x0F) as usize) << 8) | rest[4] as usize;
+            streams.push(ElementaryStream {
+                pid,
+                stream_type,
+                kind: stream_type_name(stream_type),
+            });
+            rest = rest.get(5 + es_info_length..).unwrap_or_default();
+        }
+
+        state.program.pcr_pid = (pcr_pid != NULL_PID).then_some(pcr_pid);
+        state.program.streams = streams;
+        state.last_pmt = Some(now);
+        state.missing = false;
+    }
+
+    // Gaps in the PAT, PMTs and referenced PIDs, and the end of a measurement window
+    fn tick(&mut self, now: Instant) {
+        if let Some(start) = self.window_start {
+            let elapsed = now.duration_since(start);
+            if elapsed >= WINDOW {
+                let seconds = elapsed.as_secs_f64();
+                self.bitrate = (self.window_bytes as f64 * 8.0 / seconds) as u64;
+                self.window_bytes = 0;
+                for pid in self.pids.values_mut() {
+                    pid.bitrate = (pid.window_bytes as f64 * 8.0 / seconds) as u64;
+                    pid.window_bytes = 0;
+                    if let Some(pcr) = pid.pcr.as_mut() {
+                        pcr.jitter = pcr.window_jitter;
+                        pcr.window_jitter = 0.0;
+                    }
+                }
+                self.window_start = Some(now);
+            }
+        }
+
+        if self
+            .last_check
+            .is_none_or(|t| now.duration_since(t) < CHECK_INTERVAL)
+        {
+            return;
+        }
+        self.last_check = Some(now);
+
+        if !self.pat_missing
+            && self
+                .last_pat
+                .is_some_and(|t| now.duration_since(t) > PSI_INTERVAL)
+        {
+            self.pat_missing = true;
+            self.errors.pat += 1;
+        }
+
+        let mut referenced = HashSet::new();
+        for state in self.programs.values_mut() {
+            let since = state.last_pmt.unwrap_or(state.announced);
+            if !state.missing && now.duration_since(since) > PSI_INTERVAL {
+                state.missing = true;
+                self.errors.pmt += 1;
+            }
+            referenced.extend(state.program.streams.iter().map(|s| s.pid));
+        }
+
+        for number in referenced {
+            let pid = self.pids.entry(number).or_default();
+            let since = pid.last_seen.get_or_insert(now);
+            if !pid.missing && now.duration_since(*since) > PID_INTERVAL {
+                pid.missing = true;
+                self.errors.pid += 1;
+            }
+        }
+    }
+
+    fn pid_kind(&self, number: u16) -> &'static str {
+        match number {
+            PAT_PID => return "pat",
+            0x0001 => return "cat",
+            0x0010 => return "nit",
+            0x0011 => return "sdt",
+            0x0012 => return "eit",
+            NULL_PID => return "null",
+            _ => {}
+        }
+        for state in self.programs.values() {
+            if state.program.pmt_pid == number {
+                return "pmt";
+            }
+            if let Some(stream) = state.program.streams.iter().find(|s| s.pid == number) {
+                return stream.kind;
+            }
+            if state.program.pcr_pid == Some(number) {
+                return "pcr";
+            }
+        }
+        "unreferenced"
+    }
+
+    pub(crate) fn report(&self, now: Instant) -> Report {
+        let pids = self
+            .pids
+            .iter()
+            .filter(|(_, pid)| pid.packets > 0)
+            .map(|(number, pid)| PidReport {
+                pid: *number,
+                kind: self.pid_kind(*number),
+                packets: pid.packets,
+                bitrate: pid.bitrate,
+                continuity_errors: pid.continuity_errors,
+                scrambled: pid.scrambled,
+                pcr: pid.pcr.as_ref().map(|pcr| PcrReport {
+                    count: pcr.count,
+                    interval_ms: pcr.interval.map(|i| i as f64 / 27_000.0),
+                    max_interval_ms: pcr.max_interval as f64 / 27_000.0,
+                    jitter_ms: pcr.jitter,
+                }),
+            })
+            .collect();
+
+        Report {
+            packets: self.packets,
+            bitrate: self.bitrate,
+            synced: self.synced,
+            idle: self
+                .last_packet
+                .map(|t| now.duration_since(t).as_secs_f64()),
+            errors: self.errors.clone(),
+            programs: self.programs.values().map(|p| p.program.clone()).collect(),
+            pids,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // See tests/fixtures/make_ts.py, 6 packets every 20 ms with a PCR on the video PID each time
+    const SPTS: &[u8] = include_bytes!("../tests/fixtures/spts.ts");
+    const PCR_WRAP_TS: &[u8] = include_bytes!("../tests/fixtures/pcr-wrap.ts");
+    const PACKET_TIME: Duration = Duration::from_nanos(20_000_000 / 6);
+    const VIDEO_PID: u16 = 0x0100;
+    const AUDIO_PID: u16 = 0x0101;
+
+    type Packet = [u8; PACKET_SIZE];
+
+    fn packets(ts: &[u8]) -> Vec<Packet> {
+        ts.chunks_exact(PACKET_SIZE)
+            .map(|packet| packet.try_into().unwrap())
+            .collect()
+    }
+
+    fn pid(packet: &Packet) -> u16 {
+        (((packet[1] & 0x1F) as u16) << 8) | packet[2] as u16
+    }
+
+    // Positions of the packets of a PID, the video ones carrying a PCR start a frame
+    fn positions(packets: &[Packet], number: u16) -> Vec<usize> {
+        (0..packets.len())
+            .filter(|i| pid(&packets[*i]) == number)
+            .filter(|i| number != VIDEO_PID || pcr(&packets[*i]).is_some())
+            .collect()
+    }
+
+    // Pushed at the muxrate in datagrams of 7 packets, like a udp input
+    fn analyze(packets: &[Packet]) -> Report {
+        let mut analyzer = Analyzer::new();
+        let start = Instant::now();
+        let mut now = start;
+        for (i, datagram) in packets.chunks(7).enumerate() {
+            now = start + PACKET_TIME * (i * 7) as u32;
+            analyzer.push(&datagram.concat(), now);
+        }
+        analyzer.report(now)
+    }
+
+    fn pid_report(report: &Report, number: u16) -> &PidReport {
+        report.pids.iter().find(|p| p.pid == number).unwrap()
+    }
+
+    #[test]
+    fn parses_pat_and_pmt() {
+        let report = analyze(&packets(SPTS));
+
+        assert_eq!(
+            report.programs,
+            vec![Program {
+                number: 1,
+                pmt_pid: 0x1000,
+                pcr_pid: Some(VIDEO_PID),
+                streams: vec![
+                    ElementaryStream {
+                        pid: VIDEO_PID,
+                        stream_type: 0x1B,
+                        kind: "h264",
+                    },
+                    ElementaryStream {
+                        pid: AUDIO_PID,
+                        stream_type: 0x0F,
+                        kind: "aac",
+                    },
+                ],
+            }]
+        );
+        let kinds: Vec<(u16, &str)> = report.pids.iter().map(|p| (p.pid, p.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (0x0000, "pat"),
+                (0x0011, "sdt"),
+                (VIDEO_PID, "h264"),
+                (AUDIO_PID, "aac"),
+                (0x1000, "pmt"),
+                (0x1FFF, "null"),
+            ]
+        );
+        assert_eq!(report.packets, 300);
+        assert!(report.synced);
+        assert_eq!(report.errors, Errors::default());
+    }
+
+    #[test]
+    fn counts_crc_errors() {
+        let mut packets = packets(SPTS);
+        // Last byte of the CRC of the first PMT, after the pointer field and section header
+        let pmt = (0..packets.len())
+            .find(|i| pid(&packets[*i]) == 0x1000)
+            .unwrap();
+        let end = 5 + 3 + packets[pmt][7] as usize;
+        packets[pmt][end - 1] ^= 0xFF;
+
+        let report = analyze(&packets);
+        assert_eq!(report.errors.crc, 1);
+    }
+
+    #[test]
+    fn measures_the_pcr_interval() {
+        let report = analyze(&packets(SPTS));
+        let pcr = pid_report(&report, VIDEO_PID).pcr.clone().unwrap();
+        assert_eq!(pcr.count, 50);
+        assert_eq!(pcr.interval_ms, Some(20.0));
+        // The PAT, PMT and SDT every 100 ms delay the next PCR by 3 packets
+        assert_eq!(pcr.max_interval_ms, 30.0);
+    }
+
+    #[test]
+    fn counts_late_and_missing_pcrs() {
+        // Two frames lost, 60 ms without a PCR
+        let mut late = packets(SPTS);
+        for i in positions(&late, VIDEO_PID)[10..12].iter().rev() {
+            late.remove(*i);
+        }
+        let report = analyze(&late);
+        assert_eq!(report.errors.pcr_repetition, 1);
+        assert_eq!(report.errors.pcr_discontinuity, 0);
+        let pcr = pid_report(&report, VIDEO_PID).pcr.clone().unwrap();
+        assert_eq!(pcr.max_interval_ms, 60.0);
+
+        // Ten frames lost, the 220 ms jump is a discontinuity
+        let mut jump = packets(SPTS);
+        for i in positions(&jump, VIDEO_PID)[10..20].iter().rev() {
+            jump.remove(*i);
+        }
+        let report = analyze(&jump);
+        assert_eq!(report.errors.pcr_discontinuity, 1);
+        assert_eq!(report.errors.pcr_repetition, 0);
+    }
+
+    #[test]
+    fn follows_the_pcr_across_the_wraparound() {
+        let packets = packets(PCR_WRAP_TS);
+        let pcrs: Vec<u64> = packets.iter().filter_map(|p| pcr(p)).collect();
+        assert!(pcrs.windows(2).any(|pair| pair[1] < pair[0]));
+
+        let report = analyze(&packets);
+        assert_eq!(report.errors, Errors::default());
+        let pcr = pid_report(&report, VIDEO_PID).pcr.clone().unwrap();
+        assert_eq!(pcr.count, 50);
+        assert_eq!(pcr.max_interval_ms, 30.0);
+    }
+
+    #[test]
+    fn counts_continuity_errors() {
+        let mut missing = packets(SPTS);
+        missing.remove(positions(&missing, AUDIO_PID)[10]);
+        let report = analyze(&missing);
+        assert_eq!(report.errors.continuity_count, 1);
+        assert_eq!(pid_report(&report, AUDIO_PID).continuity_errors, 1);
+        assert_eq!(pid_report(&report, VIDEO_PID).continuity_errors, 0);
+    }
+
+    #[test]
+    fn allows_one_duplicate_packet() {
+        let mut duplicated = packets(SPTS);
+        let i = positions(&duplicated, AUDIO_PID)[10];
+        duplicated.insert(i + 1, duplicated[i]);
+        assert_eq!(analyze(&duplicated).errors.continuity_count, 0);
+
+        duplicated.insert(i + 1, duplicated[i]);
+        assert_eq!(analyze(&duplicated).errors.continuity_count, 1);
+    }
+
+    #[test]
+    fn honours_the_discontinuity_indicator() {
+        let mut packets = packets(SPTS);
+        let audio = positions(&packets, AUDIO_PID);
+        packets[audio[11]][5] |= 0x80;
+        packets.remove(audio[10]);
+
+        assert_eq!(analyze(&packets).errors.continuity_count, 0);
+    }
+
+    #[test]
+    fn loses_sync_after_five_bad_sync_bytes() {
+        let mut corrupted = packets(SPTS);
+        for packet in &mut corrupted[100..104] {
+            packet[0] = 0x00;
+        }
+        let report = analyze(&corrupted);
+        assert_eq!(report.errors.sync_byte, 4);
+        assert_eq!(report.errors.sync_loss, 0);
+
+        corrupted[104][0] = 0x00;
+        let report = analyze(&corrupted);
+        assert_eq!(report.errors.sync_byte, 5);
+        assert_eq!(report.errors.sync_loss, 1);
+        // Regained on the packets that follow
+        assert!(report.synced);
+        assert_eq!(report.packets, 295);
+    }
+
+    #[test]
+    fn stays_out_of_sync_on_garbage() {
+        let mut analyzer = Analyzer::new();
+        let now = Instant::now();
+        analyzer.push(&SPTS[..PACKET_SIZE * 7], now);
+        // A stray sync byte in the garbage isn't a packet start
+        let mut garbage = vec![0u8; PACKET_SIZE * 7];
+        garbage[300] = SYNC_BYTE;
+        analyzer.push(&garbage, now);
+
+        let report = analyzer.report(now);
+        assert!(!report.synced);
+        assert_eq!(report.errors.sync_loss, 1);
+        assert_eq!(report.errors.sync_byte, 7);
+        assert_eq!(report.packets, 7);
+    }
+}
@@ -70,8 +70,80 @@ pub(crate) fn to_numbers_only(input: String) -> String {
     return input.chars().filter(|c| c.is_numeric()).collect();
 }
 
+// Percent-encode everything but unreserved characters, for URI query values
+pub(crate) fn url_encode(input: &str) -> String {
+    let mut encoded = String::new();
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// Shown instead of secrets in API responses and logs
+pub(crate) const REDACTED: &str = "********";
+
+// Mask the value of every passphrase= query parameter, for logging commands, URIs and ffmpeg output
+pub(crate) fn redact_secrets(text: &str) -> String {
+    const KEY: &str = "passphrase=";
+
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(KEY) {
+        let (head, tail) = rest.split_at(start + KEY.len());
+        redacted.push_str(head);
+        redacted.push_str(REDACTED);
+        let end = tail
+            .find(|c: char| c == '&' || c == '"' || c == '\'' || c == '|' || c.is_whitespace())
+            .unwrap_or(tail.len());
+        rest = &tail[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+// Far above any stream, keeps arithmetic on rates from overflowing
+const MAX_BITRATE: f64 = 1e12;
+
+// Bits per second of an ffmpeg style rate like "500k", "8M" or "2000000"
+pub(crate) fn parse_bitrate(rate: &str) -> Option<u64> {
+    let rate = rate.trim();
+    let (number, multiplier) = match rate.chars().last()? {
+        'k' | 'K' => (&rate[..rate.len() - 1], 1_000.0),
+        'm' | 'M' => (&rate[..rate.len() - 1], 1_000_000.0),
+        'g' | 'G' => (&rate[..rate.len() - 1], 1_000_000_000.0),
+        _ => (rate, 1.0),
+    };
+    let number: f64 = number.parse().ok()?;
+    // Also rules out "inf" and "NaN", which would saturate to u64::MAX
+    let rate = number * multiplier;
+    if !(0.0..=MAX_BITRATE).contains(&rate) {
+        return None;
+    }
+    Some(rate as u64)
+}
+
+// The test harness has arguments of its own, tests run with the defaults
+#[cfg(test)]
+fn parse_args() -> Args {
+    Args::parse_from(["gasket"])
+}
+
+#[cfg(not(test))]
+fn parse_args() -> Args {
+    Args::parse()
+}
+
 pub(crate) fn get_ffmpeg_path() -> String {
-    let args = Args::parse();
+    let args = parse_args();
     let ffmpeg_path = args.ffmpeg;
     return ffmpeg_path;
 }
+
+pub(crate) fn get_ffprobe_path() -> String {
+    parse_args().ffprobe
+}
@@ -0,0 +1,154 @@
+#!/usr/bin/env python3
+# Writes the MPEG-TS fixtures of src/ts.rs. Both are one second of a constant bitrate single
+# program stream laid out like ffmpeg's mpegts muxer output: PAT, PMT and SDT every 100 ms,
+# 50 fps H.264 on PID 0x100 carrying a PCR every 20 ms, AAC on PID 0x101, null packets as
+# padding. The payloads are placeholders, only the TS and PES layers are real.
+#
+#   spts.ts      PCR starting at 10 s
+#   pcr-wrap.ts  PCR starting 250 ms before it wraps around at 2^33 ticks of 90 kHz
+#
+# Usage: python3 make_ts.py (from this directory)
+
+PACKETS_PER_SLOT = 6
+# 20 ms slot / 6 packets, in 27 MHz ticks
+PACKET_TICKS = 90_000
+SLOTS = 50
+PCR_WRAP = (1 << 33) * 300
+
+PAT_PID = 0x0000
+SDT_PID = 0x0011
+PMT_PID = 0x1000
+VIDEO_PID = 0x0100
+AUDIO_PID = 0x0101
+NULL_PID = 0x1FFF
+
+
+def crc32(data):
+    crc = 0xFFFFFFFF
+    for byte in data:
+        crc ^= byte << 24
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x04C11DB7) if crc & 0x80000000 else crc << 1
+            crc &= 0xFFFFFFFF
+    return crc
+
+
+def section(table_id, extension, body):
+    length = 5 + len(body) + 4
+    data = bytes([table_id, 0xB0 | (length >> 8), length & 0xFF,
+                  extension >> 8, extension & 0xFF, 0xC1, 0x00, 0x00]) + body
+    return data + crc32(data).to_bytes(4, "big")
+
+
+def pat():
+    return section(0x00, 1, bytes([0x00, 0x01, 0xE0 | (PMT_PID >> 8), PMT_PID & 0xFF]))
+
+
+def pmt():
+    body = bytes([0xE0 | (VIDEO_PID >> 8), VIDEO_PID & 0xFF, 0xF0, 0x00])
+    for stream_type, pid in ((0x1B, VIDEO_PID), (0x0F, AUDIO_PID)):
+        body += bytes([stream_type, 0xE0 | (pid >> 8), pid & 0xFF, 0xF0, 0x00])
+    return section(0x02, 1, body)
+
+
+def sdt():
+    name = b"gasket"
+    provider = b"FFmpeg"
+    descriptor = bytes([0x48, 3 + len(provider) + len(name), 0x01, len(provider)]) + provider \
+        + bytes([len(name)]) + name
+    service = bytes([0x00, 0x01, 0xFC, 0x80 | (len(descriptor) >> 8), len(descriptor) & 0xFF])
+    body = bytes([0xFF, 0x01, 0xFF]) + service + descriptor
+    return section(0x42, 1, body)
+
+
+def header(pid, cc, start=False, adaptation=False):
+    control = 0x30 if adaptation else 0x10
+    return bytes([0x47, (0x40 if start else 0) | (pid >> 8), pid & 0xFF, control | cc])
+
+
+def adaptation_field(size, pcr=None):
+    # size counts the length byte too
+    if pcr is None:
+        field = bytes([0x00]) + b"\xFF" * (size - 2)
+    else:
+        base = (pcr // 300) % (1 << 33)
+        ext = pcr % 300
+        field = bytes([0x10, base >> 25, (base >> 17) & 0xFF, (base >> 9) & 0xFF, (base >> 1) & 0xFF,
+                       ((base & 1) << 7) | 0x7E | (ext >> 8), ext & 0xFF]) + b"\xFF" * (size - 8)
+    return bytes([size - 1]) + field
+
+
+def psi_packet(pid, cc, data):
+    payload = bytes([0x00]) + data
+    return header(pid, cc, start=True) + payload + b"\xFF" * (184 - len(payload))
+
+
+def pts_bytes(marker, pts):
+    pts %= 1 << 33
+    return bytes([marker | ((pts >> 29) & 0x0E) | 1, (pts >> 22) & 0xFF, ((pts >> 14) & 0xFE) | 1,
+                  (pts >> 7) & 0xFF, ((pts << 1) & 0xFE) | 1])
+
+
+def filler(length, seed):
+    # Anything but a sync byte, so corrupted packets can't be mistaken for a packet start
+    return bytes(0x10 + (seed + i) % 0x30 for i in range(length))
+
+
+def video_packets(cc, pcr, pts, frame):
+    first_af = adaptation_field(8, pcr)
+    pes_header = bytes([0x00, 0x00, 0x01, 0xE0, 0x00, 0x00, 0x80, 0x80, 0x05]) + pts_bytes(0x20, pts)
+    nal = bytes([0x00, 0x00, 0x00, 0x01, 0x09, 0xF0, 0x00, 0x00, 0x00, 0x01, 0x65 if frame % 50 == 0 else 0x41])
+    first_payload = pes_header + nal
+    first_payload += filler(184 - len(first_af) - len(first_payload), frame)
+    first = header(VIDEO_PID, cc, start=True, adaptation=True) + first_af + first_payload
+
+    second_af = adaptation_field(30)
+    second = header(VIDEO_PID, (cc + 1) % 16, adaptation=True) + second_af + filler(184 - 30, frame + 7)
+    return [first, second]
+
+
+def audio_packet(cc, pts, frame):
+    data = bytes([0xFF, 0xF1, 0x50, 0x80, 0x14, 0x1F, 0xFC]) + filler(160 - 14 - 7, frame)
+    pes = bytes([0x00, 0x00, 0x01, 0xC0, 0x00, len(data) + 8, 0x80, 0x80, 0x05]) + pts_bytes(0x20, pts) + data
+    return header(AUDIO_PID, cc, start=True, adaptation=True) + adaptation_field(184 - len(pes)) + pes
+
+
+def stream(start_pcr):
+    ccs = {PAT_PID: 0, SDT_PID: 0, PMT_PID: 0, VIDEO_PID: 0, AUDIO_PID: 0}
+    packets = []
+    audio_pts = start_pcr // 300 + 2 * 90_000 // 10
+    audio_frame = 0
+    for slot in range(SLOTS):
+        slot_packets = []
+        if slot % 5 == 0:
+            for pid, data in ((PAT_PID, pat()), (PMT_PID, pmt()), (SDT_PID, sdt())):
+                slot_packets.append(psi_packet(pid, ccs[pid], data))
+                ccs[pid] = (ccs[pid] + 1) % 16
+
+        # The PCR is the time the first packet of the frame leaves the muxer
+        pcr = (start_pcr + (len(packets) + len(slot_packets)) * PACKET_TICKS) % PCR_WRAP
+        video_pts = start_pcr // 300 + 2 * 90_000 // 10 + slot * 1800
+        slot_packets += video_packets(ccs[VIDEO_PID], pcr, video_pts, slot)
+        ccs[VIDEO_PID] = (ccs[VIDEO_PID] + 2) % 16
+
+        # 1024 samples at 48 kHz
+        slot_end = start_pcr // 300 + 2 * 90_000 // 10 + (slot + 1) * 1800
+        if audio_pts < slot_end:
+            slot_packets.append(audio_packet(ccs[AUDIO_PID], audio_pts, audio_frame))
+            ccs[AUDIO_PID] = (ccs[AUDIO_PID] + 1) % 16
+            audio_pts += 1920
+            audio_frame += 1
+
+        while len(slot_packets) < PACKETS_PER_SLOT:
+            slot_packets.append(header(NULL_PID, 0) + b"\xFF" * 184)
+        assert len(slot_packets) == PACKETS_PER_SLOT
+        packets += slot_packets
+
+    assert all(len(packet) == 188 for packet in packets)
+    return b"".join(packets)
+
+
+with open("spts.ts", "wb") as f:
+    f.write(stream(10 * 27_000_000))
+with open("pcr-wrap.ts", "wb") as f:
+    f.write(stream(PCR_WRAP - 27_000_000 // 4))